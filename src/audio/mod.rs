@@ -1,11 +1,14 @@
-use std::ops::{Deref, DerefMut};
+use std::collections::HashMap;
+use std::ops::{Deref, DerefMut, Range};
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use rodio::source::Source;
-use rodio::{OutputStream, OutputStreamHandle, Sample, Sink};
+use rodio::{OutputStream, OutputStreamHandle, PlayError, Sample, Sink, StreamError};
 
 pub mod sources;
+pub mod stereo;
 
 /// A source who's frequency can be adjusted.
 pub trait AdjustableSource: Source
@@ -13,19 +16,247 @@ where
     Self::Item: Sample,
 {
     fn set_frequency(&mut self, frequency: f32);
+
+    /// The current frequency of the source, or None if it doesn't have one (e.g. noise). Returns None by default.
+    fn frequency(&self) -> Option<f32> {
+        None
+    }
+
+    /// Restart the source from scratch, e.g. by resetting its phase. Does nothing by default.
+    fn retrigger(&mut self) {}
+
+    /// Change the frequency without a glitch in the waveform. Most sources keep their phase across frequency changes,
+    /// so this is the same as `set_frequency` by default; sources with sharp corners wait for the next one instead.
+    fn set_frequency_smooth(&mut self, frequency: f32) {
+        self.set_frequency(frequency);
+    }
+}
+
+/// Conveniences for using adjustable sources on their own, e.g. with a plain Sink. Implemented for every adjustable source.
+pub trait AdjustableSourceExt: AdjustableSource<Item = f32> + Sized {
+    /// Sets the frequency and returns the source, e.g. `sink.append(SineWave::new(0.0).with_frequency(440.0))`.
+    fn with_frequency(mut self, frequency: f32) -> Self {
+        self.set_frequency(frequency);
+        self
+    }
+
+    /// Boxes the source, e.g. to store different kinds of sources together or to give it to `ChannelsBuilder::add_boxed`.
+    fn boxed(self) -> Box<dyn AdjustableSource<Item = f32> + Send>
+    where
+        Self: Send + 'static,
+    {
+        Box::new(self)
+    }
+}
+
+impl<S> AdjustableSourceExt for S where S: AdjustableSource<Item = f32> + Sized {}
+
+impl Source for Box<dyn AdjustableSource<Item = f32> + Send> {
+    fn current_frame_len(&self) -> Option<usize> {
+        (**self).current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        (**self).channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        (**self).sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        (**self).total_duration()
+    }
+}
+
+impl AdjustableSource for Box<dyn AdjustableSource<Item = f32> + Send> {
+    fn set_frequency(&mut self, frequency: f32) {
+        (**self).set_frequency(frequency);
+    }
+
+    fn frequency(&self) -> Option<f32> {
+        (**self).frequency()
+    }
+
+    fn retrigger(&mut self) {
+        (**self).retrigger();
+    }
+
+    fn set_frequency_smooth(&mut self, frequency: f32) {
+        (**self).set_frequency_smooth(frequency);
+    }
 }
 
 const SAMPLE_RATE: u32 = 41000;
 
+/// How long (in seconds) the output level takes to fall back by a factor of e after a peak.
+const METER_RELEASE: f32 = 0.3;
+
+const LOWEST_AUDIBLE_FREQUENCY: f32 = 20.0;
+const HIGHEST_AUDIBLE_FREQUENCY: f32 = 20000.0;
+
+/// Converts a MIDI note number to its frequency, with A4 (note 69) at 440Hz.
+pub fn midi_to_frequency(midi: u8) -> f32 {
+    440.0 * 2.0_f32.powf((midi as f32 - 69.0) / 12.0)
+}
+
+/// Maps a frequency to a color in HSV, with the hue in degrees and the saturation and value between 0 and 1.
+/// The audible range (20Hz to 20kHz) is spread logarithmically across the hue wheel, so every octave gets the same share of it.
+/// Frequencies outside of that range are clamped.
+pub fn freq_to_hue(hz: f32) -> (f32, f32, f32) {
+    let hz = hz.clamp(LOWEST_AUDIBLE_FREQUENCY, HIGHEST_AUDIBLE_FREQUENCY);
+    let position = (hz / LOWEST_AUDIBLE_FREQUENCY).ln()
+        / (HIGHEST_AUDIBLE_FREQUENCY / LOWEST_AUDIBLE_FREQUENCY).ln();
+    (position * 360.0, 1.0, 1.0)
+}
+
+/// Renders the given length of Channels into a buffer, and returns a source which loops it.
+/// This is much cheaper than synthesizing a phrase live, e.g. for background music. Set the channels' volumes before rendering,
+/// and use `LoopSource::with_crossfade` if the end of the phrase doesn't line up with its start.
+pub fn render_loop(channels: Channels, length: Duration) -> sources::LoopSource {
+    let length = (length.as_secs_f64() * SAMPLE_RATE as f64).round() as usize;
+    sources::LoopSource::new(channels.take(length).collect())
+}
+
+/// An f32 which can be shared with the audio thread without locking, stored as its bits.
+struct AtomicF32(AtomicU32);
+
+impl AtomicF32 {
+    fn new(value: f32) -> Self {
+        AtomicF32(AtomicU32::new(value.to_bits()))
+    }
+
+    fn load(&self) -> f32 {
+        f32::from_bits(self.0.load(Ordering::Relaxed))
+    }
+
+    fn store(&self, value: f32) {
+        self.0.store(value.to_bits(), Ordering::Relaxed);
+    }
+
+    /// Stores the new value only if the current one is still `current`.
+    fn compare_exchange(&self, current: f32, new: f32) {
+        let _ = self.0.compare_exchange(
+            current.to_bits(),
+            new.to_bits(),
+            Ordering::Relaxed,
+            Ordering::Relaxed,
+        );
+    }
+}
+
+/// The volume of a single channel, which can move towards a target over time.
+/// It is lock-free, so that the hook never makes the audio thread wait.
+struct ChannelVolume {
+    volume: AtomicF32,
+    target: AtomicF32,
+    step: AtomicF32,
+    before_fade: AtomicF32,
+}
+
+impl ChannelVolume {
+    fn new(volume: f32) -> Self {
+        ChannelVolume {
+            volume: AtomicF32::new(volume),
+            target: AtomicF32::new(volume),
+            step: AtomicF32::new(0.0),
+            before_fade: AtomicF32::new(0.0),
+        }
+    }
+
+    fn set(&self, volume: f32) {
+        self.step.store(0.0);
+        self.target.store(volume);
+        self.volume.store(volume);
+    }
+
+    fn fade_to(&self, target: f32, duration: Duration) {
+        let samples = (duration.as_secs_f32() * SAMPLE_RATE as f32).max(1.0);
+        self.step
+            .store((target - self.volume.load()).abs() / samples);
+        self.target.store(target);
+    }
+
+    /// Moves the volume one sample closer to its target, and returns the volume before moving.
+    fn advance(&self) -> f32 {
+        let volume = self.volume.load();
+        let target = self.target.load();
+        let step = self.step.load();
+        let next = if volume < target {
+            (volume + step).min(target)
+        } else if volume > target {
+            (volume - step).max(target)
+        } else {
+            volume
+        };
+        if next != volume {
+            // If the hook set the volume in the meantime, its value wins.
+            self.volume.compare_exchange(volume, next);
+        }
+        volume
+    }
+}
+
+type PostFx = Box<dyn FnMut(f32) -> f32 + Send>;
+
+/// A one-pole high-pass filter, removing DC offset and rumble from a single channel.
+struct HighPass {
+    coefficient: f32,
+    previous_input: f32,
+    previous_output: f32,
+}
+
+impl HighPass {
+    fn new(cutoff: f32) -> Self {
+        let rc = 1.0 / (2.0 * std::f32::consts::PI * cutoff);
+        let dt = 1.0 / SAMPLE_RATE as f32;
+        HighPass {
+            coefficient: rc / (rc + dt),
+            previous_input: 0.0,
+            previous_output: 0.0,
+        }
+    }
+
+    fn filter(&mut self, input: f32) -> f32 {
+        let output = self.coefficient * (self.previous_output + input - self.previous_input);
+        self.previous_input = input;
+        self.previous_output = output;
+        output
+    }
+}
+
+/// Ducks a channel according to the envelope of another one, the trigger.
+struct Sidechain {
+    trigger: usize,
+    amount: f32,
+    decay: f32,
+    envelope: f32,
+}
+
 /// A Source which contains other adjustable sources and plays all of them at once (with adjustable volumes and frequencies).
 pub struct Channels {
     sources: Vec<Arc<Mutex<dyn AdjustableSource<Item = f32> + Send>>>,
-    volume: Vec<Arc<Mutex<f32>>>,
+    volume: Vec<Arc<ChannelVolume>>,
+    highpass: Vec<HighPass>,
+    post_fx: Option<PostFx>,
+    declick_step: f32,
+    applied_volume: Vec<f32>,
+    samples: Vec<f32>,
+    sidechains: Arc<Mutex<Vec<Option<Sidechain>>>>,
+    level: Arc<AtomicF32>,
+    clip_count: Arc<AtomicU64>,
+    position: Arc<AtomicU64>,
 }
 
 /// A builder for Channels
 pub struct ChannelsBuilder {
     sources: Vec<Arc<Mutex<dyn AdjustableSource<Item = f32> + Send>>>,
+    volumes: Vec<f32>,
+    names: HashMap<String, usize>,
+    chords: Vec<Range<usize>>,
+    highpass: Option<f32>,
+    post_fx: Option<PostFx>,
+    declick_ms: f32,
 }
 
 impl ChannelsBuilder {
@@ -33,15 +264,53 @@ impl ChannelsBuilder {
     pub fn new() -> Self {
         Self {
             sources: Vec::new(),
+            volumes: Vec::new(),
+            names: HashMap::new(),
+            chords: Vec::new(),
+            highpass: None,
+            post_fx: None,
+            declick_ms: 0.0,
         }
     }
 
-    /// Add a source.
-    pub fn add_source<T>(mut self, source: T) -> Self
+    /// Add a source. Its volume starts at 0, until it is set through the hook.
+    pub fn add_source<T>(self, source: T) -> Self
+    where
+        T: AdjustableSource<Item = f32> + Send + 'static,
+    {
+        self.add_source_with_volume(source, 0.0)
+    }
+
+    /// Add a source, with the volume it starts at.
+    pub fn add_source_with_volume<T>(mut self, source: T, volume: f32) -> Self
     where
         T: AdjustableSource<Item = f32> + Send + 'static,
     {
         self.sources.push(Arc::new(Mutex::new(source)) as _);
+        self.volumes.push(volume);
+        self
+    }
+
+    /// Add a source with a name, so that it can be found through the hook with `index_of` instead of keeping track of its index.
+    /// Its volume starts at 0. Panics if another source already has that name.
+    pub fn add_named_source<T>(mut self, name: &str, source: T) -> Self
+    where
+        T: AdjustableSource<Item = f32> + Send + 'static,
+    {
+        if self
+            .names
+            .insert(name.to_string(), self.sources.len())
+            .is_some()
+        {
+            panic!("There already is a source named {:?}!", name);
+        }
+        self.add_source(source)
+    }
+
+    /// Add a source that is already boxed, such as the ones created by `sources::make`.
+    pub fn add_boxed(mut self, source: Box<dyn AdjustableSource<Item = f32> + Send>) -> Self {
+        self.sources.push(Arc::new(Mutex::new(source)) as _);
+        self.volumes.push(0.0);
         self
     }
 
@@ -52,22 +321,66 @@ impl ChannelsBuilder {
         T: AdjustableSource<Item = f32> + Send + 'static,
     {
         self.sources.push(source.clone());
+        self.volumes.push(0.0);
+        self
+    }
+
+    /// Add a chord, as one source per frequency, created by the factory. The chord can then be controlled as a whole through the hook,
+    /// with chords numbered in the order they were added (e.g. `hook.chord_channels(0)` for the first chord).
+    pub fn add_chord<F, T>(mut self, factory: F, frequencies: &[f32]) -> Self
+    where
+        F: Fn(f32) -> T,
+        T: AdjustableSource<Item = f32> + Send + 'static,
+    {
+        let start = self.sources.len();
+        for frequency in frequencies {
+            self = self.add_source(factory(*frequency));
+        }
+        self.chords.push(start..self.sources.len());
+        self
+    }
+
+    /// Filters every channel with a high-pass at the given cutoff (in Hz) before mixing, removing DC offset and low rumble.
+    pub fn with_per_channel_highpass(mut self, cutoff_hz: f32) -> Self {
+        self.highpass = Some(cutoff_hz);
+        self
+    }
+
+    /// Applies the given closure to every sample of the mix, for custom effects.
+    /// The closure runs on the audio thread for every single sample, so it must be fast and must never block (e.g. on a lock held elsewhere).
+    pub fn with_post_fx<F>(mut self, f: F) -> Self
+    where
+        F: FnMut(f32) -> f32 + Send + 'static,
+    {
+        self.post_fx = Some(Box::new(f));
+        self
+    }
+
+    /// Smooths out sudden volume changes (e.g. a channel going from silent to audible) over the given amount of milliseconds, to avoid clicks.
+    /// A volume can't move by more than its full range within that time. 2 to 5ms is usually enough. Off (0) by default.
+    pub fn with_declick_ms(mut self, declick_ms: f32) -> Self {
+        self.declick_ms = declick_ms.max(0.0);
         self
     }
 
     /// Creates the Channels. Consumes this builder and returns a Channels, as well as a ChannelHook that allows you to control the channels.
     /// Panics if any of the sources have a limited duration or frame length, or if there are more than one channel.
     pub fn build(self) -> (Channels, ChannelHook) {
-        Channels::new(self.sources)
+        Channels::new(self)
     }
 }
 
 impl Channels {
-    fn new(
-        sources: Vec<Arc<Mutex<dyn AdjustableSource<Item = f32> + Send>>>,
-    ) -> (Self, ChannelHook) {
-        let volumes: Vec<Arc<Mutex<f32>>> = (0..sources.len())
-            .map(|_| Arc::new(Mutex::new(0.)))
+    fn new(builder: ChannelsBuilder) -> (Self, ChannelHook) {
+        let sources = builder.sources;
+        let level = Arc::new(AtomicF32::new(0.0));
+        let clip_count = Arc::new(AtomicU64::new(0));
+        let position = Arc::new(AtomicU64::new(0));
+        let sidechains = Arc::new(Mutex::new((0..sources.len()).map(|_| None).collect()));
+        let volumes: Vec<Arc<ChannelVolume>> = builder
+            .volumes
+            .iter()
+            .map(|volume| Arc::new(ChannelVolume::new(*volume)))
             .collect();
         for i in &sources {
             let j = i.lock().unwrap();
@@ -82,10 +395,32 @@ impl Channels {
             Channels {
                 sources: sources.clone(),
                 volume: volumes.clone(),
+                highpass: match builder.highpass {
+                    Some(cutoff) => sources.iter().map(|_| HighPass::new(cutoff)).collect(),
+                    None => Vec::new(),
+                },
+                post_fx: builder.post_fx,
+                declick_step: if builder.declick_ms > 0.0 {
+                    1.0 / (builder.declick_ms / 1000.0 * SAMPLE_RATE as f32).max(1.0)
+                } else {
+                    f32::INFINITY
+                },
+                applied_volume: vec![0.0; sources.len()],
+                samples: vec![0.0; sources.len()],
+                sidechains: sidechains.clone(),
+                level: level.clone(),
+                clip_count: clip_count.clone(),
+                position: position.clone(),
             },
             ChannelHook {
                 sources,
                 volume: volumes,
+                names: builder.names,
+                chords: builder.chords,
+                sidechains,
+                level,
+                clip_count,
+                position,
             },
         )
     }
@@ -95,12 +430,36 @@ impl Iterator for Channels {
     type Item = f32;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let mut result = 0.0;
         for (i, source) in self.sources.iter_mut().enumerate() {
-            result +=
-                source.lock().unwrap().next().unwrap_or(0.0) * *self.volume[i].lock().unwrap();
+            let mut sample = source.lock().unwrap().next().unwrap_or(0.0);
+            if let Some(highpass) = self.highpass.get_mut(i) {
+                sample = highpass.filter(sample);
+            }
+            let volume = self.volume[i].advance();
+            let applied = &mut self.applied_volume[i];
+            *applied += (volume - *applied).clamp(-self.declick_step, self.declick_step);
+            self.samples[i] = sample * *applied;
+        }
+        for (target, sidechain) in self.sidechains.lock().unwrap().iter_mut().enumerate() {
+            if let Some(sidechain) = sidechain {
+                sidechain.envelope = self.samples[sidechain.trigger]
+                    .abs()
+                    .max(sidechain.envelope * sidechain.decay);
+                self.samples[target] *= 1.0 - sidechain.amount * sidechain.envelope.min(1.0);
+            }
         }
-        Some(result / self.sources.len() as f32)
+        let mut result = self.samples.iter().sum::<f32>() / self.sources.len() as f32;
+        if result.abs() > 1.0 {
+            self.clip_count.fetch_add(1, Ordering::Relaxed);
+        }
+        if let Some(post_fx) = &mut self.post_fx {
+            result = post_fx(result);
+        }
+        let decay = (-1.0 / (METER_RELEASE * SAMPLE_RATE as f32)).exp();
+        self.level
+            .store(result.abs().max(self.level.load() * decay));
+        self.position.fetch_add(1, Ordering::Relaxed);
+        Some(result)
     }
 }
 
@@ -122,10 +481,30 @@ impl Source for Channels {
     }
 }
 
+/// Lets a Channels be added to another ChannelsBuilder as a single channel, e.g. to give a group of sources its own volume in the final mix.
+/// The channels inside are controlled with their own hook.
+impl AdjustableSource for Channels {
+    /// Does nothing, since the sources inside usually play different notes. Use the Channels' own hook to change their frequencies.
+    fn set_frequency(&mut self, _frequency: f32) {}
+
+    /// Restarts every source inside.
+    fn retrigger(&mut self) {
+        for source in &self.sources {
+            source.lock().unwrap().retrigger();
+        }
+    }
+}
+
 /// A hook which allows adjusting the volumes and frequencies of the channels after creation.
 pub struct ChannelHook {
-    volume: Vec<Arc<Mutex<f32>>>,
+    volume: Vec<Arc<ChannelVolume>>,
     sources: Vec<Arc<Mutex<dyn AdjustableSource<Item = f32> + Send>>>,
+    names: HashMap<String, usize>,
+    chords: Vec<Range<usize>>,
+    sidechains: Arc<Mutex<Vec<Option<Sidechain>>>>,
+    level: Arc<AtomicF32>,
+    clip_count: Arc<AtomicU64>,
+    position: Arc<AtomicU64>,
 }
 
 impl ChannelHook {
@@ -134,32 +513,326 @@ impl ChannelHook {
         self.sources[index].lock().unwrap().set_frequency(frequency);
     }
 
+    /// Get the frequency of the channel with the given index, or None if its source doesn't have one.
+    pub fn frequency(&self, index: usize) -> Option<f32> {
+        self.sources[index].lock().unwrap().frequency()
+    }
+
+    /// Bend the frequency of the channel with the given index by the given amount of semitones (which can be fractional, e.g. 0.01 for a cent).
+    /// Does nothing if the source doesn't have a frequency.
+    pub fn bend_semitones(&mut self, index: usize, semitones: f32) {
+        let mut source = self.sources[index].lock().unwrap();
+        if let Some(frequency) = source.frequency() {
+            source.set_frequency(frequency * 2.0_f32.powf(semitones / 12.0));
+        }
+    }
+
+    /// Set the frequency of the channel with the given index to the given MIDI note.
+    pub fn set_note(&mut self, index: usize, midi: u8) {
+        self.set_frequency(index, midi_to_frequency(midi));
+    }
+
+    /// Restart the source of the channel with the given index from scratch, even if its frequency didn't change.
+    pub fn retrigger(&mut self, index: usize) {
+        self.sources[index].lock().unwrap().retrigger();
+    }
+
     /// Set the volume of the channel with the given index.
     pub fn set_volume(&mut self, index: usize, volume: f32) {
-        *self.volume[index].lock().unwrap() = volume;
+        self.volume[index].set(volume);
+    }
+
+    /// Get the current volume of the channel with the given index.
+    pub fn volume(&self, index: usize) -> f32 {
+        self.volume[index].volume.load()
+    }
+
+    /// Set the volume of the channel with the given index in decibels, where 0 is full volume and every -6 dB roughly halves it.
+    /// This matches how loudness is perceived better than `set_volume`, e.g. for a mixer fader.
+    pub fn set_volume_db(&mut self, index: usize, db: f32) {
+        self.set_volume(index, 10f32.powf(db / 20.0));
+    }
+
+    /// Get the current volume of the channel with the given index in decibels. Silence is negative infinity.
+    pub fn volume_db(&self, index: usize) -> f32 {
+        20.0 * self.volume(index).log10()
+    }
+
+    /// The index of the channel added with the given name (see `ChannelsBuilder::add_named_source`), if there is one.
+    pub fn index_of(&self, name: &str) -> Option<usize> {
+        self.names.get(name).copied()
+    }
+
+    /// Set the volume of the channel with the given name. Panics if there is no channel with that name.
+    pub fn set_volume_by_name(&mut self, name: &str, volume: f32) {
+        self.set_volume(self.named_index(name), volume);
+    }
+
+    /// Set the frequency of the channel with the given name. Panics if there is no channel with that name.
+    pub fn set_frequency_by_name(&mut self, name: &str, frequency: f32) {
+        self.set_frequency(self.named_index(name), frequency);
+    }
+
+    fn named_index(&self, name: &str) -> usize {
+        match self.index_of(name) {
+            Some(index) => index,
+            None => panic!("There is no channel named {:?}!", name),
+        }
+    }
+
+    /// Smoothly fade the channel with the given index down to silence over the given duration, instead of clicking.
+    /// The volume before the fade is remembered, so that `fade_in` can restore it.
+    pub fn fade_out(&mut self, index: usize, duration: Duration) {
+        let volume = &self.volume[index];
+        let target = volume.target.load();
+        if target != 0.0 {
+            volume.before_fade.store(target);
+        }
+        volume.fade_to(0.0, duration);
+    }
+
+    /// Smoothly fade the channel with the given index up to the target volume over the given duration.
+    /// If no target is given, the volume from before the last `fade_out` is restored.
+    pub fn fade_in(&mut self, index: usize, target: Option<f32>, duration: Duration) {
+        let volume = &self.volume[index];
+        let target = target.unwrap_or_else(|| volume.before_fade.load());
+        volume.fade_to(target, duration);
+    }
+
+    /// The current level of the mixed output, between 0 and 1 for sources which stay between -1 and 1, e.g. for a VU meter.
+    /// Follows peaks instantly and falls back smoothly.
+    pub fn output_level(&self) -> f32 {
+        self.level.load()
+    }
+
+    /// The amount of samples of the mix which went past -1 or 1, e.g. to warn about it. The mix is checked before the post effects,
+    /// so that a limiter set with `with_post_fx` doesn't hide it. It is the average of the channels, so this only happens with volumes above 1.
+    pub fn clip_count(&self) -> u64 {
+        self.clip_count.load(Ordering::Relaxed)
+    }
+
+    /// Resets the clip count back to 0.
+    pub fn reset_clip_count(&mut self) {
+        self.clip_count.store(0, Ordering::Relaxed);
+    }
+
+    /// Ducks the `target` channel whenever the `trigger` channel is loud, e.g. to lower the music under a sound effect, or for a pumping effect.
+    /// The target's volume is reduced by up to `amount` (between 0 and 1) following the trigger's output, which falls back over `release`
+    /// (by a factor of e) once the trigger gets quiet. Replaces any previous sidechain on the target. Panics if the target or the trigger doesn't exist.
+    pub fn set_sidechain(&mut self, target: usize, trigger: usize, amount: f32, release: Duration) {
+        self.check_sidechain_target(target);
+        if trigger >= self.sources.len() {
+            panic!("There is no channel {} to trigger the sidechain!", trigger);
+        }
+        let samples = (release.as_secs_f32() * SAMPLE_RATE as f32).max(1.0);
+        self.sidechains.lock().unwrap()[target] = Some(Sidechain {
+            trigger,
+            amount: amount.clamp(0.0, 1.0),
+            decay: (-1.0 / samples).exp(),
+            envelope: 0.0,
+        });
+    }
+
+    /// Removes the sidechain on the `target` channel, if any. Panics if the target doesn't exist.
+    pub fn clear_sidechain(&mut self, target: usize) {
+        self.check_sidechain_target(target);
+        self.sidechains.lock().unwrap()[target] = None;
+    }
+
+    /// Panics if the target doesn't exist, before the sidechains are locked, so that the audio thread can still lock them.
+    fn check_sidechain_target(&self, target: usize) {
+        if target >= self.sources.len() {
+            panic!("There is no channel {} to apply the sidechain to!", target);
+        }
+    }
+
+    /// The amount of samples played so far (since the last `reset_position`), e.g. to sync visuals to the music.
+    pub fn position_samples(&self) -> u64 {
+        self.position.load(Ordering::Relaxed)
     }
+
+    /// The time played so far (since the last `reset_position`), in seconds.
+    pub fn position_seconds(&self) -> f64 {
+        self.position_samples() as f64 / SAMPLE_RATE as f64
+    }
+
+    /// Resets the position back to 0, e.g. when restarting a song.
+    pub fn reset_position(&mut self) {
+        self.position.store(0, Ordering::Relaxed);
+    }
+
+    /// The indices of the channels used by the chord with the given index, in the order of its frequencies.
+    pub fn chord_channels(&self, chord: usize) -> Range<usize> {
+        self.chords[chord].clone()
+    }
+
+    /// Set the volume of every channel of the chord with the given index.
+    pub fn set_chord_volume(&mut self, chord: usize, volume: f32) {
+        for index in self.chord_channels(chord) {
+            self.set_volume(index, volume);
+        }
+    }
+
+    /// Set the frequencies of the channels of the chord with the given index, e.g. to change chords. Extra frequencies are ignored.
+    pub fn set_chord_frequencies(&mut self, chord: usize, frequencies: &[f32]) {
+        for (index, frequency) in self.chord_channels(chord).zip(frequencies) {
+            self.set_frequency(index, *frequency);
+        }
+    }
+}
+
+/// Plays a mono Channels on both sides of a stereo output, attenuating one side according to the balance.
+struct Balanced {
+    channels: Channels,
+    balance: Arc<AtomicF32>,
+    pending_right: Option<f32>,
+}
+
+impl Balanced {
+    fn new(channels: Channels, balance: Arc<AtomicF32>) -> Self {
+        Balanced {
+            channels,
+            balance,
+            pending_right: None,
+        }
+    }
+}
+
+impl Source for Balanced {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        2
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.channels.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+impl Iterator for Balanced {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if let Some(right) = self.pending_right.take() {
+            return Some(right);
+        }
+        let sample = self.channels.next()?;
+        let balance = self.balance.load();
+        self.pending_right = Some(sample * (1.0 + balance).min(1.0));
+        Some(sample * (1.0 - balance).min(1.0))
+    }
+}
+
+/// Whether the default audio output can be opened, e.g. to run silently instead of failing on a machine without an audio device.
+/// The output is opened to check, and closed again right away.
+pub fn default_device_available() -> bool {
+    OutputStream::try_default().is_ok()
 }
 
 /// A playback which controls the playing of a Channels. Derefs down to a Sink.
 /// DO NOT DROP THIS OR THE CHANNEL WILL STOP PLAYING.
 pub struct ChannelPlayback {
     sink: Sink,
+    balance: Arc<AtomicF32>,
+    paused: bool,
+    sample_rate: u32,
+    channel_count: usize,
     _stream: OutputStream,
-    _handle: OutputStreamHandle,
+    handle: OutputStreamHandle,
 }
 
 impl ChannelPlayback {
     /// Create a new ChannelPlayback with the given Channels, and starts playing it.
-    pub fn new(channels: Channels) -> Self {
-        let (stream, handle) = OutputStream::try_default().unwrap();
-        let sink = Sink::try_new(&handle).ok().unwrap();
-        sink.append(channels);
+    /// Returns an error if the audio output can't be opened, e.g. if there is no audio device (see `default_device_available`).
+    pub fn new(channels: Channels) -> Result<Self, AudioError> {
+        let (stream, handle) = OutputStream::try_default()?;
+        let sink = Sink::try_new(&handle)?;
+        let sample_rate = channels.sample_rate();
+        let channel_count = channels.sources.len();
+        let balance = Arc::new(AtomicF32::new(0.0));
+        sink.append(Balanced::new(channels, balance.clone()));
         sink.play();
-        ChannelPlayback {
+        Ok(ChannelPlayback {
             sink,
+            balance,
+            paused: false,
+            sample_rate,
+            channel_count,
             _stream: stream,
-            _handle: handle,
+            handle,
+        })
+    }
+
+    /// Replaces the playing Channels with new ones, e.g. to change songs, keeping the volume, speed and paused state.
+    /// The new Channels are started on a fresh sink before the old one is dropped, to keep the gap as short as possible.
+    /// The old Channels stop playing, so their hook has no audible effect anymore.
+    /// If the new sink can't be created, the old Channels keep playing and an error is returned.
+    pub fn swap_channels(&mut self, new_channels: Channels) -> Result<(), AudioError> {
+        let sink = Sink::try_new(&self.handle)?;
+        sink.set_volume(self.sink.volume());
+        sink.set_speed(self.sink.speed());
+        if self.paused {
+            sink.pause();
         }
+        self.sample_rate = new_channels.sample_rate();
+        self.channel_count = new_channels.sources.len();
+        sink.append(Balanced::new(new_channels, self.balance.clone()));
+        self.sink = sink;
+        Ok(())
+    }
+
+    /// Places the mix between the left (-1) and right (1) speakers, by attenuating the other side. 0, the default, plays it equally on both.
+    /// The balance is clamped between -1 and 1, and kept when swapping channels.
+    pub fn set_balance(&mut self, balance: f32) {
+        self.balance.store(balance.clamp(-1.0, 1.0));
+    }
+
+    /// The current balance, as set by `set_balance`.
+    pub fn balance(&self) -> f32 {
+        self.balance.load()
+    }
+
+    /// The sample rate the Channels is playing at.
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// The amount of channels in the Channels being played.
+    pub fn channel_count(&self) -> usize {
+        self.channel_count
+    }
+
+    /// Pauses the playback. Prefer this over the Sink's `pause`, as it keeps track of the paused state.
+    pub fn pause_playback(&mut self) {
+        self.sink.pause();
+        self.paused = true;
+    }
+
+    /// Resumes the playback. Prefer this over the Sink's `play`, as it keeps track of the paused state.
+    pub fn resume_playback(&mut self) {
+        self.sink.play();
+        self.paused = false;
+    }
+
+    /// Pauses the playback if it is playing, and resumes it if it is paused.
+    pub fn toggle_pause(&mut self) {
+        if self.paused {
+            self.resume_playback();
+        } else {
+            self.pause_playback();
+        }
+    }
+
+    /// Returns whether the playback is paused, as tracked by `pause_playback`, `resume_playback` and `toggle_pause`.
+    pub fn is_paused(&self) -> bool {
+        self.paused
     }
 }
 
@@ -177,6 +850,147 @@ impl DerefMut for ChannelPlayback {
     }
 }
 
+/// An error while opening the audio output.
+#[derive(Debug)]
+pub enum AudioError {
+    /// No output stream could be opened, e.g. because there is no audio device.
+    Stream(StreamError),
+    /// The output stream couldn't be played on.
+    Play(PlayError),
+}
+
+impl std::fmt::Display for AudioError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AudioError::Stream(error) => write!(f, "Couldn't open the audio output: {}", error),
+            AudioError::Play(error) => write!(f, "Couldn't play on the audio output: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for AudioError {}
+
+impl From<StreamError> for AudioError {
+    fn from(error: StreamError) -> Self {
+        AudioError::Stream(error)
+    }
+}
+
+impl From<PlayError> for AudioError {
+    fn from(error: PlayError) -> Self {
+        AudioError::Play(error)
+    }
+}
+
+/// Plays Channels and controls them, all in one object: the audio plays for as long as the engine is alive.
+/// Derefs to the ChannelHook, so the channels can be controlled directly (e.g. `engine.set_volume(0, 0.5)`).
+pub struct AudioEngine {
+    hook: ChannelHook,
+    playback: ChannelPlayback,
+}
+
+impl AudioEngine {
+    /// Builds the Channels and starts playing them. Returns an error if the audio output can't be opened.
+    pub fn start(builder: ChannelsBuilder) -> Result<AudioEngine, AudioError> {
+        let (channels, hook) = builder.build();
+        Ok(AudioEngine {
+            hook,
+            playback: ChannelPlayback::new(channels)?,
+        })
+    }
+
+    /// The playback of the channels, e.g. to pause it.
+    pub fn playback(&self) -> &ChannelPlayback {
+        &self.playback
+    }
+
+    /// The playback of the channels, e.g. to pause it.
+    pub fn playback_mut(&mut self) -> &mut ChannelPlayback {
+        &mut self.playback
+    }
+}
+
+impl Deref for AudioEngine {
+    type Target = ChannelHook;
+
+    fn deref(&self) -> &Self::Target {
+        &self.hook
+    }
+}
+
+impl DerefMut for AudioEngine {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.hook
+    }
+}
+
+/// A change made to a channel through a `RecordingHook`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ChannelEvent {
+    Frequency(f32),
+    Volume(f32),
+}
+
+/// A change made to a channel, along with when it was made, relative to the start of the recording.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TimedEvent {
+    pub time: Duration,
+    pub channel: usize,
+    pub event: ChannelEvent,
+}
+
+/// A wrapper around a `ChannelHook` which records every frequency and volume change made through it, e.g. to replay a performance later.
+/// It derefs to the hook for reading the channels; changes made directly on the hook aren't recorded.
+pub struct RecordingHook {
+    hook: ChannelHook,
+    start: Instant,
+    events: Vec<TimedEvent>,
+}
+
+impl RecordingHook {
+    /// Starts recording the changes made to the channels of the given hook.
+    pub fn new(hook: ChannelHook) -> RecordingHook {
+        RecordingHook {
+            hook,
+            start: Instant::now(),
+            events: Vec::new(),
+        }
+    }
+
+    fn record(&mut self, channel: usize, event: ChannelEvent) {
+        self.events.push(TimedEvent {
+            time: self.start.elapsed(),
+            channel,
+            event,
+        });
+    }
+
+    /// Set the frequency of the channel with the given index, and record it.
+    pub fn set_frequency(&mut self, index: usize, frequency: f32) {
+        self.hook.set_frequency(index, frequency);
+        self.record(index, ChannelEvent::Frequency(frequency));
+    }
+
+    /// Set the volume of the channel with the given index, and record it.
+    pub fn set_volume(&mut self, index: usize, volume: f32) {
+        self.hook.set_volume(index, volume);
+        self.record(index, ChannelEvent::Volume(volume));
+    }
+
+    /// Stops recording, giving back the hook to keep adjusting the channels, along with the changes in the order they were made.
+    pub fn finish(self) -> (ChannelHook, Vec<TimedEvent>) {
+        (self.hook, self.events)
+    }
+}
+
+impl Deref for RecordingHook {
+    type Target = ChannelHook;
+
+    fn deref(&self) -> &Self::Target {
+        &self.hook
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -187,7 +1001,7 @@ mod tests {
             .add_source(sources::SineWave::new(220.0))
             .add_source(sources::SquareWave::new(220.0))
             .build();
-        let _channel_playback = ChannelPlayback::new(channels);
+        let _channel_playback = ChannelPlayback::new(channels).unwrap();
         hook.set_volume(0, 0.5);
         hook.set_volume(1, 0.1);
         std::thread::sleep(Duration::from_secs(2));
@@ -198,4 +1012,530 @@ mod tests {
         hook.set_volume(1, 0.0);
         std::thread::sleep(Duration::from_secs(2));
     }
+
+    #[test]
+    fn test_toggle_pause() {
+        let (channels, mut hook) = ChannelsBuilder::new()
+            .add_source(sources::SineWave::new(220.0))
+            .build();
+        let mut channel_playback = ChannelPlayback::new(channels).unwrap();
+        hook.set_volume(0, 0.5);
+        assert!(!channel_playback.is_paused());
+        channel_playback.toggle_pause();
+        assert!(channel_playback.is_paused());
+        std::thread::sleep(Duration::from_secs(1));
+        channel_playback.toggle_pause();
+        assert!(!channel_playback.is_paused());
+        std::thread::sleep(Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_freq_to_hue() {
+        let (hue, saturation, value) = freq_to_hue(440.0);
+        assert!((hue - 161.09).abs() < 0.01);
+        assert_eq!(saturation, 1.0);
+        assert_eq!(value, 1.0);
+        assert_eq!(freq_to_hue(440.0), freq_to_hue(440.0));
+        assert_eq!(freq_to_hue(1.0).0, 0.0);
+        assert_eq!(freq_to_hue(100000.0).0, 360.0);
+    }
+
+    #[test]
+    fn test_retrigger() {
+        let mut sine = Arc::new(Mutex::new(sources::SineWave::new(440.0)));
+        let (_channels, mut hook) = ChannelsBuilder::new().add_source_raw(&mut sine).build();
+        sine.lock().unwrap().nth(100);
+        hook.retrigger(0);
+        assert_eq!(sine.lock().unwrap().next(), Some(0.0));
+    }
+
+    #[test]
+    fn test_boxed_sources() {
+        let mut builder = ChannelsBuilder::new();
+        for waveform in sources::Waveform::ALL {
+            builder = builder.add_boxed(sources::make(waveform, 220.0));
+        }
+        let (mut channels, mut hook) = builder.build();
+        for index in 0..sources::Waveform::ALL.len() {
+            hook.set_volume(index, 0.5);
+            hook.set_frequency(index, 440.0);
+        }
+        assert!(channels.next().unwrap().is_finite());
+    }
+
+    #[test]
+    fn test_fade() {
+        let (mut channels, mut hook) = ChannelsBuilder::new()
+            .add_source(sources::SineWave::new(220.0))
+            .build();
+        hook.set_volume(0, 0.5);
+        hook.fade_out(0, Duration::from_millis(100));
+        channels.nth(SAMPLE_RATE as usize / 20);
+        assert!(hook.volume(0) > 0.0 && hook.volume(0) < 0.5);
+        channels.nth(SAMPLE_RATE as usize / 20);
+        assert_eq!(hook.volume(0), 0.0);
+        hook.fade_in(0, None, Duration::from_millis(100));
+        channels.nth(SAMPLE_RATE as usize / 10 + 1);
+        assert_eq!(hook.volume(0), 0.5);
+    }
+
+    #[test]
+    fn test_playback_info() {
+        let (channels, _hook) = ChannelsBuilder::new()
+            .add_source(sources::SineWave::new(220.0))
+            .add_source(sources::SquareWave::new(220.0))
+            .build();
+        let channel_playback = ChannelPlayback::new(channels).unwrap();
+        assert_eq!(channel_playback.sample_rate(), SAMPLE_RATE);
+        assert_eq!(channel_playback.channel_count(), 2);
+    }
+
+    #[test]
+    fn test_pitch_bend() {
+        let (_channels, mut hook) = ChannelsBuilder::new()
+            .add_source(sources::SineWave::new(440.0))
+            .add_source(sources::WhiteNoise::new())
+            .build();
+        hook.bend_semitones(0, 12.0);
+        assert!((hook.frequency(0).unwrap() - 880.0).abs() < 0.01);
+        hook.bend_semitones(1, 12.0);
+        assert_eq!(hook.frequency(1), None);
+        hook.set_note(0, 57);
+        assert!((hook.frequency(0).unwrap() - 220.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_chord() {
+        let c_major = [261.63, 329.63, 392.0];
+        let (mut channels, mut hook) = ChannelsBuilder::new()
+            .add_source(sources::SquareWave::new(110.0))
+            .add_chord(sources::SineWave::new, &c_major)
+            .build();
+        assert_eq!(hook.chord_channels(0), 1..4);
+        for (index, frequency) in hook.chord_channels(0).zip(c_major) {
+            assert_eq!(hook.frequency(index), Some(frequency));
+        }
+        hook.set_chord_volume(0, 0.5);
+        assert_eq!(hook.volume(0), 0.0);
+        assert!(hook
+            .chord_channels(0)
+            .all(|index| hook.volume(index) == 0.5));
+        hook.set_chord_frequencies(0, &[220.0, 277.18, 329.63]);
+        assert_eq!(hook.frequency(1), Some(220.0));
+        assert!(channels.next().unwrap().is_finite());
+    }
+
+    /// A source which only outputs a constant offset.
+    struct Dc(f32);
+
+    impl Source for Dc {
+        fn current_frame_len(&self) -> Option<usize> {
+            None
+        }
+        fn channels(&self) -> u16 {
+            1
+        }
+        fn sample_rate(&self) -> u32 {
+            SAMPLE_RATE
+        }
+        fn total_duration(&self) -> Option<Duration> {
+            None
+        }
+    }
+
+    impl Iterator for Dc {
+        type Item = f32;
+        fn next(&mut self) -> Option<Self::Item> {
+            Some(self.0)
+        }
+    }
+
+    impl AdjustableSource for Dc {
+        fn set_frequency(&mut self, _frequency: f32) {}
+    }
+
+    #[test]
+    fn test_highpass() {
+        let (mut channels, mut hook) = ChannelsBuilder::new().add_source(Dc(0.5)).build();
+        hook.set_volume(0, 1.0);
+        assert_eq!(channels.nth(SAMPLE_RATE as usize), Some(0.5));
+
+        let (mut channels, mut hook) = ChannelsBuilder::new()
+            .add_source(Dc(0.5))
+            .add_source(sources::SineWave::new(440.0))
+            .with_per_channel_highpass(20.0)
+            .build();
+        hook.set_volume(0, 1.0);
+        assert!(channels.next().unwrap() > 0.2);
+        let offset = channels.nth(SAMPLE_RATE as usize).unwrap();
+        assert!(offset.abs() < 0.001);
+    }
+
+    #[test]
+    fn test_output_level() {
+        let (mut channels, mut hook) = ChannelsBuilder::new()
+            .add_source(sources::SineWave::new(440.0))
+            .build();
+        assert_eq!(hook.output_level(), 0.0);
+        hook.set_volume(0, 1.0);
+        channels.nth(SAMPLE_RATE as usize);
+        assert!(hook.output_level() > 0.99);
+        hook.set_volume(0, 0.0);
+        channels.nth(SAMPLE_RATE as usize);
+        assert!(hook.output_level() < 0.1);
+    }
+
+    #[test]
+    fn test_post_fx() {
+        let build = |halve: bool| {
+            let builder = ChannelsBuilder::new().add_source(sources::SineWave::new(440.0));
+            let (channels, mut hook) = if halve {
+                builder.with_post_fx(|sample| sample * 0.5).build()
+            } else {
+                builder.build()
+            };
+            hook.set_volume(0, 1.0);
+            channels
+        };
+        for (plain, halved) in build(false).zip(build(true)).take(1000) {
+            assert_eq!(halved, plain * 0.5);
+        }
+    }
+
+    #[test]
+    fn test_nested_channels() {
+        let (drums, mut drums_hook) = ChannelsBuilder::new()
+            .add_source(sources::SquareWave::new(110.0))
+            .add_source(sources::SquareWave::new(55.0))
+            .build();
+        let (mut master, mut master_hook) = ChannelsBuilder::new()
+            .add_source(drums)
+            .add_source(sources::SineWave::new(440.0))
+            .build();
+        drums_hook.set_volume(0, 1.0);
+        drums_hook.set_volume(1, 1.0);
+        assert!(master.by_ref().take(1000).all(|sample| sample == 0.0));
+        master_hook.set_volume(0, 0.5);
+        let samples: Vec<f32> = master.by_ref().take(1000).collect();
+        assert!(samples.iter().any(|sample| *sample != 0.0));
+        assert!(samples.iter().all(|sample| sample.abs() <= 0.5));
+        master_hook.set_frequency(0, 880.0);
+        assert_eq!(drums_hook.frequency(1), Some(55.0));
+    }
+
+    #[test]
+    fn test_declick() {
+        let (mut channels, mut hook) = ChannelsBuilder::new()
+            .add_source(sources::SquareWave::new(1.0))
+            .with_declick_ms(5.0)
+            .build();
+        channels.nth(100);
+        hook.set_volume(0, 1.0);
+        let ramp = SAMPLE_RATE as usize / 200;
+        let samples: Vec<f32> = channels.by_ref().take(ramp + 10).collect();
+        assert!(samples[0] < 0.01);
+        assert!(samples
+            .windows(2)
+            .all(|pair| pair[1] > pair[0] || pair[1] == 1.0));
+        assert!(samples[ramp / 2] > 0.4 && samples[ramp / 2] < 0.6);
+        assert_eq!(samples[ramp], 1.0);
+
+        let (mut channels, mut hook) = ChannelsBuilder::new()
+            .add_source(sources::SquareWave::new(1.0))
+            .build();
+        hook.set_volume(0, 2.0);
+        assert_eq!(channels.next(), Some(2.0));
+    }
+
+    #[test]
+    fn test_swap_channels() {
+        let (channels, mut hook) = ChannelsBuilder::new()
+            .add_source(sources::SineWave::new(220.0))
+            .build();
+        let mut channel_playback = ChannelPlayback::new(channels).unwrap();
+        hook.set_volume(0, 0.5);
+        std::thread::sleep(Duration::from_secs(1));
+        let (new_channels, mut new_hook) = ChannelsBuilder::new()
+            .add_source(sources::SquareWave::new(330.0))
+            .add_source(sources::SineWave::new(440.0))
+            .build();
+        new_hook.set_volume(0, 0.1);
+        new_hook.set_volume(1, 0.5);
+        channel_playback.swap_channels(new_channels).unwrap();
+        assert_eq!(channel_playback.channel_count(), 2);
+        std::thread::sleep(Duration::from_secs(1));
+        assert!(new_hook.output_level() > 0.0);
+    }
+
+    #[test]
+    fn test_balance() {
+        let (channels, mut hook) = ChannelsBuilder::new()
+            .add_source(sources::SquareWave::new(220.0))
+            .build();
+        hook.set_volume(0, 1.0);
+        let balance = Arc::new(AtomicF32::new(0.0));
+        let mut balanced = Balanced::new(channels, balance.clone());
+        assert_eq!(balanced.channels(), 2);
+        let (left, right) = (balanced.next().unwrap(), balanced.next().unwrap());
+        assert_eq!(left, right);
+        assert!(left.abs() > 0.0);
+        balance.store(-1.0);
+        for _ in 0..100 {
+            let (left, right) = (balanced.next().unwrap(), balanced.next().unwrap());
+            assert!(left.abs() > 0.0);
+            assert_eq!(right, 0.0);
+        }
+        balance.store(0.5);
+        let (left, right) = (balanced.next().unwrap(), balanced.next().unwrap());
+        assert_eq!(left, right * 0.5);
+    }
+
+    #[test]
+    fn test_set_balance() {
+        let (channels, mut hook) = ChannelsBuilder::new()
+            .add_source(sources::SineWave::new(220.0))
+            .build();
+        let mut channel_playback = ChannelPlayback::new(channels).unwrap();
+        hook.set_volume(0, 0.5);
+        channel_playback.set_balance(-2.0);
+        assert_eq!(channel_playback.balance(), -1.0);
+        std::thread::sleep(Duration::from_secs(1));
+        channel_playback.set_balance(1.0);
+        std::thread::sleep(Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_volume_db() {
+        let (_channels, mut hook) = ChannelsBuilder::new()
+            .add_source(sources::SineWave::new(220.0))
+            .build();
+        hook.set_volume_db(0, 0.0);
+        assert_eq!(hook.volume(0), 1.0);
+        assert!(hook.volume_db(0).abs() < 1e-6);
+        hook.set_volume_db(0, -6.0);
+        assert!((hook.volume(0) - 0.5).abs() < 0.01);
+        assert!((hook.volume_db(0) + 6.0).abs() < 1e-4);
+        hook.set_volume(0, 0.0);
+        assert_eq!(hook.volume_db(0), f32::NEG_INFINITY);
+    }
+
+    #[test]
+    fn test_initial_volume() {
+        let (_channels, hook) = ChannelsBuilder::new()
+            .add_source_with_volume(sources::SineWave::new(220.0), 0.5)
+            .add_source_with_volume(sources::SquareWave::new(220.0), 0.8)
+            .add_source(sources::SineWave::new(440.0))
+            .build();
+        assert_eq!(hook.volume(0), 0.5);
+        assert_eq!(hook.volume(1), 0.8);
+        assert_eq!(hook.volume(2), 0.0);
+    }
+
+    #[test]
+    fn test_clip_count() {
+        let (mut channels, mut hook) = ChannelsBuilder::new()
+            .add_source_with_volume(sources::SquareWave::new(220.0), 1.0)
+            .add_source_with_volume(sources::SquareWave::new(220.0), 1.0)
+            .add_source_with_volume(sources::SquareWave::new(220.0), 1.0)
+            .build();
+        for _ in 0..1000 {
+            channels.next();
+        }
+        assert_eq!(hook.clip_count(), 0);
+        for i in 0..3 {
+            hook.set_volume(i, 1.5);
+        }
+        for _ in 0..1000 {
+            channels.next();
+        }
+        assert!(hook.clip_count() > 0);
+        hook.reset_clip_count();
+        assert_eq!(hook.clip_count(), 0);
+
+        // A limiter in the post effects keeps the output in range, but the mix going into it is still counted.
+        let (mut channels, hook) = ChannelsBuilder::new()
+            .add_source_with_volume(sources::SquareWave::new(220.0), 1.5)
+            .with_post_fx(|sample| sample.clamp(-1.0, 1.0))
+            .build();
+        for _ in 0..1000 {
+            assert!(channels.next().unwrap().abs() <= 1.0);
+        }
+        assert!(hook.clip_count() > 0);
+    }
+
+    #[test]
+    fn test_sidechain() {
+        let (mut channels, mut hook) = ChannelsBuilder::new()
+            .add_source_with_volume(Dc(0.8), 1.0)
+            .add_source(Dc(1.0))
+            .build();
+        hook.set_sidechain(0, 1, 0.5, Duration::from_millis(100));
+        assert!((channels.nth(100).unwrap() - 0.4).abs() < 1e-6);
+        // The trigger halves the target while it plays, giving (0.4 + 1) / 2 instead of (0.8 + 1) / 2.
+        hook.set_volume(1, 1.0);
+        assert!((channels.next().unwrap() - 0.7).abs() < 1e-6);
+        // The target comes back up over the release once the trigger stops.
+        hook.set_volume(1, 0.0);
+        assert!(channels.next().unwrap() < 0.21);
+        assert!(channels.nth(SAMPLE_RATE as usize).unwrap() > 0.39);
+        hook.clear_sidechain(0);
+        hook.set_volume(1, 1.0);
+        assert!((channels.next().unwrap() - 0.9).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_position() {
+        let (mut channels, mut hook) = ChannelsBuilder::new()
+            .add_source(sources::SineWave::new(220.0))
+            .build();
+        assert_eq!(hook.position_samples(), 0);
+        for _ in 0..1000 {
+            channels.next();
+        }
+        assert_eq!(hook.position_samples(), 1000);
+        assert_eq!(
+            hook.position_seconds(),
+            1000.0 / channels.sample_rate() as f64
+        );
+        hook.reset_position();
+        assert_eq!(hook.position_samples(), 0);
+    }
+
+    #[test]
+    fn test_source_ext() {
+        let source = sources::SineWave::new(0.0).with_frequency(440.0);
+        assert_eq!(source.frequency(), Some(440.0));
+        let boxed = sources::SquareWave::new(220.0)
+            .boxed()
+            .with_frequency(110.0);
+        assert_eq!(boxed.frequency(), Some(110.0));
+    }
+
+    #[test]
+    fn test_audio_engine() {
+        let mut engine =
+            AudioEngine::start(ChannelsBuilder::new().add_source(sources::SineWave::new(220.0)))
+                .unwrap();
+        engine.set_volume(0, 0.5);
+        assert_eq!(engine.volume(0), 0.5);
+        std::thread::sleep(Duration::from_secs(1));
+        let position = engine.position_samples();
+        assert!(position > 0);
+        assert!(!engine.playback().is_paused());
+        std::thread::sleep(Duration::from_millis(500));
+        assert!(engine.position_samples() > position);
+    }
+
+    #[test]
+    fn test_concurrent_volume() {
+        let (mut channels, mut hook) = ChannelsBuilder::new()
+            .add_source(sources::SquareWave::new(220.0))
+            .build();
+        let renderer = std::thread::spawn(move || {
+            for _ in 0..200_000 {
+                channels.next();
+            }
+            channels
+        });
+        for i in 0..100_000 {
+            hook.set_volume(0, (i % 10) as f32 / 10.0);
+            if i % 1000 == 0 {
+                hook.fade_out(0, Duration::from_millis(1));
+            }
+        }
+        hook.set_volume(0, 0.75);
+        let mut channels = renderer.join().unwrap();
+        assert_eq!(hook.volume(0), 0.75);
+        channels.next();
+        assert_eq!(hook.volume(0), 0.75);
+    }
+
+    #[test]
+    fn test_recording_hook() {
+        let (_channels, hook) = ChannelsBuilder::new()
+            .add_source(sources::SineWave::new(220.0))
+            .add_source(sources::SquareWave::new(110.0))
+            .build();
+        let mut hook = RecordingHook::new(hook);
+        hook.set_frequency(0, 440.0);
+        std::thread::sleep(Duration::from_millis(10));
+        hook.set_volume(1, 0.5);
+        assert_eq!(hook.frequency(0), Some(440.0));
+        assert_eq!(hook.volume(1), 0.5);
+        let (mut hook, events) = hook.finish();
+        assert_eq!(events.len(), 2);
+        assert_eq!(
+            (events[0].channel, events[0].event),
+            (0, ChannelEvent::Frequency(440.0))
+        );
+        assert_eq!(
+            (events[1].channel, events[1].event),
+            (1, ChannelEvent::Volume(0.5))
+        );
+        assert!(events[1].time >= events[0].time + Duration::from_millis(10));
+        hook.set_volume(1, 0.25);
+        assert_eq!(hook.volume(1), 0.25);
+    }
+
+    #[test]
+    fn test_named_channels() {
+        let (_channels, mut hook) = ChannelsBuilder::new()
+            .add_source(sources::SineWave::new(220.0))
+            .add_named_source("lead", sources::SquareWave::new(440.0))
+            .add_named_source("bass", sources::TriangleWave::new(55.0))
+            .build();
+        assert_eq!(hook.index_of("lead"), Some(1));
+        assert_eq!(hook.index_of("bass"), Some(2));
+        assert_eq!(hook.index_of("drums"), None);
+        hook.set_volume(hook.index_of("lead").unwrap(), 0.5);
+        hook.set_volume_by_name("bass", 0.25);
+        hook.set_frequency_by_name("bass", 110.0);
+        assert_eq!(hook.volume(1), 0.5);
+        assert_eq!(hook.volume(2), 0.25);
+        assert_eq!(hook.frequency(2), Some(110.0));
+        assert_eq!(hook.frequency(0), Some(220.0));
+    }
+
+    #[test]
+    fn test_render_loop() {
+        let (channels, _hook) = ChannelsBuilder::new()
+            .add_source_with_volume(sources::SineWave::new(110.25), 1.0)
+            .build();
+        let mut looped = render_loop(channels, Duration::from_secs(1));
+        let rendered = looped.samples().to_vec();
+        assert_eq!(rendered.len(), SAMPLE_RATE as usize);
+        let played: Vec<f32> = looped.by_ref().take(rendered.len() + 10).collect();
+        assert_eq!(played[..rendered.len()], rendered[..]);
+        assert_eq!(played[rendered.len()..], rendered[..10]);
+        // 110.25Hz doesn't fit a whole number of times in a second, so the raw loop jumps where it wraps around.
+        assert!((rendered[0] - rendered[rendered.len() - 1]).abs() > 0.5);
+        let crossfade = SAMPLE_RATE as usize / 100;
+        let mut crossfaded = looped.with_crossfade(Duration::from_millis(10));
+        let samples = crossfaded.samples().to_vec();
+        assert_eq!(samples.len(), rendered.len() - crossfade);
+        assert_eq!(samples[0], rendered[samples.len()]);
+        assert_eq!(samples[crossfade..], rendered[crossfade..samples.len()]);
+        let played: Vec<f32> = crossfaded.by_ref().take(samples.len() * 2).collect();
+        let largest_step = played
+            .windows(2)
+            .map(|pair| (pair[1] - pair[0]).abs())
+            .fold(0.0, f32::max);
+        assert!(largest_step < 0.1);
+        // Crossfading a loop which is playing past the new end wraps it around instead of silencing it.
+        let mut late = sources::LoopSource::new(rendered.clone());
+        late.by_ref().take(rendered.len() - 1).for_each(drop);
+        let mut late = late.with_crossfade(Duration::from_millis(10));
+        assert_eq!(
+            late.next(),
+            Some(samples[rendered.len() - 1 - samples.len()])
+        );
+    }
+
+    #[test]
+    fn test_default_device_available() {
+        // There may or may not be an audio device where the tests run, but checking must not panic either way.
+        if default_device_available() {
+            assert!(OutputStream::try_default().is_ok());
+        }
+    }
 }
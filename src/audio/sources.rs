@@ -1,9 +1,15 @@
+use std::collections::VecDeque;
+use std::io::{Read, Seek};
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
 use rand::distributions::Distribution;
-use rand::thread_rng;
+use rand::rngs::StdRng;
+use rand::{thread_rng, Rng, SeedableRng};
 use rand_distr::Normal;
-use rodio::{Sample, Source};
+use rodio::decoder::DecoderError;
+use rodio::source::UniformSourceIterator;
+use rodio::{Decoder, Sample, Source};
 
 use crate::audio::{AdjustableSource, SAMPLE_RATE};
 
@@ -56,6 +62,14 @@ impl AdjustableSource for SquareWave {
     fn set_frequency(&mut self, frequency: f32) {
         self.frequency = frequency;
     }
+
+    fn frequency(&self) -> Option<f32> {
+        Some(self.frequency)
+    }
+
+    fn retrigger(&mut self) {
+        self.phase = 0.0;
+    }
 }
 
 #[derive(Copy, Clone, Debug, PartialEq)]
@@ -105,6 +119,14 @@ impl AdjustableSource for SawtoothWave {
     fn set_frequency(&mut self, frequency: f32) {
         self.frequency = frequency;
     }
+
+    fn frequency(&self) -> Option<f32> {
+        Some(self.frequency)
+    }
+
+    fn retrigger(&mut self) {
+        self.phase = 0.0;
+    }
 }
 
 #[derive(Copy, Clone, Debug, PartialEq)]
@@ -156,6 +178,14 @@ impl AdjustableSource for TriangleWave {
     fn set_frequency(&mut self, frequency: f32) {
         self.frequency = frequency;
     }
+
+    fn frequency(&self) -> Option<f32> {
+        Some(self.frequency)
+    }
+
+    fn retrigger(&mut self) {
+        self.phase = 0.0;
+    }
 }
 
 #[derive(Copy, Clone, Debug, PartialEq)]
@@ -203,16 +233,35 @@ impl AdjustableSource for SineWave {
     fn set_frequency(&mut self, frequency: f32) {
         self.frequency = frequency;
     }
+
+    fn frequency(&self) -> Option<f32> {
+        Some(self.frequency)
+    }
+
+    fn retrigger(&mut self) {
+        self.phase = 0.0;
+    }
 }
 
-#[derive(Copy, Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 /// A noise source. Generates a random number between -1 and 1 with a normal distribution.
-pub struct WhiteNoise;
+pub struct WhiteNoise {
+    rng: StdRng,
+}
 
 impl WhiteNoise {
-    /// Create a new noise source.
+    /// Create a new noise source, seeded from the system's entropy so that every instance sounds different.
     pub fn new() -> WhiteNoise {
-        WhiteNoise
+        WhiteNoise {
+            rng: StdRng::from_entropy(),
+        }
+    }
+
+    /// Create a new noise source from a seed. Sources with the same seed generate the same samples, e.g. for tests or reproducible sounds.
+    pub fn with_seed(seed: u64) -> WhiteNoise {
+        WhiteNoise {
+            rng: StdRng::seed_from_u64(seed),
+        }
     }
 }
 
@@ -238,16 +287,18 @@ impl Source for WhiteNoise {
 impl Iterator for WhiteNoise {
     type Item = f32;
     fn next(&mut self) -> Option<Self::Item> {
-        let result = Normal::new(0.0, 1.0).unwrap().sample(&mut thread_rng());
+        let result = Normal::new(0.0, 1.0).unwrap().sample(&mut self.rng);
         Some(result)
     }
 }
 
 #[derive(Copy, Clone, Debug, PartialEq)]
 /// A semi-triangle source, with adjustable frequency. Linearly goes from -1 to 1, then down to 0, back to 1, then down to -1.
+/// Its frequency can be changed smoothly with `set_frequency_smooth`, which waits for the end of the current quarter of the cycle.
 pub struct SemiTriangle {
     phase: f32,
     frequency: f32,
+    pending_frequency: Option<f32>,
 }
 
 impl SemiTriangle {
@@ -256,6 +307,18 @@ impl SemiTriangle {
         SemiTriangle {
             phase: 0.0,
             frequency,
+            pending_frequency: None,
+        }
+    }
+
+    /// Moves the phase forward by one sample, switching to the pending frequency when it crosses into another quarter of the cycle.
+    fn advance_phase(&mut self) {
+        let quarter = (self.phase * 4.0) as u32;
+        self.phase = (self.phase + self.frequency / SAMPLE_RATE as f32) % 1.0;
+        if (self.phase * 4.0) as u32 != quarter {
+            if let Some(frequency) = self.pending_frequency.take() {
+                self.frequency = frequency;
+            }
         }
     }
 }
@@ -287,7 +350,7 @@ impl Iterator for SemiTriangle {
         } else {
             1.0 - ((self.phase - 0.75) * 8.0)
         };
-        self.phase = (self.phase + self.frequency / SAMPLE_RATE as f32) % 1.0;
+        self.advance_phase();
         Some(result)
     }
 }
@@ -295,6 +358,30 @@ impl Iterator for SemiTriangle {
 impl AdjustableSource for SemiTriangle {
     fn set_frequency(&mut self, frequency: f32) {
         self.frequency = frequency;
+        self.pending_frequency = None;
+    }
+
+    /// The frequency set last, even if a smooth change to it is still waiting for the end of the current quarter.
+    fn frequency(&self) -> Option<f32> {
+        Some(self.pending_frequency.unwrap_or(self.frequency))
+    }
+
+    /// Restarts the cycle, switching to the pending frequency right away if there is one.
+    fn retrigger(&mut self) {
+        self.phase = 0.0;
+        if let Some(frequency) = self.pending_frequency.take() {
+            self.frequency = frequency;
+        }
+    }
+
+    /// Keeps the current frequency until the end of the current quarter of the cycle, where the slope changes anyway.
+    /// At a frequency of 0, the phase never reaches the end of the quarter, so the change happens right away.
+    fn set_frequency_smooth(&mut self, frequency: f32) {
+        if self.frequency == 0.0 {
+            self.set_frequency(frequency);
+        } else {
+            self.pending_frequency = Some(frequency);
+        }
     }
 }
 
@@ -343,13 +430,23 @@ impl AdjustableSource for SemiSine {
     fn set_frequency(&mut self, frequency: f32) {
         self.frequency = frequency;
     }
+
+    fn frequency(&self) -> Option<f32> {
+        Some(self.frequency)
+    }
+
+    fn retrigger(&mut self) {
+        self.phase = 0.0;
+    }
 }
 
 #[derive(Copy, Clone, Debug, PartialEq)]
 /// A stepping square source, with adjustable frequency. Goes up and back down in steps of 1 (-1, 0, 1, 0, etc)
+/// Its frequency can be changed smoothly with `set_frequency_smooth`, which waits for the end of the current quarter of the cycle.
 pub struct StepSquare {
     phase: f32,
     frequency: f32,
+    pending_frequency: Option<f32>,
 }
 
 impl StepSquare {
@@ -358,6 +455,18 @@ impl StepSquare {
         StepSquare {
             phase: 0.0,
             frequency,
+            pending_frequency: None,
+        }
+    }
+
+    /// Moves the phase forward by one sample, switching to the pending frequency when it crosses into another quarter of the cycle.
+    fn advance_phase(&mut self) {
+        let quarter = (self.phase * 4.0) as u32;
+        self.phase = (self.phase + self.frequency / SAMPLE_RATE as f32) % 1.0;
+        if (self.phase * 4.0) as u32 != quarter {
+            if let Some(frequency) = self.pending_frequency.take() {
+                self.frequency = frequency;
+            }
         }
     }
 }
@@ -389,7 +498,7 @@ impl Iterator for StepSquare {
         } else {
             0.0
         };
-        self.phase = (self.phase + self.frequency / SAMPLE_RATE as f32) % 1.0;
+        self.advance_phase();
         Some(result)
     }
 }
@@ -397,101 +506,3015 @@ impl Iterator for StepSquare {
 impl AdjustableSource for StepSquare {
     fn set_frequency(&mut self, frequency: f32) {
         self.frequency = frequency;
+        self.pending_frequency = None;
+    }
+
+    /// The frequency set last, even if a smooth change to it is still waiting for the end of the current quarter.
+    fn frequency(&self) -> Option<f32> {
+        Some(self.pending_frequency.unwrap_or(self.frequency))
+    }
+
+    /// Restarts the cycle, switching to the pending frequency right away if there is one.
+    fn retrigger(&mut self) {
+        self.phase = 0.0;
+        if let Some(frequency) = self.pending_frequency.take() {
+            self.frequency = frequency;
+        }
+    }
+
+    /// Keeps the current frequency until the end of the current quarter of the cycle, where the slope changes anyway.
+    /// At a frequency of 0, the phase never reaches the end of the quarter, so the change happens right away.
+    fn set_frequency_smooth(&mut self, frequency: f32) {
+        if self.frequency == 0.0 {
+            self.set_frequency(frequency);
+        } else {
+            self.pending_frequency = Some(frequency);
+        }
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use rodio::{OutputStream, Sink};
+/// The shape of an LFO's oscillation.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum LfoShape {
+    Sine,
+    Triangle,
+    Square,
+    Saw,
+}
 
-    use crate::audio::sources::*;
+#[derive(Copy, Clone, Debug, PartialEq)]
+/// A low frequency oscillator, meant to modulate other sources (e.g. for vibrato or tremolo).
+/// Produces a value between -depth and depth, with a depth of at most 1.
+/// It can be played directly, but is mostly meant to be read by other sources through `tick`.
+pub struct Lfo {
+    phase: f32,
+    rate: f32,
+    depth: f32,
+    shape: LfoShape,
+}
 
-    #[test]
-    fn all_test() {
-        sine_test();
-        square_test();
-        sawtooth_test();
-        triangle_test();
-        white_noise_test();
+impl Lfo {
+    /// Create a new LFO with the given shape and rate (in Hz), with a depth of 1.
+    pub fn new(shape: LfoShape, rate: f32) -> Lfo {
+        Lfo {
+            phase: 0.0,
+            rate,
+            depth: 1.0,
+            shape,
+        }
     }
 
-    #[test]
-    fn sine_test() {
-        let (_stream, handle) = OutputStream::try_default().unwrap();
-        let sink = Sink::try_new(&handle).ok().unwrap();
-        let source = SineWave::new(220.0);
-        sink.set_volume(0.2);
-        sink.append(source);
-        std::thread::sleep(Duration::from_secs(2));
+    /// Set the rate (in Hz) of the LFO.
+    pub fn set_rate(&mut self, rate: f32) {
+        self.rate = rate;
     }
 
-    #[test]
-    fn square_test() {
-        let (_stream, handle) = OutputStream::try_default().unwrap();
-        let sink = Sink::try_new(&handle).ok().unwrap();
-        let source = SquareWave::new(220.0);
-        sink.set_volume(0.2);
-        sink.append(source);
-        std::thread::sleep(Duration::from_secs(2));
+    /// Set the depth of the LFO, i.e. how far from 0 its values can go. Clamped between 0 and 1.
+    pub fn set_depth(&mut self, depth: f32) {
+        self.depth = depth.clamp(0.0, 1.0);
     }
 
-    #[test]
-    fn sawtooth_test() {
-        let (_stream, handle) = OutputStream::try_default().unwrap();
-        let sink = Sink::try_new(&handle).ok().unwrap();
-        let source = SawtoothWave::new(220.0);
-        sink.set_volume(0.2);
-        sink.append(source);
-        std::thread::sleep(Duration::from_secs(2));
+    /// Set the shape of the LFO.
+    pub fn set_shape(&mut self, shape: LfoShape) {
+        self.shape = shape;
     }
 
-    #[test]
-    fn triangle_test() {
-        let (_stream, handle) = OutputStream::try_default().unwrap();
-        let sink = Sink::try_new(&handle).ok().unwrap();
-        let source = TriangleWave::new(220.0);
-        sink.set_volume(0.2);
-        sink.append(source);
-        std::thread::sleep(Duration::from_secs(2));
+    /// Get the current value of the LFO and advance it by one sample.
+    pub fn tick(&mut self) -> f32 {
+        let result = match self.shape {
+            LfoShape::Sine => (self.phase * 2.0 * std::f32::consts::PI).sin(),
+            LfoShape::Triangle => {
+                if self.phase < 0.5 {
+                    self.phase * 4.0 - 1.0
+                } else {
+                    -(self.phase * 4.0 - 3.0)
+                }
+            }
+            LfoShape::Square => {
+                if self.phase < 0.5 {
+                    1.0
+                } else {
+                    -1.0
+                }
+            }
+            LfoShape::Saw => self.phase * 2.0 - 1.0,
+        };
+        self.phase = (self.phase + self.rate / SAMPLE_RATE as f32) % 1.0;
+        result * self.depth
     }
+}
 
-    #[test]
-    fn semitriangle_test() {
-        let (_stream, handle) = OutputStream::try_default().unwrap();
-        let sink = Sink::try_new(&handle).ok().unwrap();
-        let source = SemiTriangle::new(220.0);
-        sink.set_volume(0.2);
-        sink.append(source);
-        std::thread::sleep(Duration::from_secs(2));
+impl Source for Lfo {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+    fn channels(&self) -> u16 {
+        1
+    }
+    fn sample_rate(&self) -> u32 {
+        SAMPLE_RATE
+    }
+    fn total_duration(&self) -> Option<Duration> {
+        None
     }
+}
 
-    #[test]
-    fn semisine_test() {
-        let (_stream, handle) = OutputStream::try_default().unwrap();
-        let sink = Sink::try_new(&handle).ok().unwrap();
-        let source = SemiSine::new(220.0);
-        sink.set_volume(0.2);
-        sink.append(source);
-        std::thread::sleep(Duration::from_secs(2));
+impl Iterator for Lfo {
+    type Item = f32;
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(self.tick())
     }
+}
 
-    #[test]
-    fn stepsquare_test() {
-        let (_stream, handle) = OutputStream::try_default().unwrap();
-        let sink = Sink::try_new(&handle).ok().unwrap();
-        let source = StepSquare::new(220.0);
-        sink.set_volume(0.2);
-        sink.append(source);
-        std::thread::sleep(Duration::from_secs(2));
+impl AdjustableSource for Lfo {
+    fn set_frequency(&mut self, frequency: f32) {
+        self.rate = frequency;
     }
 
-    #[test]
-    fn white_noise_test() {
-        let (_stream, handle) = OutputStream::try_default().unwrap();
-        let sink = Sink::try_new(&handle).ok().unwrap();
-        let source = WhiteNoise;
-        sink.set_volume(0.2);
-        sink.append(source);
-        std::thread::sleep(Duration::from_secs(2));
+    fn frequency(&self) -> Option<f32> {
+        Some(self.rate)
+    }
+
+    fn retrigger(&mut self) {
+        self.phase = 0.0;
+    }
+}
+
+const PLUCK_DECAY: f32 = 0.996;
+/// The lowest frequency a plucked string can be tuned to, which bounds the length of its delay line.
+const PLUCK_MIN_FREQUENCY: f32 = 20.0;
+
+#[derive(Clone, Debug, PartialEq)]
+/// A plucked string source, with adjustable frequency, using the Karplus-Strong algorithm.
+/// The sound decays naturally after being plucked, and then stays silent until it is plucked again.
+pub struct PluckedString {
+    delay_line: VecDeque<f32>,
+}
+
+impl PluckedString {
+    /// Create a new plucked string source with the given frequency. It is plucked right away.
+    /// Frequencies below 20Hz are raised to 20Hz.
+    pub fn new(frequency: f32) -> PluckedString {
+        let mut string = PluckedString {
+            delay_line: VecDeque::from(vec![0.0; Self::delay_length(frequency)]),
+        };
+        string.pluck();
+        string
+    }
+
+    /// The delay line length for the given frequency. Frequencies below `PLUCK_MIN_FREQUENCY` (including 0, negative
+    /// and non-finite ones) are tuned to it instead, so that the delay line stays small.
+    fn delay_length(frequency: f32) -> usize {
+        let max = (SAMPLE_RATE as f32 / PLUCK_MIN_FREQUENCY) as usize;
+        if !frequency.is_finite() || frequency <= 0.0 {
+            return max;
+        }
+        ((SAMPLE_RATE as f32 / frequency).round() as usize).clamp(2, max)
+    }
+
+    /// Pluck the string again, filling it with fresh noise.
+    pub fn pluck(&mut self) {
+        let mut rng = thread_rng();
+        for sample in self.delay_line.iter_mut() {
+            *sample = rng.gen_range(-1.0..=1.0);
+        }
+    }
+}
+
+impl Source for PluckedString {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+    fn channels(&self) -> u16 {
+        1
+    }
+    fn sample_rate(&self) -> u32 {
+        SAMPLE_RATE
+    }
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+impl Iterator for PluckedString {
+    type Item = f32;
+    fn next(&mut self) -> Option<Self::Item> {
+        let result = self.delay_line.pop_front().unwrap();
+        let feedback = (result + self.delay_line[0]) * 0.5 * PLUCK_DECAY;
+        self.delay_line.push_back(feedback);
+        Some(result)
+    }
+}
+
+impl AdjustableSource for PluckedString {
+    /// Resizes the string. This doesn't pluck it again; the current vibration is kept (truncated or padded with silence).
+    /// Like in `new`, frequencies below 20Hz are raised to 20Hz.
+    fn set_frequency(&mut self, frequency: f32) {
+        self.delay_line.resize(Self::delay_length(frequency), 0.0);
+    }
+
+    /// The frequency the string is tuned to, which is rounded to a whole delay line length.
+    fn frequency(&self) -> Option<f32> {
+        Some(SAMPLE_RATE as f32 / self.delay_line.len() as f32)
+    }
+
+    fn retrigger(&mut self) {
+        self.pluck();
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+/// A source which crossfades from one source to another, so that changing voices doesn't click.
+/// Plays the first source until `start_fade` is called, then blends linearly into the second one over the fade's duration.
+/// Both sources receive frequency changes.
+pub struct Crossfade<A, B> {
+    from: A,
+    to: B,
+    fade_length: usize,
+    fade_position: Option<usize>,
+}
+
+impl<A, B> Crossfade<A, B>
+where
+    A: AdjustableSource<Item = f32>,
+    B: AdjustableSource<Item = f32>,
+{
+    /// Create a new crossfade from `from` to `to`, with the fade lasting for the given duration.
+    pub fn new(from: A, to: B, fade: Duration) -> Crossfade<A, B> {
+        Crossfade {
+            from,
+            to,
+            fade_length: ((fade.as_secs_f32() * SAMPLE_RATE as f32) as usize).max(1),
+            fade_position: None,
+        }
+    }
+
+    /// Start fading from the first source to the second one. Does nothing if the fade has already started.
+    pub fn start_fade(&mut self) {
+        if self.fade_position.is_none() {
+            self.fade_position = Some(0);
+        }
+    }
+}
+
+impl<A, B> Source for Crossfade<A, B>
+where
+    A: AdjustableSource<Item = f32>,
+    B: AdjustableSource<Item = f32>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+    fn channels(&self) -> u16 {
+        1
+    }
+    fn sample_rate(&self) -> u32 {
+        SAMPLE_RATE
+    }
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+impl<A, B> Iterator for Crossfade<A, B>
+where
+    A: AdjustableSource<Item = f32>,
+    B: AdjustableSource<Item = f32>,
+{
+    type Item = f32;
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.fade_position {
+            Some(position) if position >= self.fade_length => Some(self.to.next().unwrap_or(0.0)),
+            Some(position) => {
+                let t = position as f32 / self.fade_length as f32;
+                self.fade_position = Some(position + 1);
+                let from = self.from.next().unwrap_or(0.0);
+                let to = self.to.next().unwrap_or(0.0);
+                Some(from * (1.0 - t) + to * t)
+            }
+            None => {
+                self.to.next();
+                Some(self.from.next().unwrap_or(0.0))
+            }
+        }
+    }
+}
+
+impl<A, B> AdjustableSource for Crossfade<A, B>
+where
+    A: AdjustableSource<Item = f32>,
+    B: AdjustableSource<Item = f32>,
+{
+    fn set_frequency(&mut self, frequency: f32) {
+        self.from.set_frequency(frequency);
+        self.to.set_frequency(frequency);
+    }
+
+    fn frequency(&self) -> Option<f32> {
+        self.to.frequency().or_else(|| self.from.frequency())
+    }
+
+    /// Retriggers both sources, and goes back to playing the first one.
+    fn retrigger(&mut self) {
+        self.from.retrigger();
+        self.to.retrigger();
+        self.fade_position = None;
+    }
+}
+
+/// The PolyBLEP correction for a discontinuity at phase 0, given the phase and the phase increment per sample.
+fn poly_blep(phase: f32, increment: f32) -> f32 {
+    if phase < increment {
+        let t = phase / increment;
+        t + t - t * t - 1.0
+    } else if phase > 1.0 - increment {
+        let t = (phase - 1.0) / increment;
+        t * t + t + t + 1.0
+    } else {
+        0.0
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+/// A band-limited square wave source, with adjustable frequency. Like `SquareWave`, but with a lot less aliasing on high notes.
+pub struct BandLimitedSquare {
+    phase: f32,
+    frequency: f32,
+}
+
+impl BandLimitedSquare {
+    /// Create a new band-limited square wave source with the given frequency.
+    pub fn new(frequency: f32) -> BandLimitedSquare {
+        BandLimitedSquare {
+            phase: 0.0,
+            frequency,
+        }
+    }
+}
+
+impl Source for BandLimitedSquare {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+    fn channels(&self) -> u16 {
+        1
+    }
+    fn sample_rate(&self) -> u32 {
+        SAMPLE_RATE
+    }
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+impl Iterator for BandLimitedSquare {
+    type Item = f32;
+    fn next(&mut self) -> Option<Self::Item> {
+        let increment = self.frequency / SAMPLE_RATE as f32;
+        let naive = if self.phase < 0.5 { 1.0 } else { -1.0 };
+        let result = naive + poly_blep(self.phase, increment)
+            - poly_blep((self.phase + 0.5) % 1.0, increment);
+        self.phase = (self.phase + increment) % 1.0;
+        Some(result)
+    }
+}
+
+impl AdjustableSource for BandLimitedSquare {
+    fn set_frequency(&mut self, frequency: f32) {
+        self.frequency = frequency;
+    }
+
+    fn frequency(&self) -> Option<f32> {
+        Some(self.frequency)
+    }
+
+    fn retrigger(&mut self) {
+        self.phase = 0.0;
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+/// A band-limited sawtooth wave source, with adjustable frequency. Like `SawtoothWave`, but with a lot less aliasing on high notes.
+pub struct BandLimitedSaw {
+    phase: f32,
+    frequency: f32,
+}
+
+impl BandLimitedSaw {
+    /// Create a new band-limited sawtooth wave source with the given frequency.
+    pub fn new(frequency: f32) -> BandLimitedSaw {
+        BandLimitedSaw {
+            phase: 0.0,
+            frequency,
+        }
+    }
+}
+
+impl Source for BandLimitedSaw {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+    fn channels(&self) -> u16 {
+        1
+    }
+    fn sample_rate(&self) -> u32 {
+        SAMPLE_RATE
+    }
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+impl Iterator for BandLimitedSaw {
+    type Item = f32;
+    fn next(&mut self) -> Option<Self::Item> {
+        let increment = self.frequency / SAMPLE_RATE as f32;
+        let result = self.phase * 2.0 - 1.0 - poly_blep(self.phase, increment);
+        self.phase = (self.phase + increment) % 1.0;
+        Some(result)
+    }
+}
+
+impl AdjustableSource for BandLimitedSaw {
+    fn set_frequency(&mut self, frequency: f32) {
+        self.frequency = frequency;
+    }
+
+    fn frequency(&self) -> Option<f32> {
+        Some(self.frequency)
+    }
+
+    fn retrigger(&mut self) {
+        self.phase = 0.0;
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+/// A two-operator FM synth source, with adjustable frequency.
+/// A sine modulator running at `ratio` times the frequency is added to the phase of a sine carrier, scaled by the modulation index.
+/// An index of 0 gives a plain sine wave, and higher indices give brighter, richer sounds.
+pub struct FmSynth {
+    carrier_phase: f32,
+    modulator_phase: f32,
+    frequency: f32,
+    ratio: f32,
+    index: f32,
+}
+
+impl FmSynth {
+    /// Create a new FM synth source with the given carrier frequency, modulator frequency ratio, and modulation index.
+    pub fn new(frequency: f32, ratio: f32, index: f32) -> FmSynth {
+        FmSynth {
+            carrier_phase: 0.0,
+            modulator_phase: 0.0,
+            frequency,
+            ratio,
+            index,
+        }
+    }
+
+    /// Set the ratio between the modulator's frequency and the carrier's.
+    pub fn set_ratio(&mut self, ratio: f32) {
+        self.ratio = ratio;
+    }
+
+    /// Set the modulation index, i.e. how much the modulator affects the carrier.
+    pub fn set_index(&mut self, index: f32) {
+        self.index = index;
+    }
+}
+
+impl Source for FmSynth {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+    fn channels(&self) -> u16 {
+        1
+    }
+    fn sample_rate(&self) -> u32 {
+        SAMPLE_RATE
+    }
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+impl Iterator for FmSynth {
+    type Item = f32;
+    fn next(&mut self) -> Option<Self::Item> {
+        let modulator = (self.modulator_phase * 2.0 * std::f32::consts::PI).sin();
+        let result =
+            (self.carrier_phase * 2.0 * std::f32::consts::PI + self.index * modulator).sin();
+        self.carrier_phase = (self.carrier_phase + self.frequency / SAMPLE_RATE as f32) % 1.0;
+        self.modulator_phase =
+            (self.modulator_phase + self.frequency * self.ratio / SAMPLE_RATE as f32) % 1.0;
+        Some(result)
+    }
+}
+
+impl AdjustableSource for FmSynth {
+    fn set_frequency(&mut self, frequency: f32) {
+        self.frequency = frequency;
+    }
+
+    fn frequency(&self) -> Option<f32> {
+        Some(self.frequency)
+    }
+
+    fn retrigger(&mut self) {
+        self.carrier_phase = 0.0;
+        self.modulator_phase = 0.0;
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+/// An amplitude modulation synth source, with adjustable frequency.
+/// A sine carrier's amplitude is multiplied by `1 + depth * modulator`, with a sine modulator at an audio-rate frequency, which adds sidebands
+/// at the carrier frequency plus and minus the modulator frequency. Unlike ring modulation, the carrier itself stays audible.
+/// The output is scaled down by `1 + depth` so that it stays between -1 and 1.
+pub struct AmSynth {
+    carrier_phase: f32,
+    modulator_phase: f32,
+    frequency: f32,
+    mod_frequency: f32,
+    depth: f32,
+}
+
+impl AmSynth {
+    /// Create a new AM synth source with the given carrier frequency, modulator frequency, and modulation depth (between 0 and 1).
+    pub fn new(carrier_freq: f32, mod_freq: f32, depth: f32) -> AmSynth {
+        AmSynth {
+            carrier_phase: 0.0,
+            modulator_phase: 0.0,
+            frequency: carrier_freq,
+            mod_frequency: mod_freq,
+            depth: depth.clamp(0.0, 1.0),
+        }
+    }
+
+    /// Set the frequency of the modulator.
+    pub fn set_mod_frequency(&mut self, mod_frequency: f32) {
+        self.mod_frequency = mod_frequency;
+    }
+
+    /// Set the modulation depth, between 0 (a plain sine wave) and 1.
+    pub fn set_depth(&mut self, depth: f32) {
+        self.depth = depth.clamp(0.0, 1.0);
+    }
+}
+
+impl Source for AmSynth {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+    fn channels(&self) -> u16 {
+        1
+    }
+    fn sample_rate(&self) -> u32 {
+        SAMPLE_RATE
+    }
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+impl Iterator for AmSynth {
+    type Item = f32;
+    fn next(&mut self) -> Option<Self::Item> {
+        let carrier = (self.carrier_phase * 2.0 * std::f32::consts::PI).sin();
+        let modulator = (self.modulator_phase * 2.0 * std::f32::consts::PI).sin();
+        let result = carrier * (1.0 + self.depth * modulator) / (1.0 + self.depth);
+        self.carrier_phase = (self.carrier_phase + self.frequency / SAMPLE_RATE as f32) % 1.0;
+        self.modulator_phase =
+            (self.modulator_phase + self.mod_frequency / SAMPLE_RATE as f32) % 1.0;
+        Some(result)
+    }
+}
+
+impl AdjustableSource for AmSynth {
+    fn set_frequency(&mut self, frequency: f32) {
+        self.frequency = frequency;
+    }
+
+    fn frequency(&self) -> Option<f32> {
+        Some(self.frequency)
+    }
+
+    fn retrigger(&mut self) {
+        self.carrier_phase = 0.0;
+        self.modulator_phase = 0.0;
+    }
+}
+
+/// The built-in waveforms, for picking a source at runtime with `make`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Waveform {
+    Square,
+    Sawtooth,
+    Triangle,
+    Sine,
+    WhiteNoise,
+    SemiTriangle,
+    SemiSine,
+    StepSquare,
+    PluckedString,
+    BandLimitedSquare,
+    BandLimitedSaw,
+}
+
+impl Waveform {
+    /// Every waveform, e.g. for listing them in a menu.
+    pub const ALL: [Waveform; 11] = [
+        Waveform::Square,
+        Waveform::Sawtooth,
+        Waveform::Triangle,
+        Waveform::Sine,
+        Waveform::WhiteNoise,
+        Waveform::SemiTriangle,
+        Waveform::SemiSine,
+        Waveform::StepSquare,
+        Waveform::PluckedString,
+        Waveform::BandLimitedSquare,
+        Waveform::BandLimitedSaw,
+    ];
+}
+
+/// Create a boxed source of the given waveform and frequency. Add it to a Channels with `ChannelsBuilder::add_boxed`.
+pub fn make(kind: Waveform, frequency: f32) -> Box<dyn AdjustableSource<Item = f32> + Send> {
+    match kind {
+        Waveform::Square => Box::new(SquareWave::new(frequency)),
+        Waveform::Sawtooth => Box::new(SawtoothWave::new(frequency)),
+        Waveform::Triangle => Box::new(TriangleWave::new(frequency)),
+        Waveform::Sine => Box::new(SineWave::new(frequency)),
+        Waveform::WhiteNoise => Box::new(WhiteNoise::new()),
+        Waveform::SemiTriangle => Box::new(SemiTriangle::new(frequency)),
+        Waveform::SemiSine => Box::new(SemiSine::new(frequency)),
+        Waveform::StepSquare => Box::new(StepSquare::new(frequency)),
+        Waveform::PluckedString => Box::new(PluckedString::new(frequency)),
+        Waveform::BandLimitedSquare => Box::new(BandLimitedSquare::new(frequency)),
+        Waveform::BandLimitedSaw => Box::new(BandLimitedSaw::new(frequency)),
+    }
+}
+
+/// A feed-forward compressor, which evens out the loudness of a source (typically a whole mix).
+/// It follows the level of the source, and when that level goes over the threshold, the part above the threshold is divided by the ratio.
+/// The attack and release control how fast the compressor reacts to the level going up and down, respectively.
+pub struct Compressor<S>
+where
+    S: Source<Item = f32>,
+{
+    source: S,
+    threshold: f32,
+    ratio: f32,
+    attack: f32,
+    release: f32,
+    envelope: f32,
+}
+
+impl<S> Compressor<S>
+where
+    S: Source<Item = f32>,
+{
+    /// Create a new compressor over the given source. The threshold is a linear amplitude, and the ratio must be at least 1.
+    pub fn new(
+        source: S,
+        threshold: f32,
+        ratio: f32,
+        attack: Duration,
+        release: Duration,
+    ) -> Compressor<S> {
+        let mut compressor = Compressor {
+            source,
+            threshold,
+            ratio: ratio.max(1.0),
+            attack: 0.0,
+            release: 0.0,
+            envelope: 0.0,
+        };
+        compressor.set_attack(attack);
+        compressor.set_release(release);
+        compressor
+    }
+
+    fn smoothing(&self, time: Duration) -> f32 {
+        (-1.0 / (time.as_secs_f32() * self.source.sample_rate() as f32)).exp()
+    }
+
+    /// Set the threshold, as a linear amplitude.
+    pub fn set_threshold(&mut self, threshold: f32) {
+        self.threshold = threshold;
+    }
+
+    /// Set the ratio. Clamped to at least 1, which means no compression.
+    pub fn set_ratio(&mut self, ratio: f32) {
+        self.ratio = ratio.max(1.0);
+    }
+
+    /// Set how quickly the compressor reacts to the level going up.
+    pub fn set_attack(&mut self, attack: Duration) {
+        self.attack = self.smoothing(attack);
+    }
+
+    /// Set how quickly the compressor recovers when the level goes back down.
+    pub fn set_release(&mut self, release: Duration) {
+        self.release = self.smoothing(release);
+    }
+}
+
+impl<S> Source for Compressor<S>
+where
+    S: Source<Item = f32>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        self.source.current_frame_len()
+    }
+    fn channels(&self) -> u16 {
+        self.source.channels()
+    }
+    fn sample_rate(&self) -> u32 {
+        self.source.sample_rate()
+    }
+    fn total_duration(&self) -> Option<Duration> {
+        self.source.total_duration()
+    }
+}
+
+impl<S> Iterator for Compressor<S>
+where
+    S: Source<Item = f32>,
+{
+    type Item = f32;
+    fn next(&mut self) -> Option<Self::Item> {
+        let sample = self.source.next()?;
+        let level = sample.abs();
+        let smoothing = if level > self.envelope {
+            self.attack
+        } else {
+            self.release
+        };
+        self.envelope = smoothing * self.envelope + (1.0 - smoothing) * level;
+        if self.envelope > self.threshold {
+            let compressed = self.threshold + (self.envelope - self.threshold) / self.ratio;
+            Some(sample * compressed / self.envelope)
+        } else {
+            Some(sample)
+        }
+    }
+}
+
+impl<S> AdjustableSource for Compressor<S>
+where
+    S: AdjustableSource<Item = f32>,
+{
+    fn set_frequency(&mut self, frequency: f32) {
+        self.source.set_frequency(frequency);
+    }
+
+    fn frequency(&self) -> Option<f32> {
+        self.source.frequency()
+    }
+
+    fn retrigger(&mut self) {
+        self.source.retrigger();
+    }
+}
+
+/// A wrapper which snaps every frequency change to the nearest note of a musical scale before giving it to the inner source.
+/// The scale is given as semitone offsets from the root, within an octave (e.g. `vec![0, 2, 4, 7, 9]` for a major pentatonic), and repeats every octave.
+pub struct ScaleQuantizer<S>
+where
+    S: AdjustableSource<Item = f32>,
+{
+    source: S,
+    root: f32,
+    scale: Vec<i32>,
+}
+
+impl<S> ScaleQuantizer<S>
+where
+    S: AdjustableSource<Item = f32>,
+{
+    /// Create a new quantizer over the given source, with the scale starting at the root frequency.
+    pub fn new(source: S, root: f32, scale: Vec<i32>) -> ScaleQuantizer<S> {
+        ScaleQuantizer {
+            source,
+            root,
+            scale,
+        }
+    }
+
+    /// Set the scale, as semitone offsets from the root. An empty scale lets every frequency through unchanged.
+    pub fn set_scale(&mut self, scale: Vec<i32>) {
+        self.scale = scale;
+    }
+
+    /// Set the root frequency of the scale.
+    pub fn set_root(&mut self, root: f32) {
+        self.root = root;
+    }
+
+    /// Returns the frequency of the note of the scale closest to the given frequency.
+    pub fn quantize(&self, frequency: f32) -> f32 {
+        let semitones = 12.0 * (frequency / self.root).log2();
+        let octave = (semitones / 12.0).floor() as i32;
+        let closest = (octave - 1..=octave + 1)
+            .flat_map(|octave| self.scale.iter().map(move |offset| octave * 12 + offset))
+            .min_by(|a, b| {
+                (*a as f32 - semitones)
+                    .abs()
+                    .total_cmp(&(*b as f32 - semitones).abs())
+            });
+        match closest {
+            Some(closest) => self.root * 2.0_f32.powf(closest as f32 / 12.0),
+            None => frequency,
+        }
+    }
+}
+
+impl<S> Source for ScaleQuantizer<S>
+where
+    S: AdjustableSource<Item = f32>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        self.source.current_frame_len()
+    }
+    fn channels(&self) -> u16 {
+        self.source.channels()
+    }
+    fn sample_rate(&self) -> u32 {
+        self.source.sample_rate()
+    }
+    fn total_duration(&self) -> Option<Duration> {
+        self.source.total_duration()
+    }
+}
+
+impl<S> Iterator for ScaleQuantizer<S>
+where
+    S: AdjustableSource<Item = f32>,
+{
+    type Item = f32;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.source.next()
+    }
+}
+
+impl<S> AdjustableSource for ScaleQuantizer<S>
+where
+    S: AdjustableSource<Item = f32>,
+{
+    fn set_frequency(&mut self, frequency: f32) {
+        let frequency = self.quantize(frequency);
+        self.source.set_frequency(frequency);
+    }
+
+    fn frequency(&self) -> Option<f32> {
+        self.source.frequency()
+    }
+
+    fn retrigger(&mut self) {
+        self.source.retrigger();
+    }
+}
+
+/// The frequency at which a SampleSource plays at its original speed.
+const SAMPLE_BASE_FREQUENCY: f32 = 440.0;
+
+#[derive(Clone, Debug, PartialEq)]
+/// A source which plays a recorded sample once, and then stays silent until it is retriggered.
+/// Its frequency controls the playback speed: at 440Hz, the sample plays at its original speed, and at 880Hz, it plays twice as fast (and an octave higher).
+pub struct SampleSource {
+    samples: Vec<f32>,
+    position: f32,
+    frequency: f32,
+}
+
+impl SampleSource {
+    /// Create a new sample source from mono samples at the library's sample rate.
+    pub fn new(samples: Vec<f32>) -> SampleSource {
+        SampleSource {
+            samples,
+            position: 0.0,
+            frequency: SAMPLE_BASE_FREQUENCY,
+        }
+    }
+
+    /// Create a new sample source from WAV data, e.g. a file. It is converted to mono at the library's sample rate.
+    pub fn from_wav<R>(data: R) -> Result<SampleSource, DecoderError>
+    where
+        R: Read + Seek + Send + Sync + 'static,
+    {
+        let decoder = Decoder::new_wav(data)?;
+        let samples = UniformSourceIterator::<_, f32>::new(decoder, 1, SAMPLE_RATE).collect();
+        Ok(SampleSource::new(samples))
+    }
+
+    /// Scales the sample so that its loudest point is at full volume. A completely silent sample is left as is.
+    pub fn normalized(mut self) -> Self {
+        let peak = self
+            .samples
+            .iter()
+            .fold(0.0_f32, |peak, x| peak.max(x.abs()));
+        if peak > 0.0 {
+            for sample in self.samples.iter_mut() {
+                *sample /= peak;
+            }
+        }
+        self
+    }
+
+    /// Removes the quiet parts (below the threshold) at the start and end of the sample, so that it starts right away.
+    /// A sample which is entirely below the threshold ends up empty.
+    pub fn trim_silence(mut self, threshold: f32) -> Self {
+        let loud = |x: &f32| x.abs() >= threshold;
+        match self.samples.iter().position(loud) {
+            Some(start) => {
+                let end = self.samples.iter().rposition(loud).unwrap() + 1;
+                self.samples.truncate(end);
+                self.samples.drain(..start);
+            }
+            None => self.samples.clear(),
+        }
+        self
+    }
+
+    /// The samples that are played.
+    pub fn samples(&self) -> &[f32] {
+        &self.samples
+    }
+}
+
+impl Source for SampleSource {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+    fn channels(&self) -> u16 {
+        1
+    }
+    fn sample_rate(&self) -> u32 {
+        SAMPLE_RATE
+    }
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+impl Iterator for SampleSource {
+    type Item = f32;
+    fn next(&mut self) -> Option<Self::Item> {
+        let index = self.position as usize;
+        let result = match (self.samples.get(index), self.samples.get(index + 1)) {
+            (Some(current), Some(next)) => {
+                let t = self.position.fract();
+                current * (1.0 - t) + next * t
+            }
+            (Some(current), None) => *current,
+            _ => return Some(0.0),
+        };
+        self.position += self.frequency / SAMPLE_BASE_FREQUENCY;
+        Some(result)
+    }
+}
+
+impl AdjustableSource for SampleSource {
+    fn set_frequency(&mut self, frequency: f32) {
+        self.frequency = frequency;
+    }
+
+    fn frequency(&self) -> Option<f32> {
+        Some(self.frequency)
+    }
+
+    /// Plays the sample again from the start.
+    fn retrigger(&mut self) {
+        self.position = 0.0;
+    }
+}
+
+/// A wrapper which gates its inner source on and off following a step pattern, e.g. for drum machines.
+/// On `true` steps, the inner source plays (and is retriggered at the start of the step); on `false` steps, it is silent.
+pub struct StepGate<S>
+where
+    S: AdjustableSource<Item = f32>,
+{
+    source: S,
+    pattern: Vec<bool>,
+    rate: f32,
+    position: f32,
+    current_step: Option<usize>,
+}
+
+impl<S> StepGate<S>
+where
+    S: AdjustableSource<Item = f32>,
+{
+    /// Create a new step gate over the given source, going through the pattern at the given rate (in steps per second).
+    pub fn new(source: S, pattern: Vec<bool>, rate: f32) -> StepGate<S> {
+        StepGate {
+            source,
+            pattern,
+            rate,
+            position: 0.0,
+            current_step: None,
+        }
+    }
+
+    /// Set the pattern, and start it over from the first step. An empty pattern is always silent.
+    pub fn set_pattern(&mut self, pattern: Vec<bool>) {
+        self.pattern = pattern;
+        self.position = 0.0;
+        self.current_step = None;
+    }
+
+    /// Set the rate (in steps per second) of the pattern.
+    pub fn set_rate(&mut self, rate: f32) {
+        self.rate = rate;
+    }
+}
+
+impl<S> Source for StepGate<S>
+where
+    S: AdjustableSource<Item = f32>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        self.source.current_frame_len()
+    }
+    fn channels(&self) -> u16 {
+        self.source.channels()
+    }
+    fn sample_rate(&self) -> u32 {
+        self.source.sample_rate()
+    }
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+impl<S> Iterator for StepGate<S>
+where
+    S: AdjustableSource<Item = f32>,
+{
+    type Item = f32;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pattern.is_empty() {
+            return Some(0.0);
+        }
+        let step = self.position as usize % self.pattern.len();
+        let gate = self.pattern[step];
+        if gate && self.current_step != Some(step) {
+            self.source.retrigger();
+        }
+        self.current_step = Some(step);
+        self.position =
+            (self.position + self.rate / SAMPLE_RATE as f32) % self.pattern.len() as f32;
+        let sample = self.source.next()?;
+        Some(if gate { sample } else { 0.0 })
+    }
+}
+
+impl<S> AdjustableSource for StepGate<S>
+where
+    S: AdjustableSource<Item = f32>,
+{
+    fn set_frequency(&mut self, frequency: f32) {
+        self.source.set_frequency(frequency);
+    }
+
+    fn frequency(&self) -> Option<f32> {
+        self.source.frequency()
+    }
+
+    /// Starts the pattern over from the first step.
+    fn retrigger(&mut self) {
+        self.position = 0.0;
+        self.current_step = None;
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+/// The current stage of an ADSR envelope.
+enum EnvelopeStage {
+    Idle,
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+/// The shape of each stage of an envelope, i.e. how it moves from one level to the next.
+pub enum CurveShape {
+    /// Moves at a constant speed.
+    Linear,
+    /// Moves quickly at first, then slows down as it nears the next level, like a natural decay.
+    Exponential,
+    /// Moves slowly at first, then speeds up towards the next level.
+    Logarithmic,
+}
+
+/// How sharply the exponential and logarithmic curves bend.
+const CURVE_STEEPNESS: f32 = 5.0;
+
+impl CurveShape {
+    /// Maps the progress through a stage (between 0 and 1) to how far between the levels the envelope is (also between 0 and 1).
+    fn apply(self, progress: f32) -> f32 {
+        match self {
+            CurveShape::Linear => progress,
+            CurveShape::Exponential => {
+                (1.0 - (-CURVE_STEEPNESS * progress).exp()) / (1.0 - (-CURVE_STEEPNESS).exp())
+            }
+            CurveShape::Logarithmic => {
+                ((CURVE_STEEPNESS * progress).exp() - 1.0) / (CURVE_STEEPNESS.exp() - 1.0)
+            }
+        }
+    }
+}
+
+/// A wrapper which low-passes its inner source, with the cutoff swept by an ADSR envelope, for the classic synth "wow".
+/// When triggered, the cutoff rises from the base cutoff to the peak cutoff during the attack, falls to the sustain level during the decay,
+/// stays there until released, and then falls back to the base cutoff during the release.
+/// The sweep is exponential, so that it sounds even across octaves. Each stage is linear by default, see `with_curve`.
+pub struct FilterEnvelope<S>
+where
+    S: AdjustableSource<Item = f32>,
+{
+    source: S,
+    base_cutoff: f32,
+    peak_cutoff: f32,
+    attack: Duration,
+    decay: Duration,
+    sustain: f32,
+    release: Duration,
+    curve: CurveShape,
+    stage: EnvelopeStage,
+    level: f32,
+    stage_start: f32,
+    stage_progress: f32,
+    filtered: f32,
+}
+
+impl<S> FilterEnvelope<S>
+where
+    S: AdjustableSource<Item = f32>,
+{
+    /// Create a new filter envelope over the given source. The cutoffs are in Hz, and the sustain level is between 0 (the base cutoff) and 1 (the peak cutoff).
+    /// The envelope starts idle, at the base cutoff, until it is triggered.
+    pub fn new(
+        source: S,
+        base_cutoff: f32,
+        peak_cutoff: f32,
+        attack: Duration,
+        decay: Duration,
+        sustain: f32,
+        release: Duration,
+    ) -> FilterEnvelope<S> {
+        FilterEnvelope {
+            source,
+            base_cutoff,
+            peak_cutoff,
+            attack,
+            decay,
+            sustain: sustain.clamp(0.0, 1.0),
+            release,
+            curve: CurveShape::Linear,
+            stage: EnvelopeStage::Idle,
+            level: 0.0,
+            stage_start: 0.0,
+            stage_progress: 0.0,
+            filtered: 0.0,
+        }
+    }
+
+    /// Set the shape of every stage of the envelope.
+    pub fn with_curve(mut self, shape: CurveShape) -> Self {
+        self.curve = shape;
+        self
+    }
+
+    /// Start the attack, from wherever the envelope currently is.
+    pub fn trigger(&mut self) {
+        self.start_stage(EnvelopeStage::Attack);
+    }
+
+    /// Start the release, from wherever the envelope currently is.
+    pub fn release(&mut self) {
+        self.start_stage(EnvelopeStage::Release);
+    }
+
+    fn start_stage(&mut self, stage: EnvelopeStage) {
+        self.stage = stage;
+        self.stage_start = self.level;
+        self.stage_progress = 0.0;
+    }
+
+    /// The current cutoff of the low-pass, in Hz.
+    pub fn cutoff(&self) -> f32 {
+        self.base_cutoff * (self.peak_cutoff / self.base_cutoff).powf(self.level)
+    }
+
+    fn samples(time: Duration) -> f32 {
+        (time.as_secs_f32() * SAMPLE_RATE as f32).max(1.0)
+    }
+
+    /// Moves the envelope forward by one sample.
+    fn advance(&mut self) {
+        match self.stage {
+            EnvelopeStage::Idle | EnvelopeStage::Sustain => {}
+            EnvelopeStage::Attack => {
+                // An attack from partway up only covers the rest of the way, at the same speed.
+                let length = (Self::samples(self.attack) * (1.0 - self.stage_start)).max(1.0);
+                self.advance_stage(length, 1.0, EnvelopeStage::Decay);
+            }
+            EnvelopeStage::Decay => {
+                self.advance_stage(
+                    Self::samples(self.decay),
+                    self.sustain,
+                    EnvelopeStage::Sustain,
+                );
+            }
+            EnvelopeStage::Release => {
+                self.advance_stage(Self::samples(self.release), 0.0, EnvelopeStage::Idle);
+            }
+        }
+    }
+
+    /// Moves the current stage, lasting `length` samples, towards the `target` level, going to the `next` stage once there.
+    fn advance_stage(&mut self, length: f32, target: f32, next: EnvelopeStage) {
+        self.stage_progress += 1.0 / length;
+        if self.stage_progress >= 1.0 {
+            self.level = target;
+            self.start_stage(next);
+        } else {
+            self.level = self.stage_start
+                + (target - self.stage_start) * self.curve.apply(self.stage_progress);
+        }
+    }
+}
+
+impl<S> Source for FilterEnvelope<S>
+where
+    S: AdjustableSource<Item = f32>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        self.source.current_frame_len()
+    }
+    fn channels(&self) -> u16 {
+        self.source.channels()
+    }
+    fn sample_rate(&self) -> u32 {
+        self.source.sample_rate()
+    }
+    fn total_duration(&self) -> Option<Duration> {
+        self.source.total_duration()
+    }
+}
+
+impl<S> Iterator for FilterEnvelope<S>
+where
+    S: AdjustableSource<Item = f32>,
+{
+    type Item = f32;
+    fn next(&mut self) -> Option<Self::Item> {
+        let sample = self.source.next()?;
+        let alpha = 1.0 - (-2.0 * std::f32::consts::PI * self.cutoff() / SAMPLE_RATE as f32).exp();
+        self.filtered += alpha * (sample - self.filtered);
+        self.advance();
+        Some(self.filtered)
+    }
+}
+
+impl<S> AdjustableSource for FilterEnvelope<S>
+where
+    S: AdjustableSource<Item = f32>,
+{
+    fn set_frequency(&mut self, frequency: f32) {
+        self.source.set_frequency(frequency);
+    }
+
+    fn frequency(&self) -> Option<f32> {
+        self.source.frequency()
+    }
+
+    /// Restarts the inner source, and triggers the envelope from the base cutoff.
+    fn retrigger(&mut self) {
+        self.source.retrigger();
+        self.level = 0.0;
+        self.trigger();
+    }
+}
+
+/// A source playing samples pushed from elsewhere (e.g. another thread), through its `StreamSourceProducer`.
+/// When it runs out of samples (an underrun), it plays silence until more are pushed. The frequency can't be adjusted.
+pub struct StreamSource {
+    samples: Arc<Mutex<VecDeque<f32>>>,
+}
+
+/// The handle used to push samples into a `StreamSource`.
+#[derive(Clone)]
+pub struct StreamSourceProducer {
+    samples: Arc<Mutex<VecDeque<f32>>>,
+}
+
+impl StreamSource {
+    /// Create a new, empty stream source, along with the handle to push samples into it.
+    pub fn new() -> (StreamSource, StreamSourceProducer) {
+        let samples = Arc::new(Mutex::new(VecDeque::new()));
+        (
+            StreamSource {
+                samples: samples.clone(),
+            },
+            StreamSourceProducer { samples },
+        )
+    }
+}
+
+impl StreamSourceProducer {
+    /// Queue samples to be played after the ones already queued. They should be at the sample rate of the other sources.
+    pub fn push(&mut self, samples: &[f32]) {
+        self.samples.lock().unwrap().extend(samples);
+    }
+
+    /// The amount of samples queued but not played yet, e.g. to know when to generate more.
+    pub fn queued(&self) -> usize {
+        self.samples.lock().unwrap().len()
+    }
+}
+
+impl Source for StreamSource {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+    fn channels(&self) -> u16 {
+        1
+    }
+    fn sample_rate(&self) -> u32 {
+        SAMPLE_RATE
+    }
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+impl Iterator for StreamSource {
+    type Item = f32;
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(self.samples.lock().unwrap().pop_front().unwrap_or(0.0))
+    }
+}
+
+impl AdjustableSource for StreamSource {
+    fn set_frequency(&mut self, _frequency: f32) {}
+}
+
+/// The delays of the comb and allpass filters of the reverb, in samples at 44.1kHz, from Freeverb.
+const REVERB_COMB_TUNINGS: [usize; 4] = [1116, 1188, 1277, 1356];
+const REVERB_ALLPASS_TUNINGS: [usize; 2] = [556, 441];
+/// How much longer the delays of the right channel of a stereo reverb are, in samples at 44.1kHz, from Freeverb.
+const REVERB_STEREO_SPREAD: usize = 23;
+
+/// A feedback comb filter with a low-pass in its loop, the building block of the reverb's tail.
+#[derive(Clone, Debug, PartialEq)]
+struct Comb {
+    buffer: Vec<f32>,
+    index: usize,
+    filtered: f32,
+}
+
+impl Comb {
+    fn process(&mut self, input: f32, feedback: f32, damping: f32) -> f32 {
+        let output = self.buffer[self.index];
+        self.filtered = output * (1.0 - damping) + self.filtered * damping;
+        self.buffer[self.index] = input + self.filtered * feedback;
+        self.index = (self.index + 1) % self.buffer.len();
+        output
+    }
+}
+
+/// An allpass filter, which diffuses the echoes of the combs without coloring them.
+#[derive(Clone, Debug, PartialEq)]
+struct Allpass {
+    buffer: Vec<f32>,
+    index: usize,
+}
+
+impl Allpass {
+    fn process(&mut self, input: f32) -> f32 {
+        let delayed = self.buffer[self.index];
+        self.buffer[self.index] = input + delayed * 0.5;
+        self.index = (self.index + 1) % self.buffer.len();
+        delayed - input
+    }
+}
+
+/// The comb and allpass filters making up one channel of a reverb.
+#[derive(Clone, Debug, PartialEq)]
+struct ReverbTank {
+    combs: Vec<Comb>,
+    allpasses: Vec<Allpass>,
+}
+
+impl ReverbTank {
+    /// Create the filters for the given sample rate, with their delays lengthened by `spread` samples (at 44.1kHz).
+    fn new(sample_rate: u32, spread: usize) -> ReverbTank {
+        let scale = |tuning: usize| ((tuning + spread) * sample_rate as usize / 44100).max(1);
+        ReverbTank {
+            combs: REVERB_COMB_TUNINGS
+                .iter()
+                .map(|tuning| Comb {
+                    buffer: vec![0.0; scale(*tuning)],
+                    index: 0,
+                    filtered: 0.0,
+                })
+                .collect(),
+            allpasses: REVERB_ALLPASS_TUNINGS
+                .iter()
+                .map(|tuning| Allpass {
+                    buffer: vec![0.0; scale(*tuning)],
+                    index: 0,
+                })
+                .collect(),
+        }
+    }
+
+    /// Feeds a sample through the filters, returning the reverberated sound.
+    fn process(&mut self, input: f32, room_size: f32, damping: f32) -> f32 {
+        // The scaling and feedback ranges are the ones from Freeverb, which keep the combs stable.
+        let feedback = room_size * 0.28 + 0.7;
+        let damping = damping * 0.4;
+        let mut wet: f32 = self
+            .combs
+            .iter_mut()
+            .map(|comb| comb.process(input * 0.015, feedback, damping))
+            .sum();
+        for allpass in &mut self.allpasses {
+            wet = allpass.process(wet);
+        }
+        wet
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+/// A room reverb over a mono source, usually the whole mix, as a simplified Freeverb: parallel comb filters followed by allpass filters.
+/// The room size (between 0 and 1) sets how long the tail lasts, and the damping (between 0 and 1) how quickly its highs die out.
+/// The wet and dry levels are the volumes of the reverberated and original sound. The tail keeps playing after the source ends.
+pub struct Reverb<S>
+where
+    S: Source<Item = f32>,
+{
+    source: S,
+    tank: ReverbTank,
+    room_size: f32,
+    damping: f32,
+    wet: f32,
+    dry: f32,
+}
+
+impl<S> Reverb<S>
+where
+    S: Source<Item = f32>,
+{
+    /// Create a new reverb over the given mono source. Panics if the source isn't mono.
+    pub fn new(source: S, room_size: f32, damping: f32, wet: f32, dry: f32) -> Reverb<S> {
+        if source.channels() != 1 {
+            panic!("The reverb needs a mono source!");
+        }
+        Reverb {
+            tank: ReverbTank::new(source.sample_rate(), 0),
+            source,
+            room_size: room_size.clamp(0.0, 1.0),
+            damping: damping.clamp(0.0, 1.0),
+            wet,
+            dry,
+        }
+    }
+
+    /// Set the size of the room, between 0 and 1. Bigger rooms have longer tails.
+    pub fn set_room_size(&mut self, room_size: f32) {
+        self.room_size = room_size.clamp(0.0, 1.0);
+    }
+
+    /// Set the damping, between 0 and 1. More damping makes the tail darker.
+    pub fn set_damping(&mut self, damping: f32) {
+        self.damping = damping.clamp(0.0, 1.0);
+    }
+
+    /// Set the volume of the reverberated sound.
+    pub fn set_wet(&mut self, wet: f32) {
+        self.wet = wet;
+    }
+
+    /// Set the volume of the original sound.
+    pub fn set_dry(&mut self, dry: f32) {
+        self.dry = dry;
+    }
+}
+
+impl<S> Source for Reverb<S>
+where
+    S: Source<Item = f32>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+    fn channels(&self) -> u16 {
+        1
+    }
+    fn sample_rate(&self) -> u32 {
+        self.source.sample_rate()
+    }
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+impl<S> Iterator for Reverb<S>
+where
+    S: Source<Item = f32>,
+{
+    type Item = f32;
+    fn next(&mut self) -> Option<Self::Item> {
+        let input = self.source.next().unwrap_or(0.0);
+        let wet = self.tank.process(input, self.room_size, self.damping);
+        Some(wet * self.wet + input * self.dry)
+    }
+}
+
+impl<S> AdjustableSource for Reverb<S>
+where
+    S: AdjustableSource<Item = f32>,
+{
+    fn set_frequency(&mut self, frequency: f32) {
+        self.source.set_frequency(frequency);
+    }
+
+    fn frequency(&self) -> Option<f32> {
+        self.source.frequency()
+    }
+
+    fn retrigger(&mut self) {
+        self.source.retrigger();
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+/// Like `Reverb`, but with a stereo tail from a mono source: the left and right channels have slightly different delays, which makes the room sound wide.
+/// The width (between 0 and 1) sets how much the two tails are kept apart; at 0 both channels get the same mix of them.
+/// The dry sound stays centered. The output is interleaved stereo, so it can't go in `Channels`, but it can wrap their mix.
+pub struct StereoReverb<S>
+where
+    S: Source<Item = f32>,
+{
+    source: S,
+    left: ReverbTank,
+    right: ReverbTank,
+    room_size: f32,
+    damping: f32,
+    wet: f32,
+    dry: f32,
+    width: f32,
+    pending_right: Option<f32>,
+}
+
+impl<S> StereoReverb<S>
+where
+    S: Source<Item = f32>,
+{
+    /// Create a new stereo reverb over the given mono source. Panics if the source isn't mono.
+    pub fn new(
+        source: S,
+        room_size: f32,
+        damping: f32,
+        wet: f32,
+        dry: f32,
+        width: f32,
+    ) -> StereoReverb<S> {
+        if source.channels() != 1 {
+            panic!("The reverb needs a mono source!");
+        }
+        StereoReverb {
+            left: ReverbTank::new(source.sample_rate(), 0),
+            right: ReverbTank::new(source.sample_rate(), REVERB_STEREO_SPREAD),
+            source,
+            room_size: room_size.clamp(0.0, 1.0),
+            damping: damping.clamp(0.0, 1.0),
+            wet,
+            dry,
+            width: width.clamp(0.0, 1.0),
+            pending_right: None,
+        }
+    }
+
+    /// Set the size of the room, between 0 and 1. Bigger rooms have longer tails.
+    pub fn set_room_size(&mut self, room_size: f32) {
+        self.room_size = room_size.clamp(0.0, 1.0);
+    }
+
+    /// Set the damping, between 0 and 1. More damping makes the tail darker.
+    pub fn set_damping(&mut self, damping: f32) {
+        self.damping = damping.clamp(0.0, 1.0);
+    }
+
+    /// Set the volume of the reverberated sound.
+    pub fn set_wet(&mut self, wet: f32) {
+        self.wet = wet;
+    }
+
+    /// Set the volume of the original sound.
+    pub fn set_dry(&mut self, dry: f32) {
+        self.dry = dry;
+    }
+
+    /// Set the stereo width of the tail, between 0 (mono) and 1 (fully apart).
+    pub fn set_width(&mut self, width: f32) {
+        self.width = width.clamp(0.0, 1.0);
+    }
+}
+
+impl<S> Source for StereoReverb<S>
+where
+    S: Source<Item = f32>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+    fn channels(&self) -> u16 {
+        2
+    }
+    fn sample_rate(&self) -> u32 {
+        self.source.sample_rate()
+    }
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+impl<S> Iterator for StereoReverb<S>
+where
+    S: Source<Item = f32>,
+{
+    type Item = f32;
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(right) = self.pending_right.take() {
+            return Some(right);
+        }
+        let input = self.source.next().unwrap_or(0.0);
+        let left = self.left.process(input, self.room_size, self.damping);
+        let right = self.right.process(input, self.room_size, self.damping);
+        // Each channel gets mostly its own tail, and the rest of the other one as the width shrinks.
+        let own = self.wet * (self.width / 2.0 + 0.5);
+        let other = self.wet * (1.0 - self.width) / 2.0;
+        self.pending_right = Some(right * own + left * other + input * self.dry);
+        Some(left * own + right * other + input * self.dry)
+    }
+}
+
+/// How long each click of the metronome lasts, in seconds.
+const CLICK_LENGTH: f32 = 0.03;
+
+#[derive(Clone, Debug, PartialEq)]
+/// A click track, which plays a short high blip on the first beat of each bar, a lower one on the other beats, and silence in between.
+/// Its frequency can't be adjusted, and retriggering it restarts the bar.
+pub struct Metronome {
+    bpm: f32,
+    beats_per_bar: u32,
+    beat: u32,
+    elapsed: f32,
+}
+
+impl Metronome {
+    /// Create a new metronome with the given tempo, in beats per minute, and amount of beats per bar. Starts on a downbeat.
+    pub fn new(bpm: f32, beats_per_bar: u32) -> Metronome {
+        Metronome {
+            bpm,
+            beats_per_bar: beats_per_bar.max(1),
+            beat: 0,
+            elapsed: 0.0,
+        }
+    }
+
+    /// Change the tempo, in beats per minute. The current beat keeps going at the new tempo.
+    pub fn set_bpm(&mut self, bpm: f32) {
+        self.bpm = bpm;
+    }
+
+    /// The tempo, in beats per minute.
+    pub fn bpm(&self) -> f32 {
+        self.bpm
+    }
+}
+
+impl Source for Metronome {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+    fn channels(&self) -> u16 {
+        1
+    }
+    fn sample_rate(&self) -> u32 {
+        SAMPLE_RATE
+    }
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+impl Iterator for Metronome {
+    type Item = f32;
+    fn next(&mut self) -> Option<Self::Item> {
+        let samples_per_beat = SAMPLE_RATE as f32 * 60.0 / self.bpm;
+        if self.elapsed >= samples_per_beat {
+            self.elapsed -= samples_per_beat;
+            self.beat = (self.beat + 1) % self.beats_per_bar;
+        }
+        let time = self.elapsed / SAMPLE_RATE as f32;
+        self.elapsed += 1.0;
+        if time >= CLICK_LENGTH {
+            return Some(0.0);
+        }
+        let frequency = if self.beat == 0 { 1760.0 } else { 880.0 };
+        Some((time * frequency * std::f32::consts::TAU).sin() * (1.0 - time / CLICK_LENGTH))
+    }
+}
+
+impl AdjustableSource for Metronome {
+    fn set_frequency(&mut self, _frequency: f32) {}
+
+    fn retrigger(&mut self) {
+        self.beat = 0;
+        self.elapsed = 0.0;
+    }
+}
+
+/// A wrapper which plays its inner source unchanged, while keeping its most recent samples for a `TapHandle` to read, e.g. for an oscilloscope.
+pub struct Tap<S>
+where
+    S: AdjustableSource<Item = f32>,
+{
+    source: S,
+    samples: Arc<Mutex<VecDeque<f32>>>,
+    length: usize,
+}
+
+/// The handle used to read the samples recently played by a `Tap`.
+#[derive(Clone)]
+pub struct TapHandle {
+    samples: Arc<Mutex<VecDeque<f32>>>,
+}
+
+impl<S> Tap<S>
+where
+    S: AdjustableSource<Item = f32>,
+{
+    /// Create a new tap over the given source, keeping its last `length` samples, along with the handle to read them.
+    pub fn new(source: S, length: usize) -> (Tap<S>, TapHandle) {
+        let samples = Arc::new(Mutex::new(VecDeque::with_capacity(length)));
+        (
+            Tap {
+                source,
+                samples: samples.clone(),
+                length,
+            },
+            TapHandle { samples },
+        )
+    }
+}
+
+impl TapHandle {
+    /// The last `n` samples played, oldest first. There are fewer if fewer were kept (or played) so far.
+    pub fn latest(&self, n: usize) -> Vec<f32> {
+        let samples = self.samples.lock().unwrap();
+        samples
+            .iter()
+            .skip(samples.len().saturating_sub(n))
+            .copied()
+            .collect()
+    }
+}
+
+impl<S> Source for Tap<S>
+where
+    S: AdjustableSource<Item = f32>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        self.source.current_frame_len()
+    }
+    fn channels(&self) -> u16 {
+        self.source.channels()
+    }
+    fn sample_rate(&self) -> u32 {
+        self.source.sample_rate()
+    }
+    fn total_duration(&self) -> Option<Duration> {
+        self.source.total_duration()
+    }
+}
+
+impl<S> Iterator for Tap<S>
+where
+    S: AdjustableSource<Item = f32>,
+{
+    type Item = f32;
+    fn next(&mut self) -> Option<Self::Item> {
+        let sample = self.source.next()?;
+        if self.length > 0 {
+            let mut samples = self.samples.lock().unwrap();
+            if samples.len() == self.length {
+                samples.pop_front();
+            }
+            samples.push_back(sample);
+        }
+        Some(sample)
+    }
+}
+
+impl<S> AdjustableSource for Tap<S>
+where
+    S: AdjustableSource<Item = f32>,
+{
+    fn set_frequency(&mut self, frequency: f32) {
+        self.source.set_frequency(frequency);
+    }
+
+    fn frequency(&self) -> Option<f32> {
+        self.source.frequency()
+    }
+
+    fn retrigger(&mut self) {
+        self.source.retrigger();
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+/// A wavefolder, which amplifies its inner source by a gain and folds the parts beyond -1 and 1 back into range, as many times as needed.
+/// Higher gains fold more, giving a brighter sound. A gain of 1 or less leaves a source within [-1, 1] unchanged.
+pub struct WaveFolder<S>
+where
+    S: AdjustableSource<Item = f32>,
+{
+    source: S,
+    gain: f32,
+}
+
+impl<S> WaveFolder<S>
+where
+    S: AdjustableSource<Item = f32>,
+{
+    /// Create a new wavefolder over the given source, with the given gain.
+    pub fn new(source: S, gain: f32) -> WaveFolder<S> {
+        WaveFolder { source, gain }
+    }
+
+    /// Set the gain applied before folding.
+    pub fn set_gain(&mut self, gain: f32) {
+        self.gain = gain;
+    }
+}
+
+/// Reflects a value about -1 and 1 until it is within [-1, 1]. The folds repeat every 4, so this is done in one go.
+fn fold(value: f32) -> f32 {
+    if (-1.0..=1.0).contains(&value) {
+        return value;
+    }
+    let position = (value + 1.0).rem_euclid(4.0);
+    if position <= 2.0 {
+        position - 1.0
+    } else {
+        3.0 - position
+    }
+}
+
+impl<S> Source for WaveFolder<S>
+where
+    S: AdjustableSource<Item = f32>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        self.source.current_frame_len()
+    }
+    fn channels(&self) -> u16 {
+        self.source.channels()
+    }
+    fn sample_rate(&self) -> u32 {
+        self.source.sample_rate()
+    }
+    fn total_duration(&self) -> Option<Duration> {
+        self.source.total_duration()
+    }
+}
+
+impl<S> Iterator for WaveFolder<S>
+where
+    S: AdjustableSource<Item = f32>,
+{
+    type Item = f32;
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(fold(self.source.next()? * self.gain))
+    }
+}
+
+impl<S> AdjustableSource for WaveFolder<S>
+where
+    S: AdjustableSource<Item = f32>,
+{
+    fn set_frequency(&mut self, frequency: f32) {
+        self.source.set_frequency(frequency);
+    }
+
+    fn frequency(&self) -> Option<f32> {
+        self.source.frequency()
+    }
+
+    fn retrigger(&mut self) {
+        self.source.retrigger();
+    }
+}
+
+/// The time constants of the drums' decays, in seconds: after that long, a hit is at about a third of its initial volume.
+const KICK_DECAY: f32 = 0.15;
+const KICK_SWEEP: f32 = 0.03;
+const SNARE_TONE_DECAY: f32 = 0.05;
+const SNARE_NOISE_DECAY: f32 = 0.1;
+const HIHAT_DECAY: f32 = 0.03;
+/// How long a drum hit lasts, in time constants of its longest decay, after which it is silent until triggered again.
+const DRUM_LENGTH: f32 = 7.0;
+
+/// The amplitude of an exponential decay with the given time constant, `time` seconds after it started.
+fn decay(time: f32, time_constant: f32) -> f32 {
+    (-time / time_constant).exp()
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+/// A kick drum: a sine whose pitch sweeps down quickly to the fundamental, with a fast decay.
+/// It is silent until `trigger` is called, and after each hit. The frequency is the fundamental, 50Hz being a typical kick.
+pub struct Kick {
+    frequency: f32,
+    phase: f32,
+    time: Option<f32>,
+}
+
+impl Kick {
+    /// Create a new kick drum with the given fundamental frequency. It is silent until triggered.
+    pub fn new(frequency: f32) -> Kick {
+        Kick {
+            frequency,
+            phase: 0.0,
+            time: None,
+        }
+    }
+
+    /// Hit the drum, restarting the sound if it was still playing.
+    pub fn trigger(&mut self) {
+        self.phase = 0.0;
+        self.time = Some(0.0);
+    }
+}
+
+impl Source for Kick {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+    fn channels(&self) -> u16 {
+        1
+    }
+    fn sample_rate(&self) -> u32 {
+        SAMPLE_RATE
+    }
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+impl Iterator for Kick {
+    type Item = f32;
+    fn next(&mut self) -> Option<Self::Item> {
+        let time = match self.time {
+            Some(time) if time < KICK_DECAY * DRUM_LENGTH => time,
+            _ => {
+                self.time = None;
+                return Some(0.0);
+            }
+        };
+        let result = (self.phase * std::f32::consts::TAU).sin() * decay(time, KICK_DECAY);
+        // The pitch starts 4 times higher, and falls to the fundamental.
+        let frequency = self.frequency * (1.0 + 3.0 * decay(time, KICK_SWEEP));
+        self.phase = (self.phase + frequency / SAMPLE_RATE as f32) % 1.0;
+        self.time = Some(time + 1.0 / SAMPLE_RATE as f32);
+        Some(result)
+    }
+}
+
+impl AdjustableSource for Kick {
+    fn set_frequency(&mut self, frequency: f32) {
+        self.frequency = frequency;
+    }
+
+    fn frequency(&self) -> Option<f32> {
+        Some(self.frequency)
+    }
+
+    fn retrigger(&mut self) {
+        self.trigger();
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+/// A snare drum: a short tone mixed with a longer burst of noise.
+/// It is silent until `trigger` is called, and after each hit. The frequency is the tone's, around 180Hz being typical.
+pub struct Snare {
+    frequency: f32,
+    phase: f32,
+    time: Option<f32>,
+}
+
+impl Snare {
+    /// Create a new snare drum with the given tone frequency. It is silent until triggered.
+    pub fn new(frequency: f32) -> Snare {
+        Snare {
+            frequency,
+            phase: 0.0,
+            time: None,
+        }
+    }
+
+    /// Hit the drum, restarting the sound if it was still playing.
+    pub fn trigger(&mut self) {
+        self.phase = 0.0;
+        self.time = Some(0.0);
+    }
+}
+
+impl Source for Snare {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+    fn channels(&self) -> u16 {
+        1
+    }
+    fn sample_rate(&self) -> u32 {
+        SAMPLE_RATE
+    }
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+impl Iterator for Snare {
+    type Item = f32;
+    fn next(&mut self) -> Option<Self::Item> {
+        let time = match self.time {
+            Some(time) if time < SNARE_NOISE_DECAY * DRUM_LENGTH => time,
+            _ => {
+                self.time = None;
+                return Some(0.0);
+            }
+        };
+        let tone = (self.phase * std::f32::consts::TAU).sin() * decay(time, SNARE_TONE_DECAY);
+        let noise = thread_rng().gen_range(-1.0..=1.0) * decay(time, SNARE_NOISE_DECAY);
+        self.phase = (self.phase + self.frequency / SAMPLE_RATE as f32) % 1.0;
+        self.time = Some(time + 1.0 / SAMPLE_RATE as f32);
+        Some((tone + noise) * 0.5)
+    }
+}
+
+impl AdjustableSource for Snare {
+    fn set_frequency(&mut self, frequency: f32) {
+        self.frequency = frequency;
+    }
+
+    fn frequency(&self) -> Option<f32> {
+        Some(self.frequency)
+    }
+
+    fn retrigger(&mut self) {
+        self.trigger();
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+/// A closed hi-hat: high-passed noise with a very short decay.
+/// It is silent until `trigger` is called, and after each hit. Its frequency can't be adjusted.
+pub struct HiHat {
+    previous_noise: f32,
+    time: Option<f32>,
+}
+
+impl HiHat {
+    /// Create a new hi-hat. It is silent until triggered.
+    pub fn new() -> HiHat {
+        HiHat {
+            previous_noise: 0.0,
+            time: None,
+        }
+    }
+
+    /// Hit the hi-hat, restarting the sound if it was still playing.
+    pub fn trigger(&mut self) {
+        self.time = Some(0.0);
+    }
+}
+
+impl Source for HiHat {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+    fn channels(&self) -> u16 {
+        1
+    }
+    fn sample_rate(&self) -> u32 {
+        SAMPLE_RATE
+    }
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+impl Iterator for HiHat {
+    type Item = f32;
+    fn next(&mut self) -> Option<Self::Item> {
+        let time = match self.time {
+            Some(time) if time < HIHAT_DECAY * DRUM_LENGTH => time,
+            _ => {
+                self.time = None;
+                return Some(0.0);
+            }
+        };
+        // Differentiating the noise keeps only its highs.
+        let noise: f32 = thread_rng().gen_range(-1.0..=1.0);
+        let result = (noise - self.previous_noise) * 0.5 * decay(time, HIHAT_DECAY);
+        self.previous_noise = noise;
+        self.time = Some(time + 1.0 / SAMPLE_RATE as f32);
+        Some(result)
+    }
+}
+
+impl AdjustableSource for HiHat {
+    fn set_frequency(&mut self, _frequency: f32) {}
+
+    fn retrigger(&mut self) {
+        self.trigger();
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+/// A hard-synced oscillator: a slave waveform, whose phase is reset every time an inaudible master oscillator completes a cycle.
+/// The output repeats at the master frequency, while the slave frequency shapes the timbre. Sweeping the slave above the master gives the classic sync lead sound.
+/// The slave is reset through `retrigger`, so any of the built-in waveforms can be used.
+pub struct SyncOsc<S>
+where
+    S: AdjustableSource<Item = f32>,
+{
+    slave: S,
+    master_phase: f32,
+    master_frequency: f32,
+}
+
+impl<S> SyncOsc<S>
+where
+    S: AdjustableSource<Item = f32>,
+{
+    /// Create a new synced oscillator with the given master frequency. The slave keeps its own frequency, which is its free-running pitch.
+    pub fn new(slave: S, master_frequency: f32) -> SyncOsc<S> {
+        SyncOsc {
+            slave,
+            master_phase: 0.0,
+            master_frequency,
+        }
+    }
+
+    /// Set the free-running frequency of the slave.
+    pub fn set_slave_frequency(&mut self, frequency: f32) {
+        self.slave.set_frequency(frequency);
+    }
+
+    /// The free-running frequency of the slave.
+    pub fn slave_frequency(&self) -> Option<f32> {
+        self.slave.frequency()
+    }
+}
+
+impl<S> Source for SyncOsc<S>
+where
+    S: AdjustableSource<Item = f32>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+    fn channels(&self) -> u16 {
+        1
+    }
+    fn sample_rate(&self) -> u32 {
+        SAMPLE_RATE
+    }
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+impl<S> Iterator for SyncOsc<S>
+where
+    S: AdjustableSource<Item = f32>,
+{
+    type Item = f32;
+    fn next(&mut self) -> Option<Self::Item> {
+        let result = self.slave.next()?;
+        self.master_phase += self.master_frequency / SAMPLE_RATE as f32;
+        if self.master_phase >= 1.0 {
+            self.master_phase %= 1.0;
+            self.slave.retrigger();
+        }
+        Some(result)
+    }
+}
+
+impl<S> AdjustableSource for SyncOsc<S>
+where
+    S: AdjustableSource<Item = f32>,
+{
+    /// Sets the master frequency, which is the pitch heard.
+    fn set_frequency(&mut self, frequency: f32) {
+        self.master_frequency = frequency;
+    }
+
+    fn frequency(&self) -> Option<f32> {
+        Some(self.master_frequency)
+    }
+
+    fn retrigger(&mut self) {
+        self.master_phase = 0.0;
+        self.slave.retrigger();
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+/// A source which plays a buffer of mono samples over and over, wrapping around from its end straight back to its start.
+/// Usually created by `audio::render_loop`, which renders Channels once so that a musical phrase can be looped without synthesizing it live.
+pub struct LoopSource {
+    samples: Vec<f32>,
+    position: usize,
+}
+
+impl LoopSource {
+    /// Create a new loop from mono samples at the library's sample rate. An empty loop is silent.
+    pub fn new(samples: Vec<f32>) -> LoopSource {
+        LoopSource {
+            samples,
+            position: 0,
+        }
+    }
+
+    /// Crossfades the end of the loop into its start, so that there is no click where it wraps around.
+    /// The last `crossfade` of the buffer is removed and faded out over its start, so the loop gets shorter by that much:
+    /// render the phrase with `crossfade` of extra audio at its end (e.g. a reverb tail) to keep its length.
+    /// The crossfade is clamped to half of the loop. If the loop was already playing, its position wraps around the new length.
+    pub fn with_crossfade(mut self, crossfade: Duration) -> Self {
+        let length = ((crossfade.as_secs_f64() * SAMPLE_RATE as f64).round() as usize)
+            .min(self.samples.len() / 2);
+        let tail = self.samples.split_off(self.samples.len() - length);
+        for (i, (head, tail)) in self.samples.iter_mut().zip(tail).enumerate() {
+            let t = i as f32 / length as f32;
+            *head = *head * t + tail * (1.0 - t);
+        }
+        self.position %= self.samples.len().max(1);
+        self
+    }
+
+    /// The samples that are looped.
+    pub fn samples(&self) -> &[f32] {
+        &self.samples
+    }
+}
+
+impl Source for LoopSource {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+    fn channels(&self) -> u16 {
+        1
+    }
+    fn sample_rate(&self) -> u32 {
+        SAMPLE_RATE
+    }
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+impl Iterator for LoopSource {
+    type Item = f32;
+    fn next(&mut self) -> Option<Self::Item> {
+        let result = match self.samples.get(self.position) {
+            Some(sample) => *sample,
+            None => return Some(0.0),
+        };
+        self.position = (self.position + 1) % self.samples.len();
+        Some(result)
+    }
+}
+
+impl AdjustableSource for LoopSource {
+    /// Does nothing, since the loop is already rendered.
+    fn set_frequency(&mut self, _frequency: f32) {}
+
+    /// Restarts the loop from its start.
+    fn retrigger(&mut self) {
+        self.position = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rodio::{OutputStream, Sink};
+
+    use crate::audio::sources::*;
+
+    const SPECTRUM_SAMPLES: usize = 1024;
+
+    /// The energy of the given DFT bins, over the first `SPECTRUM_SAMPLES` samples of a source.
+    fn spectrum_energy(source: &mut dyn Iterator<Item = f32>, bins: std::ops::Range<usize>) -> f32 {
+        let samples: Vec<f32> = source.take(SPECTRUM_SAMPLES).collect();
+        bins.map(|bin| {
+            let (mut re, mut im) = (0.0, 0.0);
+            for (n, sample) in samples.iter().enumerate() {
+                let angle = 2.0 * std::f32::consts::PI * (bin * n) as f32 / SPECTRUM_SAMPLES as f32;
+                re += sample * angle.cos();
+                im -= sample * angle.sin();
+            }
+            re * re + im * im
+        })
+        .sum()
+    }
+
+    #[test]
+    fn all_test() {
+        sine_test();
+        square_test();
+        sawtooth_test();
+        triangle_test();
+        white_noise_test();
+    }
+
+    #[test]
+    fn sine_test() {
+        let (_stream, handle) = OutputStream::try_default().unwrap();
+        let sink = Sink::try_new(&handle).ok().unwrap();
+        let source = SineWave::new(220.0);
+        sink.set_volume(0.2);
+        sink.append(source);
+        std::thread::sleep(Duration::from_secs(2));
+    }
+
+    #[test]
+    fn square_test() {
+        let (_stream, handle) = OutputStream::try_default().unwrap();
+        let sink = Sink::try_new(&handle).ok().unwrap();
+        let source = SquareWave::new(220.0);
+        sink.set_volume(0.2);
+        sink.append(source);
+        std::thread::sleep(Duration::from_secs(2));
+    }
+
+    #[test]
+    fn sawtooth_test() {
+        let (_stream, handle) = OutputStream::try_default().unwrap();
+        let sink = Sink::try_new(&handle).ok().unwrap();
+        let source = SawtoothWave::new(220.0);
+        sink.set_volume(0.2);
+        sink.append(source);
+        std::thread::sleep(Duration::from_secs(2));
+    }
+
+    #[test]
+    fn triangle_test() {
+        let (_stream, handle) = OutputStream::try_default().unwrap();
+        let sink = Sink::try_new(&handle).ok().unwrap();
+        let source = TriangleWave::new(220.0);
+        sink.set_volume(0.2);
+        sink.append(source);
+        std::thread::sleep(Duration::from_secs(2));
+    }
+
+    #[test]
+    fn semitriangle_test() {
+        let (_stream, handle) = OutputStream::try_default().unwrap();
+        let sink = Sink::try_new(&handle).ok().unwrap();
+        let source = SemiTriangle::new(220.0);
+        sink.set_volume(0.2);
+        sink.append(source);
+        std::thread::sleep(Duration::from_secs(2));
+    }
+
+    #[test]
+    fn semisine_test() {
+        let (_stream, handle) = OutputStream::try_default().unwrap();
+        let sink = Sink::try_new(&handle).ok().unwrap();
+        let source = SemiSine::new(220.0);
+        sink.set_volume(0.2);
+        sink.append(source);
+        std::thread::sleep(Duration::from_secs(2));
+    }
+
+    #[test]
+    fn stepsquare_test() {
+        let (_stream, handle) = OutputStream::try_default().unwrap();
+        let sink = Sink::try_new(&handle).ok().unwrap();
+        let source = StepSquare::new(220.0);
+        sink.set_volume(0.2);
+        sink.append(source);
+        std::thread::sleep(Duration::from_secs(2));
+    }
+
+    #[test]
+    fn white_noise_test() {
+        let (_stream, handle) = OutputStream::try_default().unwrap();
+        let sink = Sink::try_new(&handle).ok().unwrap();
+        let source = WhiteNoise::new();
+        sink.set_volume(0.2);
+        sink.append(source);
+        std::thread::sleep(Duration::from_secs(2));
+    }
+
+    #[test]
+    fn lfo_cycle_test() {
+        let rate = 2.0;
+        let mut lfo = Lfo::new(LfoShape::Saw, rate);
+        let samples_per_cycle = (SAMPLE_RATE as f32 / rate) as usize;
+        assert_eq!(lfo.tick(), -1.0);
+        for _ in 1..samples_per_cycle / 2 {
+            lfo.tick();
+        }
+        assert!((lfo.phase - 0.5).abs() < 0.01);
+        for _ in samples_per_cycle / 2..samples_per_cycle {
+            lfo.tick();
+        }
+        assert!(lfo.phase < 0.01 || lfo.phase > 0.99);
+    }
+
+    #[test]
+    fn plucked_string_decay_test() {
+        let mut string = PluckedString::new(220.0);
+        let rms = |string: &mut PluckedString| {
+            (string.take(1000).map(|x| x * x).sum::<f32>() / 1000.0).sqrt()
+        };
+        let start = rms(&mut string);
+        string.nth(SAMPLE_RATE as usize);
+        let later = rms(&mut string);
+        assert!(later < start / 2.0);
+        string.nth(SAMPLE_RATE as usize * 10);
+        assert!(string.next().unwrap().abs() < 0.01);
+    }
+
+    #[test]
+    fn plucked_string_test() {
+        let (_stream, handle) = OutputStream::try_default().unwrap();
+        let sink = Sink::try_new(&handle).ok().unwrap();
+        let source = PluckedString::new(220.0);
+        sink.set_volume(0.2);
+        sink.append(source);
+        std::thread::sleep(Duration::from_secs(2));
+    }
+
+    #[test]
+    fn plucked_string_frequency_test() {
+        let mut string = PluckedString::new(0.0);
+        assert_eq!(string.frequency(), Some(PLUCK_MIN_FREQUENCY));
+        string.set_frequency(440.0);
+        assert_eq!(string.frequency(), Some(SAMPLE_RATE as f32 / 93.0));
+        for frequency in [0.0, 0.001, -220.0, f32::NAN, f32::INFINITY] {
+            string.set_frequency(frequency);
+            assert_eq!(string.frequency(), Some(PLUCK_MIN_FREQUENCY));
+            assert!(string.next().is_some());
+        }
+    }
+
+    #[test]
+    fn crossfade_blend_test() {
+        let mut square = SquareWave::new(220.0);
+        let mut sine = SineWave::new(440.0);
+        let mut crossfade = Crossfade::new(square, sine, Duration::from_millis(10));
+        for _ in 0..100 {
+            assert_eq!(crossfade.next(), square.next());
+            sine.next();
+        }
+        crossfade.start_fade();
+        for _ in 0..SAMPLE_RATE / 100 {
+            crossfade.next();
+            square.next();
+            sine.next();
+        }
+        for _ in 0..100 {
+            assert_eq!(crossfade.next(), sine.next());
+        }
+
+        // Constant sources, so that the blend itself is visible.
+        let mut crossfade = Crossfade::new(
+            SquareWave::new(0.0),
+            SawtoothWave::new(0.0),
+            Duration::from_millis(10),
+        );
+        assert_eq!(crossfade.next(), Some(1.0));
+        crossfade.start_fade();
+        let mut previous = 1.0;
+        for _ in 0..SAMPLE_RATE / 100 {
+            let current = crossfade.next().unwrap();
+            assert!(current <= previous);
+            previous = current;
+        }
+        assert_eq!(crossfade.next(), Some(-1.0));
+    }
+
+    #[test]
+    fn retrigger_test() {
+        let mut sine = SineWave::new(440.0);
+        sine.nth(100);
+        assert_ne!(sine.phase, 0.0);
+        sine.retrigger();
+        assert_eq!(sine.phase, 0.0);
+    }
+
+    #[test]
+    fn compressor_test() {
+        // A burst at full volume for one second, then quiet.
+        struct Burst(usize);
+
+        impl Iterator for Burst {
+            type Item = f32;
+            fn next(&mut self) -> Option<Self::Item> {
+                self.0 += 1;
+                Some(if self.0 <= SAMPLE_RATE as usize {
+                    1.0
+                } else {
+                    0.1
+                })
+            }
+        }
+
+        impl Source for Burst {
+            fn current_frame_len(&self) -> Option<usize> {
+                None
+            }
+            fn channels(&self) -> u16 {
+                1
+            }
+            fn sample_rate(&self) -> u32 {
+                SAMPLE_RATE
+            }
+            fn total_duration(&self) -> Option<Duration> {
+                None
+            }
+        }
+
+        let mut compressor = Compressor::new(
+            Burst(0),
+            0.5,
+            4.0,
+            Duration::from_millis(10),
+            Duration::from_millis(100),
+        );
+        let burst = compressor.nth(SAMPLE_RATE as usize / 2).unwrap();
+        assert!((burst - 0.625).abs() < 0.01);
+        let quiet = compressor.nth(SAMPLE_RATE as usize).unwrap();
+        assert!((quiet - 0.1).abs() < 0.001);
+    }
+
+    #[test]
+    fn band_limited_aliasing_test() {
+        // Energy between 40% of the sample rate and the Nyquist frequency.
+        let high_bins = SPECTRUM_SAMPLES * 4 / 10..SPECTRUM_SAMPLES / 2;
+        let naive = spectrum_energy(&mut SawtoothWave::new(2950.0), high_bins.clone());
+        let band_limited = spectrum_energy(&mut BandLimitedSaw::new(2950.0), high_bins.clone());
+        assert!(band_limited < naive / 2.0);
+        let naive = spectrum_energy(&mut SquareWave::new(2950.0), high_bins.clone());
+        let band_limited = spectrum_energy(&mut BandLimitedSquare::new(2950.0), high_bins);
+        assert!(band_limited < naive / 2.0);
+    }
+
+    #[test]
+    fn band_limited_test() {
+        let (_stream, handle) = OutputStream::try_default().unwrap();
+        let sink = Sink::try_new(&handle).ok().unwrap();
+        let source = BandLimitedSaw::new(220.0);
+        sink.set_volume(0.2);
+        sink.append(source);
+        std::thread::sleep(Duration::from_secs(2));
+    }
+
+    #[test]
+    fn fm_synth_test() {
+        // Lands exactly on the 10th bin, so that a plain sine has all of its energy there.
+        let frequency = 10.0 * SAMPLE_RATE as f32 / SPECTRUM_SAMPLES as f32;
+        let mut fm = FmSynth::new(frequency, 1.0, 0.0);
+        let mut sine = SineWave::new(frequency);
+        for _ in 0..1000 {
+            assert_eq!(fm.next(), sine.next());
+        }
+
+        let sidebands = 15..SPECTRUM_SAMPLES / 2;
+        let plain = spectrum_energy(&mut FmSynth::new(frequency, 1.0, 0.0), sidebands.clone());
+        let modulated = spectrum_energy(&mut FmSynth::new(frequency, 1.0, 5.0), sidebands);
+        let total = spectrum_energy(
+            &mut FmSynth::new(frequency, 1.0, 5.0),
+            0..SPECTRUM_SAMPLES / 2,
+        );
+        assert!(plain < total * 0.01);
+        assert!(modulated > total * 0.1);
+    }
+
+    #[test]
+    fn scale_quantizer_test() {
+        let mut quantizer = ScaleQuantizer::new(SineWave::new(440.0), 440.0, vec![0, 2, 4, 7, 9]);
+        quantizer.set_frequency(445.0);
+        assert!((quantizer.frequency().unwrap() - 440.0).abs() < 0.01);
+        // Halfway between A and B, but closer to B (a whole tone up).
+        quantizer.set_frequency(480.0);
+        assert!((quantizer.frequency().unwrap() - 493.88).abs() < 0.01);
+        // Below the root, C# is the closest note of A major pentatonic to C.
+        quantizer.set_frequency(262.0);
+        assert!((quantizer.frequency().unwrap() - 277.18).abs() < 0.01);
+        quantizer.set_scale(vec![]);
+        quantizer.set_frequency(445.0);
+        assert_eq!(quantizer.frequency(), Some(445.0));
+    }
+
+    #[test]
+    fn sample_normalize_test() {
+        let sample = SampleSource::new(vec![0.0, 0.25, -0.5, 0.1]).normalized();
+        assert_eq!(sample.samples(), &[0.0, 0.5, -1.0, 0.2]);
+        let silence = SampleSource::new(vec![0.0; 4]).normalized();
+        assert_eq!(silence.samples(), &[0.0; 4]);
+    }
+
+    #[test]
+    fn sample_trim_test() {
+        let sample =
+            SampleSource::new(vec![0.0, 0.001, 0.5, 0.0, -0.3, 0.0, 0.0]).trim_silence(0.01);
+        assert_eq!(sample.samples(), &[0.5, 0.0, -0.3]);
+        let mut sample = sample;
+        assert_eq!(sample.next(), Some(0.5));
+        let silence = SampleSource::new(vec![0.0; 4]).trim_silence(0.01);
+        assert!(silence.samples().is_empty());
+    }
+
+    #[test]
+    fn step_gate_test() {
+        let rate = 10.0;
+        let step_length = (SAMPLE_RATE as f32 / rate) as usize;
+        let mut gate = StepGate::new(SquareWave::new(100.0), vec![true, false, true, false], rate);
+        let samples: Vec<f32> = gate.by_ref().take(step_length * 8).collect();
+        for (step, chunk) in samples.chunks(step_length).enumerate() {
+            // Leave some room around step boundaries for floating point drift.
+            let inner = &chunk[10..step_length - 10];
+            if step % 2 == 0 {
+                assert!(inner.iter().all(|x| x.abs() == 1.0));
+            } else {
+                assert!(inner.iter().all(|x| *x == 0.0));
+            }
+        }
+        gate.set_pattern(vec![false]);
+        assert!(gate.take(1000).all(|x| x == 0.0));
+    }
+
+    #[test]
+    fn filter_envelope_test() {
+        let mut envelope = FilterEnvelope::new(
+            SawtoothWave::new(110.0),
+            200.0,
+            5000.0,
+            Duration::from_millis(10),
+            Duration::from_millis(100),
+            0.5,
+            Duration::from_millis(50),
+        );
+        envelope.nth(100);
+        assert_eq!(envelope.cutoff(), 200.0);
+        envelope.trigger();
+        let cutoffs: Vec<f32> = (0..SAMPLE_RATE / 5)
+            .map(|_| {
+                envelope.next();
+                envelope.cutoff()
+            })
+            .collect();
+        let peak = cutoffs.iter().position(|cutoff| *cutoff == 5000.0).unwrap();
+        // The attack lasts 10ms.
+        assert!(peak.abs_diff(SAMPLE_RATE as usize / 100) <= 1);
+        assert!(cutoffs[..=peak].windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(cutoffs[peak..].windows(2).all(|pair| pair[0] >= pair[1]));
+        let sustain = (200.0_f32 * 5000.0).sqrt();
+        assert!((envelope.cutoff() - sustain).abs() < 1.0);
+        envelope.nth(1000);
+        assert!((envelope.cutoff() - sustain).abs() < 1.0);
+        envelope.release();
+        envelope.nth(SAMPLE_RATE as usize / 20);
+        assert_eq!(envelope.cutoff(), 200.0);
+    }
+
+    #[test]
+    fn am_synth_test() {
+        // Frequencies in the middle of DFT bins: the carrier in bin 25, and the modulator 5 bins above 0.
+        let bin = SAMPLE_RATE as f32 / SPECTRUM_SAMPLES as f32;
+        let mut am = AmSynth::new(25.0 * bin, 5.0 * bin, 0.0);
+        let mut sine = SineWave::new(25.0 * bin);
+        for _ in 0..1000 {
+            assert_eq!(am.next(), sine.next());
+        }
+
+        let upper_sideband = 29..32;
+        let plain = spectrum_energy(
+            &mut AmSynth::new(25.0 * bin, 5.0 * bin, 0.0),
+            upper_sideband.clone(),
+        );
+        let modulated = spectrum_energy(
+            &mut AmSynth::new(25.0 * bin, 5.0 * bin, 1.0),
+            upper_sideband,
+        );
+        let total = spectrum_energy(
+            &mut AmSynth::new(25.0 * bin, 5.0 * bin, 1.0),
+            0..SPECTRUM_SAMPLES / 2,
+        );
+        assert!(plain < total * 0.01);
+        assert!(modulated > total * 0.1);
+    }
+
+    #[test]
+    fn stream_source_test() {
+        let (mut source, mut producer) = StreamSource::new();
+        assert_eq!(source.next(), Some(0.0));
+        producer.push(&[0.5, -0.25, 1.0]);
+        producer.push(&[0.75]);
+        assert_eq!(producer.queued(), 4);
+        let samples: Vec<f32> = source.by_ref().take(6).collect();
+        assert_eq!(samples, vec![0.5, -0.25, 1.0, 0.75, 0.0, 0.0]);
+        assert_eq!(producer.queued(), 0);
+        let mut producer = producer.clone();
+        std::thread::spawn(move || producer.push(&[0.1]))
+            .join()
+            .unwrap();
+        assert_eq!(source.next(), Some(0.1));
+    }
+
+    #[test]
+    fn reverb_test() {
+        let (impulse, mut producer) = StreamSource::new();
+        producer.push(&[1.0]);
+        let mut reverb = Reverb::new(impulse, 0.8, 0.2, 1.0, 1.0);
+        let samples: Vec<f32> = reverb.by_ref().take(SAMPLE_RATE as usize).collect();
+        assert_eq!(samples[0], 1.0);
+        let energy =
+            |range: std::ops::Range<usize>| samples[range].iter().map(|s| s * s).sum::<f32>();
+        // The dry impulse is a single sample, but the tail goes on long after it, slowly decaying.
+        assert!(samples[1..500].iter().all(|sample| *sample == 0.0));
+        let early = energy(1000..5000);
+        let late = energy(20000..24000);
+        assert!(early > 1e-4);
+        assert!(late > 0.0);
+        assert!(late < early);
+        assert!(samples.iter().all(|sample| sample.abs() <= 1.0));
+    }
+
+    #[test]
+    fn metronome_test() {
+        let samples: Vec<f32> = Metronome::new(120.0, 4)
+            .take(3 * SAMPLE_RATE as usize)
+            .collect();
+        // At 120 BPM, there is a beat every half second.
+        let beat_length = SAMPLE_RATE as usize / 2;
+        let crossings = |range: std::ops::Range<usize>| {
+            samples[range]
+                .windows(2)
+                .filter(|pair| (pair[0] < 0.0) != (pair[1] < 0.0))
+                .count()
+        };
+        for beat in 0..6 {
+            let start = beat * beat_length;
+            assert!(samples[start..start + 200].iter().any(|s| s.abs() > 0.1));
+            assert!(samples[start + 2000..start + beat_length]
+                .iter()
+                .all(|s| *s == 0.0));
+        }
+        // The downbeats are higher than the other beats.
+        assert!(crossings(0..1000) > crossings(beat_length..beat_length + 1000) * 3 / 2);
+        assert!(
+            crossings(4 * beat_length..4 * beat_length + 1000)
+                > crossings(beat_length..beat_length + 1000) * 3 / 2
+        );
+    }
+
+    #[test]
+    fn envelope_curve_test() {
+        let halfway_through_decay = |curve| {
+            let mut envelope = FilterEnvelope::new(
+                SawtoothWave::new(110.0),
+                200.0,
+                5000.0,
+                Duration::from_millis(10),
+                Duration::from_millis(100),
+                0.5,
+                Duration::from_millis(50),
+            )
+            .with_curve(curve);
+            envelope.trigger();
+            envelope.nth((SAMPLE_RATE / 100 + SAMPLE_RATE / 20) as usize);
+            envelope.cutoff()
+        };
+        let linear = halfway_through_decay(CurveShape::Linear);
+        let exponential = halfway_through_decay(CurveShape::Exponential);
+        let logarithmic = halfway_through_decay(CurveShape::Logarithmic);
+        // The exponential decay is mostly done by then, while the logarithmic one has barely started.
+        assert!(exponential < linear);
+        assert!(logarithmic > linear);
+        let sustain = (200.0_f32 * 5000.0).sqrt();
+        assert!(exponential > sustain);
+        assert!(logarithmic < 5000.0);
+    }
+
+    #[test]
+    fn tap_test() {
+        let (mut tap, handle) = Tap::new(SineWave::new(440.0), 100);
+        assert!(handle.latest(10).is_empty());
+        let played: Vec<f32> = tap.by_ref().take(250).collect();
+        assert_eq!(handle.latest(10), &played[240..]);
+        // Only the last 100 samples are kept.
+        assert_eq!(handle.latest(1000), &played[150..]);
+        tap.set_frequency(880.0);
+        assert_eq!(tap.frequency(), Some(880.0));
+        let mut sine = SineWave::new(440.0);
+        sine.nth(249);
+        sine.set_frequency(880.0);
+        let sample = tap.next();
+        assert_eq!(sample, sine.next());
+        assert_eq!(handle.latest(1), vec![sample.unwrap()]);
+    }
+
+    #[test]
+    fn stereo_reverb_test() {
+        let (impulse, mut producer) = StreamSource::new();
+        producer.push(&[1.0]);
+        let mut reverb = StereoReverb::new(impulse, 0.8, 0.2, 1.0, 1.0, 1.0);
+        assert_eq!(reverb.channels(), 2);
+        let samples: Vec<f32> = reverb.by_ref().take(SAMPLE_RATE as usize).collect();
+        let (left, right): (Vec<f32>, Vec<f32>) =
+            samples.chunks(2).map(|frame| (frame[0], frame[1])).unzip();
+        // The dry impulse is centered, but the tails differ.
+        assert_eq!((left[0], right[0]), (1.0, 1.0));
+        let energy = |channel: &[f32]| channel[1..].iter().map(|s| s * s).sum::<f32>();
+        assert!(energy(&left) > 1e-4);
+        assert!(energy(&right) > 1e-4);
+        let difference: f32 = left.iter().zip(&right).map(|(l, r)| (l - r).powi(2)).sum();
+        assert!(difference > energy(&left) * 0.1);
+
+        // Without width, both channels get the same mix of the tails.
+        let (impulse, mut producer) = StreamSource::new();
+        producer.push(&[1.0]);
+        let samples: Vec<f32> = StereoReverb::new(impulse, 0.8, 0.2, 1.0, 1.0, 0.0)
+            .take(SAMPLE_RATE as usize)
+            .collect();
+        assert!(samples.chunks(2).all(|frame| frame[0] == frame[1]));
+    }
+
+    #[test]
+    fn smooth_frequency_test() {
+        let max_delta = |source: &mut dyn Iterator<Item = f32>| {
+            let samples: Vec<f32> = source.take(20).collect();
+            samples
+                .windows(2)
+                .map(|pair| (pair[1] - pair[0]).abs())
+                .fold(0.0, f32::max)
+        };
+        // Halfway through the first quarter, where the semi-triangle rises by 8 * 100 / SAMPLE_RATE per sample.
+        let mut smooth = SemiTriangle::new(100.0);
+        smooth.nth(SAMPLE_RATE as usize / 800);
+        let mut abrupt = smooth;
+        smooth.set_frequency_smooth(4000.0);
+        abrupt.set_frequency(4000.0);
+        let slope = 8.0 * 100.0 / SAMPLE_RATE as f32;
+        assert!(max_delta(&mut smooth) < slope * 1.01);
+        assert!(max_delta(&mut abrupt) > slope * 10.0);
+        // The new frequency is reported right away, and used once the quarter is over.
+        assert_eq!(smooth.frequency(), Some(4000.0));
+        let after: Vec<f32> = smooth.by_ref().take(SAMPLE_RATE as usize / 400).collect();
+        assert!(after.iter().any(|sample| *sample < 0.9));
+
+        // Changing the frequency in the middle of a step cuts it short, to a length neither frequency gives.
+        // A step lasts a quarter of a cycle: 102.5 samples at 100Hz, and 2.5 samples at 4000Hz.
+        let step_length =
+            |source: &mut StepSquare| 11 + source.take_while(|sample| *sample == -1.0).count();
+        let mut smooth = StepSquare::new(100.0);
+        smooth.nth(10);
+        let mut abrupt = smooth;
+        smooth.set_frequency_smooth(4000.0);
+        abrupt.set_frequency(4000.0);
+        assert!((5..100).contains(&step_length(&mut abrupt)));
+        assert_eq!(step_length(&mut smooth), 103);
+        assert!(smooth
+            .take(20)
+            .collect::<Vec<f32>>()
+            .windows(4)
+            .all(|window| window.iter().any(|sample| *sample != window[0])));
+
+        // At 0Hz the phase doesn't move, so the change can't wait for the end of the quarter.
+        let mut silent = StepSquare::new(0.0);
+        silent.set_frequency_smooth(4000.0);
+        assert!(silent.take(4).any(|sample| sample != -1.0));
+
+        // Retriggering starts the cycle over, with the pending frequency.
+        let mut retriggered = StepSquare::new(100.0);
+        retriggered.nth(10);
+        retriggered.set_frequency_smooth(4000.0);
+        retriggered.retrigger();
+        assert_eq!(retriggered.frequency(), Some(4000.0));
+        assert!(retriggered.take(4).any(|sample| sample != -1.0));
+    }
+
+    #[test]
+    fn wave_folder_test() {
+        assert_eq!(fold(0.5), 0.5);
+        assert_eq!(fold(1.5), 0.5);
+        assert_eq!(fold(-2.5), 0.5);
+        assert_eq!(fold(4.0), 0.0);
+        let crossings = |samples: &[f32]| {
+            samples
+                .windows(2)
+                .filter(|pair| (pair[0] < 0.0) != (pair[1] < 0.0))
+                .count()
+        };
+        let sine: Vec<f32> = SineWave::new(100.0).take(SAMPLE_RATE as usize).collect();
+        let mut folder = WaveFolder::new(SineWave::new(100.0), 1.0);
+        let unfolded: Vec<f32> = folder.by_ref().take(SAMPLE_RATE as usize).collect();
+        assert_eq!(unfolded, sine);
+        folder.retrigger();
+        folder.set_gain(3.0);
+        let folded: Vec<f32> = folder.by_ref().take(SAMPLE_RATE as usize).collect();
+        assert!(folded.iter().all(|sample| (-1.0..=1.0).contains(sample)));
+        assert!(crossings(&folded) > crossings(&sine) * 2);
+        folder.set_frequency(200.0);
+        assert_eq!(folder.frequency(), Some(200.0));
+    }
+
+    /// Checks that a drum is silent until triggered, then plays a decaying burst, and goes silent again.
+    fn assert_drum_hit<S: Iterator<Item = f32>>(mut drum: S, trigger: impl FnOnce(&mut S)) {
+        assert!(drum.by_ref().take(1000).all(|sample| sample == 0.0));
+        trigger(&mut drum);
+        let samples: Vec<f32> = drum.by_ref().take(2 * SAMPLE_RATE as usize).collect();
+        let energy =
+            |range: std::ops::Range<usize>| samples[range].iter().map(|s| s * s).sum::<f32>();
+        let window = SAMPLE_RATE as usize / 100;
+        assert!(samples[..window].iter().any(|sample| sample.abs() > 0.2));
+        assert!(energy(window..2 * window) < energy(0..window));
+        assert!(energy(5 * window..6 * window) < energy(window..2 * window));
+        assert!(samples.iter().all(|sample| sample.abs() <= 1.0));
+        assert!(drum.take(1000).all(|sample| sample == 0.0));
+    }
+
+    #[test]
+    fn drums_test() {
+        assert_drum_hit(Kick::new(50.0), Kick::trigger);
+        assert_drum_hit(Snare::new(180.0), Snare::trigger);
+        assert_drum_hit(HiHat::new(), HiHat::trigger);
+        let mut kick = Kick::new(50.0);
+        kick.set_frequency(60.0);
+        assert_eq!(kick.frequency(), Some(60.0));
+        assert_eq!(HiHat::new().frequency(), None);
+    }
+
+    #[test]
+    fn sync_test() {
+        let jumps = |samples: &[f32]| -> Vec<usize> {
+            samples
+                .windows(2)
+                .enumerate()
+                .filter(|(_, pair)| (pair[1] - pair[0]).abs() > 0.5)
+                .map(|(i, _)| i + 1)
+                .collect()
+        };
+        let mut sync = SyncOsc::new(SawtoothWave::new(100.0), 100.0);
+        assert_eq!(sync.frequency(), Some(100.0));
+        assert_eq!(sync.slave_frequency(), Some(100.0));
+        let period = SAMPLE_RATE as usize / 100;
+        // Sweeping the slave above the master, the slave is cut mid-cycle on every master wrap,
+        // which a free-running slave of the same frequency does not do.
+        for slave_frequency in [150.0, 230.0, 370.0] {
+            sync.retrigger();
+            sync.set_slave_frequency(slave_frequency);
+            let samples: Vec<f32> = sync.by_ref().take(SAMPLE_RATE as usize).collect();
+            let free: Vec<f32> = SawtoothWave::new(slave_frequency)
+                .take(SAMPLE_RATE as usize)
+                .collect();
+            assert_eq!(samples[..period], free[..period]);
+            let synced = jumps(&samples);
+            let free = jumps(&free);
+            let near_wrap =
+                |jumps: &[usize], wrap: usize| jumps.iter().any(|&i| i.abs_diff(wrap) <= 1);
+            assert!((1..100).all(|k| near_wrap(&synced, k * period)));
+            assert!(!(1..100).all(|k| near_wrap(&free, k * period)));
+        }
+        sync.set_frequency(200.0);
+        assert_eq!(sync.frequency(), Some(200.0));
+    }
+
+    #[test]
+    fn noise_seed_test() {
+        let first: Vec<f32> = WhiteNoise::with_seed(42).take(1000).collect();
+        let second: Vec<f32> = WhiteNoise::with_seed(42).take(1000).collect();
+        let other: Vec<f32> = WhiteNoise::with_seed(43).take(1000).collect();
+        assert_eq!(first, second);
+        assert_ne!(first, other);
     }
 }
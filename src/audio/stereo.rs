@@ -0,0 +1,313 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use rodio::Source;
+
+use crate::audio::AtomicF32;
+
+#[derive(Clone, Debug, PartialEq)]
+/// A ping-pong delay, which turns a mono source into a stereo one where the echoes bounce between the left and right channels.
+/// The first echo is on the left, the second one on the right, and so on, each one quieter by the feedback.
+/// The mix controls the balance between the dry source (0) and the echoes (1).
+pub struct PingPongDelay<S>
+where
+    S: Source<Item = f32>,
+{
+    source: S,
+    left: VecDeque<f32>,
+    right: VecDeque<f32>,
+    feedback: f32,
+    mix: f32,
+    pending_right: Option<f32>,
+}
+
+impl<S> PingPongDelay<S>
+where
+    S: Source<Item = f32>,
+{
+    /// Create a new ping-pong delay over the given mono source. Panics if the source isn't mono.
+    /// The feedback is clamped between 0 and 1 so that the echoes always die out.
+    pub fn new(source: S, delay: Duration, feedback: f32, mix: f32) -> PingPongDelay<S> {
+        if source.channels() != 1 {
+            panic!("The ping-pong delay needs a mono source!");
+        }
+        let length = ((delay.as_secs_f32() * source.sample_rate() as f32) as usize).max(1);
+        PingPongDelay {
+            source,
+            left: VecDeque::from(vec![0.0; length]),
+            right: VecDeque::from(vec![0.0; length]),
+            feedback: feedback.clamp(0.0, 1.0),
+            mix: mix.clamp(0.0, 1.0),
+            pending_right: None,
+        }
+    }
+
+    /// Set the feedback, i.e. how much quieter each echo is than the previous one. Clamped between 0 and 1.
+    pub fn set_feedback(&mut self, feedback: f32) {
+        self.feedback = feedback.clamp(0.0, 1.0);
+    }
+
+    /// Set the balance between the dry source (0) and the echoes (1).
+    pub fn set_mix(&mut self, mix: f32) {
+        self.mix = mix.clamp(0.0, 1.0);
+    }
+}
+
+impl<S> Source for PingPongDelay<S>
+where
+    S: Source<Item = f32>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+    fn channels(&self) -> u16 {
+        2
+    }
+    fn sample_rate(&self) -> u32 {
+        self.source.sample_rate()
+    }
+    fn total_duration(&self) -> Option<Duration> {
+        self.source.total_duration()
+    }
+}
+
+impl<S> Iterator for PingPongDelay<S>
+where
+    S: Source<Item = f32>,
+{
+    type Item = f32;
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(right) = self.pending_right.take() {
+            return Some(right);
+        }
+        let dry = self.source.next()?;
+        let left_echo = self.left.pop_front().unwrap();
+        let right_echo = self.right.pop_front().unwrap();
+        self.left.push_back(dry + right_echo * self.feedback);
+        self.right.push_back(left_echo * self.feedback);
+        self.pending_right = Some(dry * (1.0 - self.mix) + right_echo * self.mix);
+        Some(dry * (1.0 - self.mix) + left_echo * self.mix)
+    }
+}
+
+/// A stereo widener, which changes how wide the stereo field of a stereo source (such as a ping-pong delay) sounds.
+/// The source is split into mid (what both channels share) and side (what differs between them), and the side is scaled by the width:
+/// 0 collapses it to mono, 1 leaves it as is, and above 1 widens it.
+/// Optionally, the right channel can also be delayed by a few milliseconds (the Haas effect), which makes even mono sound wider.
+/// Both are adjusted through its `StereoWidenerHook`, which keeps working once the widener is playing.
+pub struct StereoWidener<S>
+where
+    S: Source<Item = f32>,
+{
+    source: S,
+    width: Arc<AtomicF32>,
+    haas_length: Arc<AtomicUsize>,
+    haas: VecDeque<f32>,
+    pending_right: Option<f32>,
+}
+
+/// The handle used to adjust a `StereoWidener`, e.g. after it was appended to a Sink.
+#[derive(Clone)]
+pub struct StereoWidenerHook {
+    width: Arc<AtomicF32>,
+    haas_length: Arc<AtomicUsize>,
+    sample_rate: u32,
+}
+
+impl<S> StereoWidener<S>
+where
+    S: Source<Item = f32>,
+{
+    /// Create a new stereo widener over the given stereo source, with a width of 1 and no Haas delay, along with the handle to adjust it.
+    /// Panics if the source isn't stereo.
+    pub fn new(source: S) -> (StereoWidener<S>, StereoWidenerHook) {
+        if source.channels() != 2 {
+            panic!("The stereo widener needs a stereo source!");
+        }
+        let width = Arc::new(AtomicF32::new(1.0));
+        let haas_length = Arc::new(AtomicUsize::new(0));
+        let hook = StereoWidenerHook {
+            width: width.clone(),
+            haas_length: haas_length.clone(),
+            sample_rate: source.sample_rate(),
+        };
+        (
+            StereoWidener {
+                source,
+                width,
+                haas_length,
+                haas: VecDeque::new(),
+                pending_right: None,
+            },
+            hook,
+        )
+    }
+}
+
+impl StereoWidenerHook {
+    /// Set the width, where 0 is mono, 1 is unchanged, and above 1 is wider. Clamped to at least 0.
+    pub fn set_width(&self, width: f32) {
+        self.width.store(width.max(0.0));
+    }
+
+    /// Set how long the right channel is delayed by, usually a few milliseconds (below 40ms, the delay is heard as width rather than as an echo).
+    /// A delay of zero turns the Haas effect off.
+    pub fn set_haas_delay(&self, delay: Duration) {
+        let length = (delay.as_secs_f32() * self.sample_rate as f32) as usize;
+        self.haas_length.store(length, Ordering::Relaxed);
+    }
+}
+
+impl<S> Source for StereoWidener<S>
+where
+    S: Source<Item = f32>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+    fn channels(&self) -> u16 {
+        2
+    }
+    fn sample_rate(&self) -> u32 {
+        self.source.sample_rate()
+    }
+    fn total_duration(&self) -> Option<Duration> {
+        self.source.total_duration()
+    }
+}
+
+impl<S> Iterator for StereoWidener<S>
+where
+    S: Source<Item = f32>,
+{
+    type Item = f32;
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(right) = self.pending_right.take() {
+            return Some(right);
+        }
+        let left = self.source.next()?;
+        let right = self.source.next()?;
+        let mid = (left + right) / 2.0;
+        let side = (left - right) / 2.0 * self.width.load();
+        let mut right = mid - side;
+        self.haas
+            .resize(self.haas_length.load(Ordering::Relaxed), 0.0);
+        if !self.haas.is_empty() {
+            self.haas.push_back(right);
+            right = self.haas.pop_front().unwrap();
+        }
+        self.pending_right = Some(right);
+        Some(mid + side)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rodio::{OutputStream, Sink};
+
+    use crate::audio::sources::PluckedString;
+    use crate::audio::stereo::*;
+    use crate::audio::SAMPLE_RATE;
+
+    /// A single sample at full volume, followed by silence.
+    struct Impulse(bool);
+
+    impl Iterator for Impulse {
+        type Item = f32;
+        fn next(&mut self) -> Option<Self::Item> {
+            let done = std::mem::replace(&mut self.0, true);
+            Some(if done { 0.0 } else { 1.0 })
+        }
+    }
+
+    impl Source for Impulse {
+        fn current_frame_len(&self) -> Option<usize> {
+            None
+        }
+        fn channels(&self) -> u16 {
+            1
+        }
+        fn sample_rate(&self) -> u32 {
+            SAMPLE_RATE
+        }
+        fn total_duration(&self) -> Option<Duration> {
+            None
+        }
+    }
+
+    #[test]
+    fn ping_pong_test() {
+        let source = Impulse(false);
+        let delay = SAMPLE_RATE as usize / 10;
+        let samples: Vec<(f32, f32)> =
+            PingPongDelay::new(source, Duration::from_millis(100), 0.5, 1.0)
+                .take(delay * 3 * 2 + 2)
+                .collect::<Vec<f32>>()
+                .chunks(2)
+                .map(|frame| (frame[0], frame[1]))
+                .collect();
+        assert_eq!(samples[delay], (1.0, 0.0));
+        assert_eq!(samples[delay * 2], (0.0, 0.5));
+        assert_eq!(samples[delay * 3], (0.25, 0.0));
+        let echoes = samples
+            .iter()
+            .filter(|(l, r)| *l != 0.0 || *r != 0.0)
+            .count();
+        assert_eq!(echoes, 3);
+    }
+
+    #[test]
+    fn ping_pong_playback_test() {
+        let (_stream, handle) = OutputStream::try_default().unwrap();
+        let sink = Sink::try_new(&handle).ok().unwrap();
+        let source = PingPongDelay::new(
+            PluckedString::new(220.0),
+            Duration::from_millis(250),
+            0.6,
+            0.5,
+        );
+        sink.set_volume(0.2);
+        sink.append(source);
+        std::thread::sleep(Duration::from_secs(2));
+    }
+
+    #[test]
+    fn stereo_widener_test() {
+        let frames = |width: f32, haas: Duration| -> Vec<(f32, f32)> {
+            let delay = PingPongDelay::new(Impulse(false), Duration::from_millis(10), 0.5, 0.5);
+            let (widener, hook) = StereoWidener::new(delay);
+            hook.set_width(width);
+            hook.set_haas_delay(haas);
+            widener
+                .take(SAMPLE_RATE as usize / 10)
+                .collect::<Vec<f32>>()
+                .chunks(2)
+                .map(|frame| (frame[0], frame[1]))
+                .collect()
+        };
+        assert!(frames(0.0, Duration::ZERO).iter().all(|(l, r)| l == r));
+        assert!(frames(1.0, Duration::ZERO).iter().any(|(l, r)| l != r));
+        let haas = frames(0.0, Duration::from_millis(1));
+        let length = SAMPLE_RATE as usize / 1000;
+        assert_eq!(haas[0], (0.5, 0.0));
+        assert_eq!(haas[length], (0.0, 0.5));
+        // The hook still works once the widener is running, e.g. in a Sink on the audio thread.
+        let (mut widener, hook) = StereoWidener::new(PingPongDelay::new(
+            Impulse(false),
+            Duration::from_millis(10),
+            0.5,
+            0.5,
+        ));
+        assert_eq!((widener.next(), widener.next()), (Some(0.5), Some(0.5)));
+        std::thread::spawn(move || hook.set_width(0.0))
+            .join()
+            .unwrap();
+        assert!(widener
+            .take(SAMPLE_RATE as usize / 10)
+            .collect::<Vec<f32>>()
+            .chunks(2)
+            .all(|frame| frame[0] == frame[1]));
+    }
+}
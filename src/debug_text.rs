@@ -0,0 +1,169 @@
+use sdl2::pixels::Color;
+
+use crate::RetroCanvas;
+
+/// A 5x7 font for the printable ASCII characters, from `' '` to `'~'`. Each glyph is 5 columns, with the top row in the lowest bit.
+const DEBUG_FONT: [[u8; 5]; 95] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00],
+    [0x00, 0x00, 0x5F, 0x00, 0x00],
+    [0x00, 0x07, 0x00, 0x07, 0x00],
+    [0x14, 0x7F, 0x14, 0x7F, 0x14],
+    [0x24, 0x2A, 0x7F, 0x2A, 0x12],
+    [0x23, 0x13, 0x08, 0x64, 0x62],
+    [0x36, 0x49, 0x55, 0x22, 0x50],
+    [0x00, 0x05, 0x03, 0x00, 0x00],
+    [0x00, 0x1C, 0x22, 0x41, 0x00],
+    [0x00, 0x41, 0x22, 0x1C, 0x00],
+    [0x08, 0x2A, 0x1C, 0x2A, 0x08],
+    [0x08, 0x08, 0x3E, 0x08, 0x08],
+    [0x00, 0x50, 0x30, 0x00, 0x00],
+    [0x08, 0x08, 0x08, 0x08, 0x08],
+    [0x00, 0x60, 0x60, 0x00, 0x00],
+    [0x20, 0x10, 0x08, 0x04, 0x02],
+    [0x3E, 0x51, 0x49, 0x45, 0x3E],
+    [0x00, 0x42, 0x7F, 0x40, 0x00],
+    [0x42, 0x61, 0x51, 0x49, 0x46],
+    [0x21, 0x41, 0x45, 0x4B, 0x31],
+    [0x18, 0x14, 0x12, 0x7F, 0x10],
+    [0x27, 0x45, 0x45, 0x45, 0x39],
+    [0x3C, 0x4A, 0x49, 0x49, 0x30],
+    [0x01, 0x71, 0x09, 0x05, 0x03],
+    [0x36, 0x49, 0x49, 0x49, 0x36],
+    [0x06, 0x49, 0x49, 0x29, 0x1E],
+    [0x00, 0x36, 0x36, 0x00, 0x00],
+    [0x00, 0x56, 0x36, 0x00, 0x00],
+    [0x08, 0x14, 0x22, 0x41, 0x00],
+    [0x14, 0x14, 0x14, 0x14, 0x14],
+    [0x00, 0x41, 0x22, 0x14, 0x08],
+    [0x02, 0x01, 0x51, 0x09, 0x06],
+    [0x32, 0x49, 0x79, 0x41, 0x3E],
+    [0x7E, 0x11, 0x11, 0x11, 0x7E],
+    [0x7F, 0x49, 0x49, 0x49, 0x36],
+    [0x3E, 0x41, 0x41, 0x41, 0x22],
+    [0x7F, 0x41, 0x41, 0x22, 0x1C],
+    [0x7F, 0x49, 0x49, 0x49, 0x41],
+    [0x7F, 0x09, 0x09, 0x01, 0x01],
+    [0x3E, 0x41, 0x41, 0x51, 0x32],
+    [0x7F, 0x08, 0x08, 0x08, 0x7F],
+    [0x00, 0x41, 0x7F, 0x41, 0x00],
+    [0x20, 0x40, 0x41, 0x3F, 0x01],
+    [0x7F, 0x08, 0x14, 0x22, 0x41],
+    [0x7F, 0x40, 0x40, 0x40, 0x40],
+    [0x7F, 0x02, 0x04, 0x02, 0x7F],
+    [0x7F, 0x04, 0x08, 0x10, 0x7F],
+    [0x3E, 0x41, 0x41, 0x41, 0x3E],
+    [0x7F, 0x09, 0x09, 0x09, 0x06],
+    [0x3E, 0x41, 0x51, 0x21, 0x5E],
+    [0x7F, 0x09, 0x19, 0x29, 0x46],
+    [0x46, 0x49, 0x49, 0x49, 0x31],
+    [0x01, 0x01, 0x7F, 0x01, 0x01],
+    [0x3F, 0x40, 0x40, 0x40, 0x3F],
+    [0x1F, 0x20, 0x40, 0x20, 0x1F],
+    [0x7F, 0x20, 0x18, 0x20, 0x7F],
+    [0x63, 0x14, 0x08, 0x14, 0x63],
+    [0x03, 0x04, 0x78, 0x04, 0x03],
+    [0x61, 0x51, 0x49, 0x45, 0x43],
+    [0x00, 0x7F, 0x41, 0x41, 0x00],
+    [0x02, 0x04, 0x08, 0x10, 0x20],
+    [0x00, 0x41, 0x41, 0x7F, 0x00],
+    [0x04, 0x02, 0x01, 0x02, 0x04],
+    [0x40, 0x40, 0x40, 0x40, 0x40],
+    [0x00, 0x01, 0x02, 0x04, 0x00],
+    [0x20, 0x54, 0x54, 0x54, 0x78],
+    [0x7F, 0x48, 0x44, 0x44, 0x38],
+    [0x38, 0x44, 0x44, 0x44, 0x20],
+    [0x38, 0x44, 0x44, 0x48, 0x7F],
+    [0x38, 0x54, 0x54, 0x54, 0x18],
+    [0x08, 0x7E, 0x09, 0x01, 0x02],
+    [0x08, 0x14, 0x54, 0x54, 0x3C],
+    [0x7F, 0x08, 0x04, 0x04, 0x78],
+    [0x00, 0x44, 0x7D, 0x40, 0x00],
+    [0x20, 0x40, 0x44, 0x3D, 0x00],
+    [0x00, 0x7F, 0x10, 0x28, 0x44],
+    [0x00, 0x41, 0x7F, 0x40, 0x00],
+    [0x7C, 0x04, 0x18, 0x04, 0x78],
+    [0x7C, 0x08, 0x04, 0x04, 0x78],
+    [0x38, 0x44, 0x44, 0x44, 0x38],
+    [0x7C, 0x14, 0x14, 0x14, 0x08],
+    [0x08, 0x14, 0x14, 0x18, 0x7C],
+    [0x7C, 0x08, 0x04, 0x04, 0x08],
+    [0x48, 0x54, 0x54, 0x54, 0x20],
+    [0x04, 0x3F, 0x44, 0x40, 0x20],
+    [0x3C, 0x40, 0x40, 0x20, 0x7C],
+    [0x1C, 0x20, 0x40, 0x20, 0x1C],
+    [0x3C, 0x40, 0x30, 0x40, 0x3C],
+    [0x44, 0x28, 0x10, 0x28, 0x44],
+    [0x0C, 0x50, 0x50, 0x50, 0x3C],
+    [0x44, 0x64, 0x54, 0x4C, 0x44],
+    [0x00, 0x08, 0x36, 0x41, 0x00],
+    [0x00, 0x00, 0x7F, 0x00, 0x00],
+    [0x00, 0x41, 0x36, 0x08, 0x00],
+    [0x02, 0x01, 0x02, 0x04, 0x02],
+];
+
+/// How far apart the characters and lines of debug text are, in pixels.
+const DEBUG_ADVANCE: (i32, i32) = (6, 8);
+
+/// Returns the glyph of a character, with characters outside of the font shown as `'?'`.
+fn debug_glyph(c: char) -> [u8; 5] {
+    let index = match c {
+        ' '..='~' => c as usize - ' ' as usize,
+        _ => '?' as usize - ' ' as usize,
+    };
+    DEBUG_FONT[index]
+}
+
+impl RetroCanvas {
+    /// Draws text onto the framebuffer with a built-in 5x7 font, with its top left corner at the given position on the simulated screen.
+    /// This is meant for debugging (e.g. FPS counters), so it ignores the camera and needs no font asset.
+    /// Each character is 6 pixels wide and each line 8 pixels tall; `'\n'` starts a new line. Non-ASCII characters are drawn as `'?'`.
+    pub fn draw_debug_text(&mut self, text: &str, x: i32, y: i32, color: Color) {
+        let (x, y) = self.screen_to_world(x, y);
+        let mut points = Vec::new();
+        for (row, line) in text.split('\n').enumerate() {
+            for (column, c) in line.chars().enumerate() {
+                let origin = (
+                    x + column as i32 * DEBUG_ADVANCE.0,
+                    y + row as i32 * DEBUG_ADVANCE.1,
+                );
+                for (glyph_x, bits) in debug_glyph(c).iter().enumerate() {
+                    for glyph_y in (0..7).filter(|bit| bits & (1 << bit) != 0) {
+                        points.push(((origin.0 + glyph_x as i32, origin.1 + glyph_y), color));
+                    }
+                }
+            }
+        }
+        self.put_points(points.into_iter());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sdl2::pixels::Color;
+
+    use crate::tests::SDL;
+    use crate::RetroCanvas;
+
+    #[test]
+    fn debug_text() {
+        let _sdl = SDL.lock().unwrap_or_else(|e| e.into_inner());
+        let mut canvas = RetroCanvas::new_headless((64, 24)).unwrap();
+        let (black, white) = (Color::RGB(0, 0, 0), Color::RGB(255, 255, 255));
+        canvas.clear_buffer(black);
+        canvas.set_camera(100, 100);
+        canvas.draw_debug_text("FPS:60", 2, 2, white);
+        canvas.flip();
+        let screenshot = canvas.screenshot();
+        let pixel = |x: usize, y: usize| screenshot[y * 64 + x];
+        assert!(screenshot.iter().filter(|pixel| **pixel == white).count() > 30);
+        // The stem of the F, and its top bar.
+        for y in 2..9 {
+            assert_eq!(pixel(2, y), white);
+        }
+        assert_eq!(pixel(6, 2), white);
+        assert_eq!(pixel(6, 3), black);
+        // Nothing is drawn outside of the 6 characters.
+        assert!((0..24).all(|y| (38..64).all(|x| pixel(x, y) == black)));
+        assert!((0..64).all(|x| pixel(x, 9) == black));
+    }
+}
@@ -0,0 +1,451 @@
+use std::collections::HashMap;
+
+use sdl2::controller;
+use sdl2::event::Event;
+use sdl2::mouse;
+use sdl2::{EventPump, Sdl};
+
+use crate::{viewport_scale, RetroCanvas};
+
+/// Identifies a controller opened with `RetroCanvas::open_controller`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ControllerId(u32);
+
+/// A button of a controller, named after the layout of an Xbox controller.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Button {
+    A,
+    B,
+    X,
+    Y,
+    Back,
+    Guide,
+    Start,
+    LeftStick,
+    RightStick,
+    LeftShoulder,
+    RightShoulder,
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight,
+}
+
+impl Button {
+    fn from_sdl(button: controller::Button) -> Option<Button> {
+        Some(match button {
+            controller::Button::A => Button::A,
+            controller::Button::B => Button::B,
+            controller::Button::X => Button::X,
+            controller::Button::Y => Button::Y,
+            controller::Button::Back => Button::Back,
+            controller::Button::Guide => Button::Guide,
+            controller::Button::Start => Button::Start,
+            controller::Button::LeftStick => Button::LeftStick,
+            controller::Button::RightStick => Button::RightStick,
+            controller::Button::LeftShoulder => Button::LeftShoulder,
+            controller::Button::RightShoulder => Button::RightShoulder,
+            controller::Button::DPadUp => Button::DPadUp,
+            controller::Button::DPadDown => Button::DPadDown,
+            controller::Button::DPadLeft => Button::DPadLeft,
+            controller::Button::DPadRight => Button::DPadRight,
+            _ => return None,
+        })
+    }
+
+    fn to_sdl(self) -> controller::Button {
+        match self {
+            Button::A => controller::Button::A,
+            Button::B => controller::Button::B,
+            Button::X => controller::Button::X,
+            Button::Y => controller::Button::Y,
+            Button::Back => controller::Button::Back,
+            Button::Guide => controller::Button::Guide,
+            Button::Start => controller::Button::Start,
+            Button::LeftStick => controller::Button::LeftStick,
+            Button::RightStick => controller::Button::RightStick,
+            Button::LeftShoulder => controller::Button::LeftShoulder,
+            Button::RightShoulder => controller::Button::RightShoulder,
+            Button::DPadUp => controller::Button::DPadUp,
+            Button::DPadDown => controller::Button::DPadDown,
+            Button::DPadLeft => controller::Button::DPadLeft,
+            Button::DPadRight => controller::Button::DPadRight,
+        }
+    }
+}
+
+/// An analog axis of a controller.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Axis {
+    LeftX,
+    LeftY,
+    RightX,
+    RightY,
+    TriggerLeft,
+    TriggerRight,
+}
+
+impl Axis {
+    fn from_sdl(axis: controller::Axis) -> Axis {
+        match axis {
+            controller::Axis::LeftX => Axis::LeftX,
+            controller::Axis::LeftY => Axis::LeftY,
+            controller::Axis::RightX => Axis::RightX,
+            controller::Axis::RightY => Axis::RightY,
+            controller::Axis::TriggerLeft => Axis::TriggerLeft,
+            controller::Axis::TriggerRight => Axis::TriggerRight,
+        }
+    }
+}
+
+/// A mouse button.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum MouseButton {
+    Left,
+    Middle,
+    Right,
+    X1,
+    X2,
+}
+
+impl MouseButton {
+    fn from_sdl(button: mouse::MouseButton) -> Option<MouseButton> {
+        Some(match button {
+            mouse::MouseButton::Left => MouseButton::Left,
+            mouse::MouseButton::Middle => MouseButton::Middle,
+            mouse::MouseButton::Right => MouseButton::Right,
+            mouse::MouseButton::X1 => MouseButton::X1,
+            mouse::MouseButton::X2 => MouseButton::X2,
+            mouse::MouseButton::Unknown => return None,
+        })
+    }
+}
+
+/// Converts a position in the window to a position on the simulated screen, undoing the scaling and letterboxing.
+/// Returns None if the position is in the margins around the simulated screen.
+fn window_to_simulated(
+    window_size: (u32, u32),
+    simulated_size: (u32, u32),
+    integer_scale: bool,
+    position: (i32, i32),
+) -> Option<(i32, i32)> {
+    let scale = viewport_scale(window_size, simulated_size, integer_scale);
+    let margin = (
+        (window_size.0 as f32 - simulated_size.0 as f32 * scale) / 2.0,
+        (window_size.1 as f32 - simulated_size.1 as f32 * scale) / 2.0,
+    );
+    let x = ((position.0 as f32 - margin.0) / scale).floor() as i32;
+    let y = ((position.1 as f32 - margin.1) / scale).floor() as i32;
+    inside_simulated(simulated_size, (x, y))
+}
+
+/// Returns the position if it is on the simulated screen, and None otherwise.
+fn inside_simulated(simulated_size: (u32, u32), position: (i32, i32)) -> Option<(i32, i32)> {
+    let (x, y) = position;
+    if x < 0 || y < 0 || x >= simulated_size.0 as i32 || y >= simulated_size.1 as i32 {
+        return None;
+    }
+    Some(position)
+}
+
+/// An input event, as returned by `RetroCanvas::poll_events`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RetroEvent {
+    /// The window was closed.
+    Quit,
+    /// A button of an opened controller was pressed or released.
+    ControllerButton {
+        controller: ControllerId,
+        button: Button,
+        pressed: bool,
+    },
+    /// An axis of an opened controller moved. The value is between -1 and 1 for the sticks, and between 0 and 1 for the triggers.
+    ControllerAxis {
+        controller: ControllerId,
+        axis: Axis,
+        value: f32,
+    },
+    /// A mouse button was pressed or released. The position is on the simulated screen, or None if the mouse is in the margins around it.
+    MouseButton {
+        button: MouseButton,
+        pressed: bool,
+        position: Option<(i32, i32)>,
+    },
+    /// The mouse moved. The position is on the simulated screen, or None if the mouse is in the margins around it.
+    MouseMotion { position: Option<(i32, i32)> },
+    /// A key was pressed or released. The name is SDL2's name for the key in the current layout, e.g. "A", "Space" or "Left Shift".
+    /// Holding a key down sends repeated presses, which have `repeat` set.
+    Key {
+        name: String,
+        pressed: bool,
+        repeat: bool,
+    },
+    /// Any other event, such as the window being resized.
+    Other,
+}
+
+impl RetroEvent {
+    /// Converts an SDL2 event. SDL2 already maps mouse positions to the simulated screen, but not clicks outside of it.
+    fn from_sdl(event: Event, simulated_size: (u32, u32)) -> RetroEvent {
+        let (which, button, pressed) = match event {
+            Event::Quit { .. } => return RetroEvent::Quit,
+            Event::MouseMotion { x, y, .. } => {
+                return RetroEvent::MouseMotion {
+                    position: inside_simulated(simulated_size, (x, y)),
+                }
+            }
+            Event::MouseButtonDown {
+                mouse_btn, x, y, ..
+            }
+            | Event::MouseButtonUp {
+                mouse_btn, x, y, ..
+            } => {
+                return match MouseButton::from_sdl(mouse_btn) {
+                    Some(button) => RetroEvent::MouseButton {
+                        button,
+                        pressed: matches!(event, Event::MouseButtonDown { .. }),
+                        position: inside_simulated(simulated_size, (x, y)),
+                    },
+                    None => RetroEvent::Other,
+                }
+            }
+            Event::ControllerButtonDown { which, button, .. } => (which, button, true),
+            Event::ControllerButtonUp { which, button, .. } => (which, button, false),
+            Event::ControllerAxisMotion {
+                which, axis, value, ..
+            } => {
+                return RetroEvent::ControllerAxis {
+                    controller: ControllerId(which),
+                    axis: Axis::from_sdl(axis),
+                    value: (value as f32 / i16::MAX as f32).max(-1.0),
+                }
+            }
+            Event::KeyDown {
+                keycode: Some(keycode),
+                repeat,
+                ..
+            }
+            | Event::KeyUp {
+                keycode: Some(keycode),
+                repeat,
+                ..
+            } => {
+                return RetroEvent::Key {
+                    name: keycode.name(),
+                    pressed: matches!(event, Event::KeyDown { .. }),
+                    repeat,
+                }
+            }
+            _ => return RetroEvent::Other,
+        };
+        match Button::from_sdl(button) {
+            Some(button) => RetroEvent::ControllerButton {
+                controller: ControllerId(which),
+                button,
+                pressed,
+            },
+            None => RetroEvent::Other,
+        }
+    }
+}
+
+/// The events of the canvases created from the same `RetroContext`, which share its event pump since SDL only allows one.
+/// Polled events are queued by the window they happened in, until its canvas reads them.
+#[derive(Default)]
+pub(crate) struct SharedEvents {
+    pump: Option<EventPump>,
+    queues: HashMap<u32, Vec<Event>>,
+}
+
+impl SharedEvents {
+    /// Starts queueing the events of the given window.
+    pub(crate) fn register(&mut self, window_id: u32) {
+        self.queues.insert(window_id, Vec::new());
+    }
+
+    /// Stops queueing the events of the given window, e.g. once its canvas is dropped.
+    pub(crate) fn unregister(&mut self, window_id: u32) {
+        self.queues.remove(&window_id);
+    }
+
+    /// Polls SDL, and returns the events queued for the given window.
+    /// Events which don't belong to a window (e.g. quitting, or controllers) are queued for every window.
+    fn take(&mut self, sdl: &Sdl, window_id: u32) -> Vec<Event> {
+        let pump = self.pump.get_or_insert_with(|| sdl.event_pump().unwrap());
+        for event in pump.poll_iter() {
+            match event.get_window_id() {
+                Some(id) => {
+                    if let Some(queue) = self.queues.get_mut(&id) {
+                        queue.push(event);
+                    }
+                }
+                None => {
+                    for queue in self.queues.values_mut() {
+                        queue.push(event.clone());
+                    }
+                }
+            }
+        }
+        self.queues
+            .get_mut(&window_id)
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+}
+
+impl RetroCanvas {
+    /// Returns every event that happened since the last call. With several canvases created from the same `RetroContext`,
+    /// each one only receives the events of its own window, along with those which don't belong to a window (e.g. quitting, or controllers).
+    pub fn poll_events(&mut self) -> Vec<RetroEvent> {
+        let simulated_size = self.simulated_size;
+        let sdl = self.interior.window().subsystem().sdl();
+        let window_id = self.interior.window().id();
+        let events: Vec<RetroEvent> = self
+            .events
+            .borrow_mut()
+            .take(&sdl, window_id)
+            .into_iter()
+            .map(|event| RetroEvent::from_sdl(event, simulated_size))
+            .collect();
+        for event in &events {
+            if let RetroEvent::MouseMotion {
+                position: Some(position),
+            } = event
+            {
+                self.mouse_position = *position;
+            }
+        }
+        events
+    }
+
+    /// The last position of the mouse on the simulated screen, as of the last `poll_events`.
+    /// When the mouse leaves the simulated screen, this stays at the last position it had on it.
+    pub fn mouse_position(&self) -> (i32, i32) {
+        self.mouse_position
+    }
+
+    /// Converts a position in the window (e.g. from an SDL2 event that RetroCore doesn't convert) to a position on the simulated screen.
+    /// Returns None if the position is in the margins around the simulated screen.
+    pub fn window_to_simulated(&self, x: i32, y: i32) -> Option<(i32, i32)> {
+        window_to_simulated(
+            self.interior.window().size(),
+            self.simulated_size,
+            self.interior.integer_scale(),
+            (x, y),
+        )
+    }
+
+    /// Opens the controller with the given index (from 0 to the amount of connected controllers), so that its events show up in `poll_events`.
+    /// Returns an error if there is no controller with that index.
+    pub fn open_controller(&mut self, index: u32) -> Result<ControllerId, String> {
+        let subsystem = self.interior.window().subsystem().sdl().game_controller()?;
+        let available = subsystem.num_joysticks()?;
+        if index >= available {
+            return Err(format!(
+                "There is no controller with index {}, only {} are connected!",
+                index, available
+            ));
+        }
+        if !subsystem.is_game_controller(index) {
+            return Err(format!(
+                "The joystick with index {} isn't a supported controller!",
+                index
+            ));
+        }
+        let controller = subsystem.open(index).map_err(|e| e.to_string())?;
+        let id = ControllerId(controller.instance_id());
+        self.controllers.push(controller);
+        Ok(id)
+    }
+
+    /// Returns whether the given button of the given controller is currently held down. Returns false for controllers that aren't open.
+    pub fn is_button_down(&self, id: ControllerId, button: Button) -> bool {
+        self.controllers.iter().any(|controller| {
+            controller.instance_id() == id.0 && controller.button(button.to_sdl())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::input::{window_to_simulated, Button, ControllerId, RetroEvent};
+    use crate::tests::SDL;
+    use crate::RetroCanvas;
+
+    #[test]
+    fn no_controller() {
+        let _sdl = SDL.lock().unwrap_or_else(|e| e.into_inner());
+        let mut canvas = RetroCanvas::new_headless((32, 24)).unwrap();
+        // No machine has that many controllers connected.
+        assert!(canvas.open_controller(1000).is_err());
+        assert!(!canvas.is_button_down(ControllerId(0), Button::A));
+    }
+
+    #[test]
+    fn mouse_mapping() {
+        // 320x240 in 1000x600 is scaled by 2.5, with 100 pixel margins on the left and right.
+        assert_eq!(
+            window_to_simulated((1000, 600), (320, 240), false, (500, 300)),
+            Some((160, 120))
+        );
+        assert_eq!(
+            window_to_simulated((1000, 600), (320, 240), false, (100, 0)),
+            Some((0, 0))
+        );
+        assert_eq!(
+            window_to_simulated((1000, 600), (320, 240), false, (50, 300)),
+            None
+        );
+        assert_eq!(
+            window_to_simulated((1000, 600), (320, 240), false, (950, 300)),
+            None
+        );
+        // With integer scaling, the scale is 2, with 180 and 60 pixel margins.
+        assert_eq!(
+            window_to_simulated((1000, 600), (320, 240), true, (500, 300)),
+            Some((160, 120))
+        );
+        assert_eq!(
+            window_to_simulated((1000, 600), (320, 240), true, (180, 60)),
+            Some((0, 0))
+        );
+        assert_eq!(
+            window_to_simulated((1000, 600), (320, 240), true, (179, 60)),
+            None
+        );
+    }
+
+    #[test]
+    fn key_mapping() {
+        use sdl2::event::Event;
+        use sdl2::keyboard::{Keycode, Mod, Scancode};
+        let key_down = Event::KeyDown {
+            timestamp: 0,
+            window_id: 0,
+            keycode: Some(Keycode::Space),
+            scancode: Some(Scancode::Space),
+            keymod: Mod::NOMOD,
+            repeat: true,
+        };
+        assert_eq!(
+            RetroEvent::from_sdl(key_down, (32, 24)),
+            RetroEvent::Key {
+                name: "Space".to_string(),
+                pressed: true,
+                repeat: true
+            }
+        );
+        let unknown_key = Event::KeyUp {
+            timestamp: 0,
+            window_id: 0,
+            keycode: None,
+            scancode: None,
+            keymod: Mod::NOMOD,
+            repeat: false,
+        };
+        assert_eq!(
+            RetroEvent::from_sdl(unknown_key, (32, 24)),
+            RetroEvent::Other
+        );
+    }
+}
@@ -0,0 +1,142 @@
+use std::ops::{Deref, DerefMut};
+
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::rect::Rect;
+use sdl2::render::{BlendMode, Canvas};
+use sdl2::video::Window;
+
+use crate::{own_texture, OwnedTexture, RetroCanvas};
+
+/// Identifies a layer of a RetroCanvas. Created by `RetroCanvas::add_layer`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct LayerId(usize);
+
+/// A layer, which is a transparent texture of the simulated screen's size that gets composited onto the simulated screen.
+pub(crate) struct Layer {
+    texture: OwnedTexture,
+    opacity: f32,
+    offset: (i32, i32),
+}
+
+/// Drawing access to a layer, given by `RetroCanvas::with_layer`. Derefs down to a Canvas, whose target is the layer.
+pub struct LayerDraw<'a> {
+    canvas: &'a mut Canvas<Window>,
+}
+
+impl LayerDraw<'_> {
+    /// Clears the whole layer back to transparent, keeping the draw color.
+    pub fn clear_layer(&mut self) {
+        let color = self.canvas.draw_color();
+        self.canvas.set_draw_color(Color::RGBA(0, 0, 0, 0));
+        self.canvas.clear();
+        self.canvas.set_draw_color(color);
+    }
+}
+
+impl Deref for LayerDraw<'_> {
+    type Target = Canvas<Window>;
+
+    fn deref(&self) -> &Self::Target {
+        self.canvas
+    }
+}
+
+impl DerefMut for LayerDraw<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.canvas
+    }
+}
+
+impl RetroCanvas {
+    /// Adds a new, fully transparent layer on top of the existing ones.
+    /// Layers are drawn to with `with_layer`, and drawn onto the simulated screen in the order they were added with `composite`.
+    pub fn add_layer(&mut self) -> LayerId {
+        let (width, height) = self.simulated_size;
+        let mut texture = self
+            .texture_creator
+            .create_texture_target(PixelFormatEnum::RGBA8888, width, height)
+            .unwrap();
+        texture.set_blend_mode(BlendMode::Blend);
+        self.layers.push(Layer {
+            // SAFETY: the texture is only ever stored in the layers of the canvas it was created from.
+            texture: unsafe { own_texture(texture) },
+            opacity: 1.0,
+            offset: (0, 0),
+        });
+        let id = LayerId(self.layers.len() - 1);
+        self.with_layer(id, |layer| layer.clear_layer());
+        id
+    }
+
+    /// Draws onto the given layer. Everything drawn inside the closure goes to the layer instead of the simulated screen.
+    pub fn with_layer(&mut self, id: LayerId, f: impl FnOnce(&mut LayerDraw)) {
+        let layer = &mut self.layers[id.0];
+        self.interior
+            .with_texture_canvas(&mut layer.texture, |canvas| f(&mut LayerDraw { canvas }))
+            .unwrap();
+    }
+
+    /// Sets the opacity of the given layer, between 0 (invisible) and 1 (opaque).
+    pub fn set_layer_opacity(&mut self, id: LayerId, opacity: f32) {
+        self.layers[id.0].opacity = opacity.clamp(0.0, 1.0);
+    }
+
+    /// Sets the offset the given layer is drawn at, e.g. to scroll it at its own rate for parallax.
+    pub fn set_layer_offset(&mut self, id: LayerId, x: i32, y: i32) {
+        self.layers[id.0].offset = (x, y);
+    }
+
+    /// Draws every layer onto the simulated screen, in the order they were added, with their opacity and offset.
+    /// Call this after drawing the frame and before presenting it.
+    pub fn composite(&mut self) {
+        let (width, height) = self.simulated_size;
+        for layer in &mut self.layers {
+            layer
+                .texture
+                .set_alpha_mod((layer.opacity * 255.0).round() as u8);
+            self.interior
+                .copy(
+                    &layer.texture,
+                    None,
+                    Rect::new(layer.offset.0, layer.offset.1, width, height),
+                )
+                .unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sdl2::pixels::{Color, PixelFormatEnum};
+    use sdl2::rect::Rect;
+
+    use crate::RetroCanvas;
+
+    #[test]
+    #[cfg_attr(not(feature = "window-tests"), ignore)]
+    fn layers() {
+        // The same real and simulated size, so that the window can be read back pixel for pixel.
+        let mut canvas = RetroCanvas::new((320, 240), (320, 240), "Test");
+        let background = canvas.add_layer();
+        let foreground = canvas.add_layer();
+        canvas.with_layer(background, |layer| {
+            layer.set_draw_color(Color::RGB(255, 0, 0));
+            layer.clear();
+        });
+        canvas.with_layer(foreground, |layer| {
+            layer.set_draw_color(Color::RGB(0, 0, 255));
+            layer.fill_rect(Rect::new(0, 0, 10, 10)).unwrap();
+        });
+        canvas.set_layer_offset(foreground, 5, 5);
+        canvas.composite();
+        let pixels = canvas.read_pixels(None, PixelFormatEnum::RGBA32).unwrap();
+        let pixel = |x: usize, y: usize| {
+            let offset = (y * 320 + x) * 4;
+            Color::RGB(pixels[offset], pixels[offset + 1], pixels[offset + 2])
+        };
+        assert_eq!(pixel(0, 0), Color::RGB(255, 0, 0));
+        assert_eq!(pixel(7, 7), Color::RGB(0, 0, 255));
+        assert_eq!(pixel(100, 100), Color::RGB(255, 0, 0));
+        canvas.present();
+    }
+}
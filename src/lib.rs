@@ -1,17 +1,441 @@
 extern crate core;
 
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::ops::{Deref, DerefMut};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
 
-use sdl2::pixels::Color;
+use sdl2::controller::GameController;
+use sdl2::hint::Hint;
+use sdl2::pixels::{Color, PixelFormatEnum};
 use sdl2::rect::Rect;
+use sdl2::render::BlendMode;
 use sdl2::render::Canvas;
-use sdl2::video::Window;
+use sdl2::render::{Texture, TextureCreator};
+use sdl2::video::{SwapInterval, Window, WindowContext};
+use sdl2::VideoSubsystem;
+
+use crate::input::{RetroEvent, SharedEvents};
 
 pub mod audio;
+mod debug_text;
+pub mod input;
+pub mod layer;
+mod palette;
+mod recording;
 pub mod tokenizing;
 
+/// Converts a color in HSV to an SDL color.
+/// The hue is in degrees (wrapping around at 360), and the saturation and value are between 0 and 1.
+/// Pairs well with `audio::freq_to_hue` to color things by pitch.
+pub fn hsv_to_color(h: f32, s: f32, v: f32) -> Color {
+    let h = h.rem_euclid(360.0) / 60.0;
+    let chroma = v * s;
+    let x = chroma * (1.0 - (h % 2.0 - 1.0).abs());
+    let (r, g, b) = match h as u32 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+    let m = v - chroma;
+    let to_channel = |c: f32| ((c + m) * 255.0).round() as u8;
+    Color::RGB(to_channel(r), to_channel(g), to_channel(b))
+}
+
+/// Blends a color onto another, with the same formulas SDL uses for its blend modes.
+fn blend(source: Color, destination: Color, mode: BlendMode) -> Color {
+    let alpha = source.a as f32 / 255.0;
+    let channel = |source: u8, destination: u8| {
+        let (source, destination) = (source as f32, destination as f32);
+        let result = match mode {
+            BlendMode::Blend => source * alpha + destination * (1.0 - alpha),
+            BlendMode::Add => source * alpha + destination,
+            BlendMode::Mod => source * destination / 255.0,
+            BlendMode::Mul => source * destination / 255.0 + destination * (1.0 - alpha),
+            BlendMode::None | BlendMode::Invalid => source,
+        };
+        result.round().min(255.0) as u8
+    };
+    let a = match mode {
+        BlendMode::Blend => (source.a as f32 + destination.a as f32 * (1.0 - alpha)).round() as u8,
+        BlendMode::None | BlendMode::Invalid => source.a,
+        _ => destination.a,
+    };
+    Color::RGBA(
+        channel(source.r, destination.r),
+        channel(source.g, destination.g),
+        channel(source.b, destination.b),
+        a,
+    )
+}
+
+/// A rectangular image which can be drawn onto a RetroCanvas' framebuffer.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sprite {
+    width: u32,
+    height: u32,
+    pixels: Vec<Color>,
+}
+
+impl Sprite {
+    /// Creates a new sprite with the given size. The pixels are given row by row, starting from the top left.
+    /// Panics if the amount of pixels doesn't match the size.
+    pub fn new(width: u32, height: u32, pixels: Vec<Color>) -> Sprite {
+        if pixels.len() != (width * height) as usize {
+            panic!(
+                "A {}x{} sprite needs {} pixels, but {} were given!",
+                width,
+                height,
+                width * height,
+                pixels.len()
+            );
+        }
+        Sprite {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    /// The width of the sprite.
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    /// The height of the sprite.
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+}
+
+/// The tile index of empty cells in a TileMap, which are skipped when drawing.
+pub const EMPTY_TILE: u16 = u16::MAX;
+
+/// A grid of tiles, which are drawn from a tileset with `RetroCanvas::draw_tilemap`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TileMap {
+    width: u32,
+    height: u32,
+    tile_size: (u32, u32),
+    tiles: Vec<u16>,
+}
+
+impl TileMap {
+    /// Creates a new tilemap of the given size (in tiles), with every cell empty.
+    /// The tile size is the size (in pixels) of a single tile in the tileset. Panics if it is zero in either direction.
+    pub fn new(width: u32, height: u32, tile_size: (u32, u32)) -> TileMap {
+        if tile_size.0 == 0 || tile_size.1 == 0 {
+            panic!(
+                "Tiles can't be empty, but the tile size is {}x{}!",
+                tile_size.0, tile_size.1
+            );
+        }
+        TileMap {
+            width,
+            height,
+            tile_size,
+            tiles: vec![EMPTY_TILE; (width * height) as usize],
+        }
+    }
+
+    /// The width of the map, in tiles.
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    /// The height of the map, in tiles.
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// The size of a single tile, in pixels.
+    pub fn tile_size(&self) -> (u32, u32) {
+        self.tile_size
+    }
+
+    /// Returns the tile at the given cell, or None if the cell is outside the map.
+    pub fn get(&self, x: u32, y: u32) -> Option<u16> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        Some(self.tiles[(y * self.width + x) as usize])
+    }
+
+    /// Sets the tile at the given cell. Tiles are numbered row by row in the tileset, starting from the top left.
+    /// Panics if the cell is outside the map.
+    pub fn set(&mut self, x: u32, y: u32, tile: u16) {
+        if x >= self.width || y >= self.height {
+            panic!(
+                "The cell ({}, {}) is outside of the {}x{} tilemap!",
+                x, y, self.width, self.height
+            );
+        }
+        self.tiles[(y * self.width + x) as usize] = tile;
+    }
+}
+
+/// A sprite containing many images (e.g. all of a game's graphics), which are drawn by name with `RetroCanvas::draw_atlas_region`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SpriteAtlas {
+    sprite: Sprite,
+    regions: HashMap<String, Rect>,
+}
+
+impl SpriteAtlas {
+    /// Creates a new atlas from the given sprite, without any regions.
+    pub fn new(sprite: Sprite) -> SpriteAtlas {
+        SpriteAtlas {
+            sprite,
+            regions: HashMap::new(),
+        }
+    }
+
+    /// Names a rectangle of the atlas' sprite, replacing the region which had that name, if any.
+    /// Panics if the rectangle isn't entirely inside the sprite.
+    pub fn add_region(&mut self, name: &str, rect: Rect) {
+        if rect.left() < 0
+            || rect.top() < 0
+            || rect.right() > self.sprite.width as i32
+            || rect.bottom() > self.sprite.height as i32
+        {
+            panic!(
+                "The region {:?} ({:?}) is outside of the {}x{} atlas!",
+                name, rect, self.sprite.width, self.sprite.height
+            );
+        }
+        self.regions.insert(name.to_string(), rect);
+    }
+
+    /// Returns the rectangle of the region with the given name, or None if there is none.
+    pub fn region(&self, name: &str) -> Option<Rect> {
+        self.regions.get(name).copied()
+    }
+
+    /// The sprite containing the regions.
+    pub fn sprite(&self) -> &Sprite {
+        &self.sprite
+    }
+}
+
+/// The factor by which the simulated screen is scaled up to fit in the window, keeping its aspect ratio.
+pub(crate) fn viewport_scale(
+    window_size: (u32, u32),
+    simulated_size: (u32, u32),
+    integer_scale: bool,
+) -> f32 {
+    let scale = (window_size.0 as f32 / simulated_size.0 as f32)
+        .min(window_size.1 as f32 / simulated_size.1 as f32);
+    if integer_scale {
+        scale.floor().max(1.0)
+    } else {
+        scale
+    }
+}
+
+/// The part of the window showing the scaled simulated screen, centered in it, in window pixels.
+fn viewport_rect(window_size: (u32, u32), simulated_size: (u32, u32), integer_scale: bool) -> Rect {
+    let scale = viewport_scale(window_size, simulated_size, integer_scale);
+    let (width, height) = window_size;
+    let viewport_width = ((simulated_size.0 as f32 * scale) as u32).min(width);
+    let viewport_height = ((simulated_size.1 as f32 * scale) as u32).min(height);
+    Rect::new(
+        ((width - viewport_width) / 2) as i32,
+        ((height - viewport_height) / 2) as i32,
+        viewport_width,
+        viewport_height,
+    )
+}
+
+/// The parts of the window around the scaled simulated screen (the letterbox or pillarbox), in window pixels.
+fn border_rects(
+    window_size: (u32, u32),
+    simulated_size: (u32, u32),
+    integer_scale: bool,
+) -> Vec<Rect> {
+    let viewport = viewport_rect(window_size, simulated_size, integer_scale);
+    let (width, height) = window_size;
+    let (x, y) = (viewport.x() as u32, viewport.y() as u32);
+    let (viewport_width, viewport_height) = (viewport.width(), viewport.height());
+    [
+        (0, 0, width, y),
+        (0, y + viewport_height, width, height - y - viewport_height),
+        (0, y, x, viewport_height),
+        (
+            x + viewport_width,
+            y,
+            width - x - viewport_width,
+            viewport_height,
+        ),
+    ]
+    .iter()
+    .filter(|(_, _, w, h)| *w > 0 && *h > 0)
+    .map(|(x, y, w, h)| Rect::new(*x as i32, *y as i32, *w, *h))
+    .collect()
+}
+
+/// The points of the first octant of a circle, from (radius, 0) up to the diagonal, with the midpoint circle algorithm.
+/// Mirroring them gives the whole circle.
+fn circle_octant(radius: i32) -> Vec<(i32, i32)> {
+    let mut points = Vec::new();
+    let (mut x, mut y) = (radius, 0);
+    let mut error = 1 - radius;
+    while x >= y {
+        points.push((x, y));
+        y += 1;
+        if error < 0 {
+            error += 2 * y + 1;
+        } else {
+            x -= 1;
+            error += 2 * (y - x) + 1;
+        }
+    }
+    points
+}
+
+/// A 4x4 Bayer matrix, giving the order in which the pixels of each 4x4 cell switch colors when dithering.
+const BAYER_MATRIX: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+/// Interpolates linearly between two colors, including their alpha.
+fn lerp_color(from: Color, to: Color, t: f32) -> Color {
+    let lerp = |from: u8, to: u8| (from as f32 + (to as f32 - from as f32) * t).round() as u8;
+    Color::RGBA(
+        lerp(from.r, to.r),
+        lerp(from.g, to.g),
+        lerp(from.b, to.b),
+        lerp(from.a, to.a),
+    )
+}
+
+const SCALE_QUALITY_HINT: &str = "SDL_RENDER_SCALE_QUALITY";
+const VIDEO_DRIVER_HINT: &str = "SDL_VIDEODRIVER";
+
+type PresentHook = Box<dyn FnMut(&mut Canvas<Window>, Rect)>;
+
+/// A texture created by a RetroCanvas' `texture_creator` and stored in that canvas. The canvas can't borrow from its own field,
+/// so the lifetime tying the texture to its creator is erased instead, and the field order keeps the creator alive for longer.
+pub(crate) type OwnedTexture = Texture<'static>;
+
+/// Erases the lifetime of a texture, to store it in a RetroCanvas.
+///
+/// # Safety
+/// The texture must have been created by the canvas' `texture_creator`, and be stored in a field of the canvas declared before it,
+/// so that it is destroyed while the renderer is still alive. It must never be handed out with the erased lifetime.
+pub(crate) unsafe fn own_texture(texture: Texture<'_>) -> OwnedTexture {
+    std::mem::transmute::<Texture<'_>, OwnedTexture>(texture)
+}
+
+/// The time between two updates in `RetroCanvas::run_loop`.
+pub const FIXED_TIMESTEP: Duration = Duration::from_nanos(1_000_000_000 / 60);
+/// The most time `run_loop` catches up on in a single frame, so that a long stall doesn't cause a burst of updates.
+const MAX_FRAME_TIME: Duration = Duration::from_millis(250);
+
+/// The SDL context which canvases are created from, for opening several windows (e.g. one per monitor for split-screen).
+/// `RetroCanvas::new` creates its own, so this is only needed for more than one canvas.
+/// The canvases share a single event pump, and each one's `poll_events` only receives the events of its own window.
+pub struct RetroContext {
+    video: VideoSubsystem,
+    headless: bool,
+    events: Rc<RefCell<SharedEvents>>,
+}
+
+impl RetroContext {
+    /// Initializes SDL and its video subsystem.
+    pub fn new() -> Result<RetroContext, String> {
+        let sdl_context = sdl2::init()?;
+        Ok(RetroContext {
+            video: sdl_context.video()?,
+            headless: false,
+            events: Rc::default(),
+        })
+    }
+
+    /// Like `new`, but the canvases created from it are headless (see `RetroCanvas::new_headless`).
+    pub fn new_headless() -> Result<RetroContext, String> {
+        // SDL picks its video driver when the video subsystem starts, so the hint only needs to be overridden until then.
+        // Overriding it also takes precedence over the environment variable of the same name, which is left alone.
+        let previous_driver = sdl2::hint::get(VIDEO_DRIVER_HINT);
+        sdl2::hint::set_with_priority(VIDEO_DRIVER_HINT, "dummy", &Hint::Override);
+        let video_subsystem = sdl2::init().and_then(|sdl_context| sdl_context.video());
+        sdl2::hint::set_with_priority(
+            VIDEO_DRIVER_HINT,
+            previous_driver.as_deref().unwrap_or(""),
+            &Hint::Override,
+        );
+        Ok(RetroContext {
+            video: video_subsystem?,
+            headless: true,
+            events: Rc::default(),
+        })
+    }
+
+    /// Creates a new RetroCanvas in its own window. The sizes and the title are the same as for `RetroCanvas::new`.
+    /// The window is centered, and can be moved (e.g. to another monitor) through the canvas' `window_mut`.
+    /// Presenting waits for vsync; use `create_canvas_ex` to turn that off.
+    pub fn create_canvas(
+        &self,
+        real_size: (u32, u32),
+        simulated_size: (u32, u32),
+        title: &str,
+    ) -> Result<RetroCanvas, String> {
+        self.create_canvas_ex(real_size, simulated_size, title, true)
+    }
+
+    /// Like `create_canvas`, but chooses whether presenting waits for vsync.
+    /// Without vsync, frames are shown as soon as they are presented, which can tear, but doesn't hold `run_loop` back to the display's refresh rate.
+    pub fn create_canvas_ex(
+        &self,
+        real_size: (u32, u32),
+        simulated_size: (u32, u32),
+        title: &str,
+        present_vsync: bool,
+    ) -> Result<RetroCanvas, String> {
+        let mut window = self.video.window(title, real_size.0, real_size.1);
+        if self.headless {
+            window.hidden();
+        } else {
+            window.position_centered();
+        }
+        let mut interior = window.build().map_err(|e| e.to_string())?.into_canvas();
+        if self.headless {
+            interior = interior.software();
+        }
+        if present_vsync {
+            interior = interior.present_vsync();
+        }
+        RetroCanvas::with_interior(
+            interior.build().map_err(|e| e.to_string())?,
+            simulated_size,
+            self.events.clone(),
+        )
+    }
+}
+
 pub struct RetroCanvas {
     simulated_size: (u32, u32),
+    buffer: Vec<Color>,
+    indices: Vec<Option<u8>>,
+    palette: Vec<Color>,
+    dirty: Option<Rect>,
+    // The textures must stay declared before `texture_creator`, so that they are dropped while it keeps the renderer alive.
+    texture: OwnedTexture,
+    layers: Vec<layer::Layer>,
+    texture_creator: TextureCreator<WindowContext>,
+    camera: (i32, i32),
+    blend_mode: BlendMode,
+    nearest_scaling: bool,
+    frame_count: u64,
+    last_present: Instant,
+    delta_time: Duration,
+    events: Rc<RefCell<SharedEvents>>,
+    controllers: Vec<GameController>,
+    mouse_position: (i32, i32),
+    border_color: Color,
+    recording: Option<recording::Recording>,
+    present_hook: Option<PresentHook>,
     interior: Canvas<Window>,
 }
 
@@ -29,33 +453,124 @@ impl DerefMut for RetroCanvas {
     }
 }
 
+impl Drop for RetroCanvas {
+    fn drop(&mut self) {
+        self.events
+            .borrow_mut()
+            .unregister(self.interior.window().id());
+    }
+}
+
 impl RetroCanvas {
     /// Creates a new RetroCanvas with the given real size, simulated size, and title.
     /// The simulated size is the size of your console's display, and is what you write to.
-    /// The real size is the size of the window. If the ratios are different, parts of the real window will be whatever color you clear with, by default black.
+    /// The real size is the size of the window. If the ratios are different, the parts of the real window around the simulated screen are filled with the border color
+    /// (see `set_border_color`) when presenting, by default black.
     /// The title is the title of the window.
     /// If you wish to force integer scaling, call `set_integer_scaling(true)` after creating the canvas.
+    /// To open several windows, create them from a shared `RetroContext` instead.
     pub fn new(real_size: (u32, u32), simulated_size: (u32, u32), title: &str) -> RetroCanvas {
-        let sdl_context = sdl2::init().unwrap();
-        let video_subsystem = sdl_context.video().unwrap();
+        RetroContext::new()
+            .unwrap()
+            .create_canvas(real_size, simulated_size, title)
+            .unwrap()
+    }
 
-        let window = video_subsystem
-            .window(title, real_size.0, real_size.1)
-            .position_centered()
-            .build()
-            .unwrap();
+    /// Creates a new RetroCanvas which doesn't open a visible window, e.g. for tests on machines without a display.
+    /// It uses SDL's dummy video driver and software renderer, and supports the same drawing API as a regular canvas,
+    /// with the real size being the same as the simulated size.
+    pub fn new_headless(simulated_size: (u32, u32)) -> Result<RetroCanvas, String> {
+        RetroContext::new_headless()?.create_canvas(simulated_size, simulated_size, "")
+    }
+
+    fn with_interior(
+        interior: Canvas<Window>,
+        simulated_size: (u32, u32),
+        events: Rc<RefCell<SharedEvents>>,
+    ) -> Result<RetroCanvas, String> {
+        events.borrow_mut().register(interior.window().id());
+        sdl2::hint::set(SCALE_QUALITY_HINT, "nearest");
+        let texture_creator = interior.texture_creator();
+        let texture = Self::framebuffer_texture(&texture_creator, simulated_size);
         let mut canvas = RetroCanvas {
             simulated_size,
-            interior: window.into_canvas().build().unwrap(),
+            buffer: vec![Color::RGB(0, 0, 0); (simulated_size.0 * simulated_size.1) as usize],
+            indices: vec![None; (simulated_size.0 * simulated_size.1) as usize],
+            palette: vec![Color::RGB(0, 0, 0); 256],
+            dirty: Some(Rect::new(0, 0, simulated_size.0, simulated_size.1)),
+            texture,
+            layers: Vec::new(),
+            texture_creator,
+            camera: (0, 0),
+            blend_mode: BlendMode::None,
+            nearest_scaling: true,
+            frame_count: 0,
+            last_present: Instant::now(),
+            delta_time: Duration::ZERO,
+            events,
+            controllers: Vec::new(),
+            mouse_position: (0, 0),
+            border_color: Color::RGB(0, 0, 0),
+            recording: None,
+            present_hook: None,
+            interior,
         };
         canvas
             .set_logical_size(simulated_size.0, simulated_size.1)
-            .unwrap();
+            .map_err(|e| e.to_string())?;
         canvas.set_draw_color(Color::RGB(0, 0, 0));
         canvas.clear();
-        canvas.present();
+        canvas.interior.present();
 
-        canvas
+        Ok(canvas)
+    }
+
+    fn framebuffer_texture(
+        texture_creator: &TextureCreator<WindowContext>,
+        simulated_size: (u32, u32),
+    ) -> OwnedTexture {
+        let mut texture = texture_creator
+            .create_texture_streaming(PixelFormatEnum::RGBA32, simulated_size.0, simulated_size.1)
+            .unwrap();
+        texture.set_blend_mode(BlendMode::None);
+        // SAFETY: the texture is only ever stored as the framebuffer texture of the canvas it was created from.
+        unsafe { own_texture(texture) }
+    }
+
+    /// Sets whether textures are scaled with nearest neighbor (crisp pixels, the default) or linearly (smooth, but blurry).
+    /// This applies to the framebuffer and to textures created afterwards, but not to already existing layers or textures.
+    pub fn set_scale_quality(&mut self, nearest: bool) {
+        self.nearest_scaling = nearest;
+        sdl2::hint::set(
+            SCALE_QUALITY_HINT,
+            if nearest { "nearest" } else { "linear" },
+        );
+        self.texture = Self::framebuffer_texture(&self.texture_creator, self.simulated_size);
+        self.mark_all_dirty();
+    }
+
+    /// Returns whether textures are scaled with nearest neighbor, as set by `set_scale_quality`.
+    pub fn nearest_scaling(&self) -> bool {
+        self.nearest_scaling
+    }
+
+    /// Returns the pixel format of the window, which is what textures created by `create_texture_streaming` use.
+    pub fn pixel_format(&self) -> PixelFormatEnum {
+        self.interior.window().window_pixel_format()
+    }
+
+    /// Creates a streaming texture in the window's pixel format, for rendering the canvas doesn't support itself.
+    /// The texture borrows the given creator, which comes from the canvas' `texture_creator` and keeps the renderer alive,
+    /// so it can be drawn with `copy` on the canvas, and is freed when dropped.
+    pub fn create_texture_streaming<'a>(
+        &self,
+        texture_creator: &'a TextureCreator<WindowContext>,
+        width: u32,
+        height: u32,
+    ) -> Result<Texture<'a>, String> {
+        texture_creator
+            .create_texture_streaming(self.pixel_format(), width, height)
+            .map_err(|e| e.to_string())
     }
 
     /// Clears the simulated screen of your console with whatever color you set.
@@ -69,13 +584,511 @@ impl RetroCanvas {
             ))
             .unwrap();
     }
+
+    /// Clears the framebuffer with the given color. This doesn't show up until the next `flip`.
+    pub fn clear_buffer(&mut self, color: Color) {
+        self.buffer.fill(color);
+        self.indices.fill(None);
+        self.mark_all_dirty();
+    }
+
+    /// Marks the whole framebuffer as changed, so that the next `flip` uploads all of it.
+    /// Changes made through the drawing methods are tracked automatically, so this is rarely needed.
+    pub fn mark_all_dirty(&mut self) {
+        self.dirty = Some(Rect::new(
+            0,
+            0,
+            self.simulated_size.0,
+            self.simulated_size.1,
+        ));
+    }
+
+    /// Returns the region of the framebuffer which changed since the last `flip`, in screen coordinates, if any.
+    pub fn dirty_bounds(&self) -> Option<Rect> {
+        self.dirty
+    }
+
+    fn mark_dirty(&mut self, rect: Rect) {
+        self.dirty = Some(match self.dirty {
+            Some(dirty) => dirty.union(rect),
+            None => rect,
+        });
+    }
+
+    /// Sets the position of the camera. Everything drawn onto the framebuffer is in world coordinates, and is offset by the camera's position.
+    pub fn set_camera(&mut self, x: i32, y: i32) {
+        self.camera = (x, y);
+    }
+
+    /// Returns the position of the camera.
+    pub fn camera(&self) -> (i32, i32) {
+        self.camera
+    }
+
+    /// Converts a position on the simulated screen to world coordinates, taking the camera into account.
+    pub fn screen_to_world(&self, x: i32, y: i32) -> (i32, i32) {
+        (x + self.camera.0, y + self.camera.1)
+    }
+
+    /// Converts a position in world coordinates to a position on the simulated screen, taking the camera into account.
+    pub fn world_to_screen(&self, x: i32, y: i32) -> (i32, i32) {
+        (x - self.camera.0, y - self.camera.1)
+    }
+
+    /// Returns the index of a pixel in the framebuffer, in screen coordinates, or None if it is outside of the simulated screen.
+    fn buffer_index(&self, x: i32, y: i32) -> Option<usize> {
+        let (width, height) = self.simulated_size;
+        if x < 0 || y < 0 || x >= width as i32 || y >= height as i32 {
+            return None;
+        }
+        Some(y as usize * width as usize + x as usize)
+    }
+
+    /// Writes a pixel into the framebuffer, in screen coordinates. Pixels outside of the simulated screen are ignored.
+    fn put_pixel(&mut self, x: i32, y: i32, color: Color) {
+        if let Some(index) = self.buffer_index(x, y) {
+            self.buffer[index] = color;
+            self.indices[index] = None;
+            self.mark_dirty(Rect::new(x, y, 1, 1));
+        }
+    }
+
+    /// Draws a pixel onto the framebuffer, in world coordinates. Pixels which end up outside of the simulated screen are ignored.
+    pub fn draw_pixel(&mut self, x: i32, y: i32, color: Color) {
+        let (x, y) = self.world_to_screen(x, y);
+        self.put_pixel(x, y, color);
+    }
+
+    /// Draws many pixels of the same color onto the framebuffer, in world coordinates, e.g. for particles.
+    /// This is faster than calling `draw_pixel` for each of them, as the changed region is only updated once.
+    pub fn draw_points(&mut self, points: &[(i32, i32)], color: Color) {
+        self.put_points(points.iter().map(|point| (*point, color)));
+    }
+
+    /// Like `draw_points`, but with a color for each pixel.
+    pub fn draw_points_colored(&mut self, points: &[((i32, i32), Color)]) {
+        self.put_points(points.iter().copied());
+    }
+
+    /// Writes pixels into the framebuffer, in world coordinates, marking the region containing all of them as dirty at once.
+    fn put_points(&mut self, points: impl Iterator<Item = ((i32, i32), Color)>) {
+        let mut bounds: Option<(i32, i32, i32, i32)> = None;
+        for ((x, y), color) in points {
+            let (x, y) = self.world_to_screen(x, y);
+            if let Some(index) = self.buffer_index(x, y) {
+                self.buffer[index] = color;
+                self.indices[index] = None;
+                bounds = Some(match bounds {
+                    Some((min_x, min_y, max_x, max_y)) => {
+                        (min_x.min(x), min_y.min(y), max_x.max(x), max_y.max(y))
+                    }
+                    None => (x, y, x, y),
+                });
+            }
+        }
+        if let Some((min_x, min_y, max_x, max_y)) = bounds {
+            self.mark_dirty(Rect::new(
+                min_x,
+                min_y,
+                (max_x - min_x + 1) as u32,
+                (max_y - min_y + 1) as u32,
+            ));
+        }
+    }
+
+    /// Sets the blend mode, both for regular SDL drawing and for `draw_pixel_blended`.
+    pub fn set_blend_mode(&mut self, mode: BlendMode) {
+        self.blend_mode = mode;
+        self.interior.set_blend_mode(mode);
+    }
+
+    /// Returns the current blend mode.
+    pub fn blend_mode(&self) -> BlendMode {
+        self.blend_mode
+    }
+
+    /// Draws a pixel onto the framebuffer, in world coordinates, blending it with what is already there using the current blend mode.
+    /// Pixels which end up outside of the simulated screen are ignored.
+    pub fn draw_pixel_blended(&mut self, x: i32, y: i32, color: Color) {
+        let (x, y) = self.world_to_screen(x, y);
+        if let Some(index) = self.buffer_index(x, y) {
+            self.buffer[index] = blend(color, self.buffer[index], self.blend_mode);
+            self.indices[index] = None;
+            self.mark_dirty(Rect::new(x, y, 1, 1));
+        }
+    }
+
+    /// Draws a sprite onto the framebuffer, with its top left corner at the given position in world coordinates.
+    /// Fully transparent pixels (with an alpha of 0) are skipped, and parts of the sprite outside of the simulated screen are clipped.
+    pub fn draw_sprite(&mut self, sprite: &Sprite, x: i32, y: i32) {
+        for (i, color) in sprite.pixels.iter().enumerate() {
+            if color.a == 0 {
+                continue;
+            }
+            let sprite_x = (i % sprite.width as usize) as i32;
+            let sprite_y = (i / sprite.width as usize) as i32;
+            self.draw_pixel(x + sprite_x, y + sprite_y, *color);
+        }
+    }
+
+    /// Draws the region of an atlas with the given name onto the framebuffer, like `draw_sprite` would draw it if it were its own sprite.
+    /// Panics if the atlas has no region with that name.
+    pub fn draw_atlas_region(&mut self, atlas: &SpriteAtlas, name: &str, x: i32, y: i32) {
+        let region = atlas
+            .region(name)
+            .unwrap_or_else(|| panic!("The atlas has no region named {:?}!", name));
+        let sprite = &atlas.sprite;
+        let points = (0..region.height() as i32).flat_map(|row| {
+            let start =
+                ((region.top() + row) as u32 * sprite.width) as usize + region.left() as usize;
+            let pixels = &sprite.pixels[start..start + region.width() as usize];
+            pixels
+                .iter()
+                .enumerate()
+                .filter(|(_, color)| color.a != 0)
+                .map(move |(column, color)| ((x + column as i32, y + row), *color))
+        });
+        self.put_points(points);
+    }
+
+    /// Draws a sprite onto the framebuffer like `draw_sprite`, but flipped and/or rotated clockwise by the given angle (in degrees) around its center.
+    /// The sprite is flipped before being rotated. Each pixel of the result is picked from the nearest pixel of the sprite, so it stays crisp.
+    pub fn draw_sprite_ex(
+        &mut self,
+        sprite: &Sprite,
+        x: i32,
+        y: i32,
+        flip_h: bool,
+        flip_v: bool,
+        rotation_deg: f64,
+    ) {
+        let (width, height) = (sprite.width as f64, sprite.height as f64);
+        let (sin, cos) = rotation_deg.to_radians().sin_cos();
+        let center = (x as f64 + width / 2.0, y as f64 + height / 2.0);
+        let half_extent = (
+            (width * cos.abs() + height * sin.abs()) / 2.0,
+            (width * sin.abs() + height * cos.abs()) / 2.0,
+        );
+        let columns =
+            (center.0 - half_extent.0).floor() as i32..(center.0 + half_extent.0).ceil() as i32;
+        let rows =
+            (center.1 - half_extent.1).floor() as i32..(center.1 + half_extent.1).ceil() as i32;
+        for destination_y in rows {
+            for destination_x in columns.clone() {
+                // Rotate the center of the destination pixel back onto the sprite.
+                let offset_x = destination_x as f64 + 0.5 - center.0;
+                let offset_y = destination_y as f64 + 0.5 - center.1;
+                let source_x = (offset_x * cos + offset_y * sin + width / 2.0).floor();
+                let source_y = (-offset_x * sin + offset_y * cos + height / 2.0).floor();
+                if source_x < 0.0 || source_y < 0.0 || source_x >= width || source_y >= height {
+                    continue;
+                }
+                let mut source_x = source_x as u32;
+                let mut source_y = source_y as u32;
+                if flip_h {
+                    source_x = sprite.width - 1 - source_x;
+                }
+                if flip_v {
+                    source_y = sprite.height - 1 - source_y;
+                }
+                let color = sprite.pixels[(source_y * sprite.width + source_x) as usize];
+                if color.a != 0 {
+                    self.draw_pixel(destination_x, destination_y, color);
+                }
+            }
+        }
+    }
+
+    /// Fills a rectangle of the framebuffer (in world coordinates) with a vertical gradient, from `top` on its first row to `bottom` on its last one.
+    pub fn fill_gradient(&mut self, rect: Rect, top: Color, bottom: Color) {
+        let steps = (rect.height() - 1).max(1) as f32;
+        let points = (0..rect.height() as i32).flat_map(|row| {
+            let color = lerp_color(top, bottom, row as f32 / steps);
+            (rect.left()..rect.right()).map(move |x| ((x, rect.top() + row), color))
+        });
+        self.put_points(points);
+    }
+
+    /// Fills a rectangle of the framebuffer (in world coordinates) with a mix of two colors, using ordered (Bayer) dithering.
+    /// `ratio` is the share of pixels using `color_b`, from 0 (only `color_a`) to 1 (only `color_b`); 0.5 gives a checkerboard.
+    /// The pattern is aligned to world coordinates, so that neighboring rectangles line up seamlessly.
+    pub fn fill_dithered(&mut self, rect: Rect, color_a: Color, color_b: Color, ratio: f32) {
+        let points = (rect.top()..rect.bottom()).flat_map(|y| {
+            (rect.left()..rect.right()).map(move |x| {
+                let threshold = BAYER_MATRIX[y.rem_euclid(4) as usize][x.rem_euclid(4) as usize];
+                let color = if (threshold as f32 + 0.5) / 16.0 < ratio {
+                    color_b
+                } else {
+                    color_a
+                };
+                ((x, y), color)
+            })
+        });
+        self.put_points(points);
+    }
+
+    /// Draws the outline of a circle onto the framebuffer, centered at the given position in world coordinates.
+    /// A radius of 0 draws a single pixel, and a negative radius draws nothing.
+    pub fn draw_circle(&mut self, center: (i32, i32), radius: i32, color: Color) {
+        if radius < 0 {
+            return;
+        }
+        let points = circle_octant(radius).into_iter().flat_map(|(x, y)| {
+            [
+                (x, y),
+                (y, x),
+                (-y, x),
+                (-x, y),
+                (-x, -y),
+                (-y, -x),
+                (y, -x),
+                (x, -y),
+            ]
+            .map(|(x, y)| ((center.0 + x, center.1 + y), color))
+        });
+        self.put_points(points);
+    }
+
+    /// Fills a circle onto the framebuffer, centered at the given position in world coordinates, covering the same pixels as `draw_circle` and everything inside.
+    /// A radius of 0 draws a single pixel, and a negative radius draws nothing.
+    pub fn fill_circle(&mut self, center: (i32, i32), radius: i32, color: Color) {
+        if radius < 0 {
+            return;
+        }
+        let points = circle_octant(radius).into_iter().flat_map(|(x, y)| {
+            [(x, y), (x, -y), (y, x), (y, -x)]
+                .into_iter()
+                .flat_map(move |(half_width, row)| {
+                    (-half_width..=half_width)
+                        .map(move |column| ((center.0 + column, center.1 + row), color))
+                })
+        });
+        self.put_points(points);
+    }
+
+    /// Draws a tilemap onto the framebuffer, with its top left corner at the given position in world coordinates.
+    /// Each tile is taken from the tileset, which is cut into cells of the map's tile size. Only the tiles which are on screen are drawn.
+    /// Panics if an on-screen tile is outside of the tileset (e.g. if the tileset is smaller than a single tile).
+    pub fn draw_tilemap(&mut self, tileset: &Sprite, map: &TileMap, origin: (i32, i32)) {
+        let (tile_width, tile_height) = map.tile_size;
+        let tileset_columns = tileset.width / tile_width;
+        let tile_count = tileset_columns * (tileset.height / tile_height);
+        let (left, top) = self.screen_to_world(0, 0);
+        let first_column = ((left - origin.0).div_euclid(tile_width as i32)).max(0);
+        let first_row = ((top - origin.1).div_euclid(tile_height as i32)).max(0);
+        let last_column = ((left + self.simulated_size.0 as i32 - origin.0)
+            .div_euclid(tile_width as i32))
+        .min(map.width as i32 - 1);
+        let last_row = ((top + self.simulated_size.1 as i32 - origin.1)
+            .div_euclid(tile_height as i32))
+        .min(map.height as i32 - 1);
+        for row in first_row..=last_row {
+            for column in first_column..=last_column {
+                let tile = map.tiles[(row as u32 * map.width + column as u32) as usize];
+                if tile == EMPTY_TILE {
+                    continue;
+                }
+                if tile as u32 >= tile_count {
+                    panic!(
+                        "The tile {} at ({}, {}) is outside of the tileset, which only has {} tiles!",
+                        tile, column, row, tile_count
+                    );
+                }
+                let tile_x = (tile as u32 % tileset_columns) * tile_width;
+                let tile_y = (tile as u32 / tileset_columns) * tile_height;
+                for y in 0..tile_height {
+                    for x in 0..tile_width {
+                        let color =
+                            tileset.pixels[((tile_y + y) * tileset.width + tile_x + x) as usize];
+                        if color.a == 0 {
+                            continue;
+                        }
+                        self.draw_pixel(
+                            origin.0 + column * tile_width as i32 + x as i32,
+                            origin.1 + row * tile_height as i32 + y as i32,
+                            color,
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    /// Returns a copy of the framebuffer, row by row, starting from the top left.
+    pub fn screenshot(&self) -> Vec<Color> {
+        self.buffer.clone()
+    }
+
+    /// Uploads the framebuffer to the simulated screen and presents it.
+    /// Everything drawn with `draw_pixel` or `draw_sprite` only shows up after this is called.
+    /// Only the parts of the framebuffer which changed since the last flip are uploaded.
+    pub fn flip(&mut self) {
+        let (width, height) = self.simulated_size;
+        if let Some(dirty) = self.dirty.take() {
+            let buffer = &self.buffer;
+            self.texture
+                .with_lock(dirty, |data: &mut [u8], pitch: usize| {
+                    for row in 0..dirty.height() as usize {
+                        let start =
+                            (dirty.y() as usize + row) * width as usize + dirty.x() as usize;
+                        let colors = &buffer[start..start + dirty.width() as usize];
+                        for (column, color) in colors.iter().enumerate() {
+                            let offset = row * pitch + column * 4;
+                            data[offset..offset + 4]
+                                .copy_from_slice(&[color.r, color.g, color.b, color.a]);
+                        }
+                    }
+                })
+                .unwrap();
+        }
+        self.interior
+            .copy(&self.texture, None, Rect::new(0, 0, width, height))
+            .unwrap();
+        self.present();
+    }
+
+    /// Sets the color of the margins around the simulated screen when the window has a different aspect ratio, independently of the clear color.
+    /// The margins are filled when presenting. Black by default.
+    pub fn set_border_color(&mut self, color: Color) {
+        self.border_color = color;
+    }
+
+    /// Returns the color of the margins around the simulated screen.
+    pub fn border_color(&self) -> Color {
+        self.border_color
+    }
+
+    /// Fills the margins around the simulated screen with the border color. They are outside of the logical size, so it is turned off while drawing them.
+    fn draw_border(&mut self) {
+        let rects = border_rects(
+            self.interior.output_size().unwrap(),
+            self.simulated_size,
+            self.interior.integer_scale(),
+        );
+        if rects.is_empty() {
+            return;
+        }
+        let draw_color = self.interior.draw_color();
+        let blend_mode = self.interior.blend_mode();
+        self.interior.set_logical_size(0, 0).unwrap();
+        self.interior.set_blend_mode(BlendMode::None);
+        self.interior.set_draw_color(self.border_color);
+        self.interior.fill_rects(&rects).unwrap();
+        self.interior
+            .set_logical_size(self.simulated_size.0, self.simulated_size.1)
+            .unwrap();
+        self.interior.set_blend_mode(blend_mode);
+        self.interior.set_draw_color(draw_color);
+    }
+
+    /// Sets a hook to run every time the canvas is presented, e.g. for full-screen effects drawn with SDL calls.
+    /// It receives the canvas and the part of the window showing the simulated screen (the viewport), in window pixels.
+    /// The logical size is turned off while it runs, so it draws in window pixels too. Replaces the previous hook, if any.
+    ///
+    /// When presenting, the framebuffer is drawn first (by `flip`), then the margins are filled with the border color,
+    /// then the hook runs, and then the frame is shown. Recordings capture the framebuffer, so they don't include what the hook draws.
+    pub fn set_present_hook(&mut self, hook: impl FnMut(&mut Canvas<Window>, Rect) + 'static) {
+        self.present_hook = Some(Box::new(hook));
+    }
+
+    /// Removes the hook set with `set_present_hook`.
+    pub fn clear_present_hook(&mut self) {
+        self.present_hook = None;
+    }
+
+    /// Runs the present hook, if there is one, without the logical size.
+    fn run_present_hook(&mut self) {
+        let hook = match &mut self.present_hook {
+            Some(hook) => hook,
+            None => return,
+        };
+        let viewport = viewport_rect(
+            self.interior.output_size().unwrap(),
+            self.simulated_size,
+            self.interior.integer_scale(),
+        );
+        self.interior.set_logical_size(0, 0).unwrap();
+        hook(&mut self.interior, viewport);
+        self.interior
+            .set_logical_size(self.simulated_size.0, self.simulated_size.1)
+            .unwrap();
+    }
+
+    /// Presents the canvas, like the Canvas' `present`, while keeping track of the frame count and the time between frames.
+    /// The margins around the simulated screen are filled with the border color first, and then the present hook runs (see `set_present_hook`).
+    pub fn present(&mut self) {
+        self.draw_border();
+        self.run_present_hook();
+        self.interior.present();
+        let now = Instant::now();
+        self.delta_time = now - self.last_present;
+        self.last_present = now;
+        self.frame_count += 1;
+        self.capture_frame();
+    }
+
+    /// The amount of frames presented so far.
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
+    /// The time between the last two presents (or between the creation of the canvas and the first present).
+    pub fn delta_time(&self) -> Duration {
+        self.delta_time
+    }
+
+    /// Sets how presenting synchronizes with the display, overriding the vsync choice the canvas was created with.
+    /// This only works with the OpenGL renderer, on platforms which support the given interval (`LateSwapTearing` especially is rare);
+    /// otherwise SDL's error is returned and nothing changes.
+    pub fn set_swap_interval(&mut self, interval: SwapInterval) -> Result<(), String> {
+        self.interior
+            .window()
+            .subsystem()
+            .gl_set_swap_interval(interval)
+    }
+
+    /// Runs a fixed-timestep game loop until the window is closed, and returns the final state.
+    /// Every frame, the events are polled, `update` is called once per `FIXED_TIMESTEP` of real time that passed (with the timestep),
+    /// and then `render` is called once before presenting.
+    /// Each event is given to exactly one `update`: the first one after it was polled.
+    pub fn run_loop<S, U, R>(mut self, mut state: S, mut update: U, mut render: R) -> S
+    where
+        U: FnMut(&mut S, Duration, &[RetroEvent]),
+        R: FnMut(&mut RetroCanvas, &S),
+    {
+        let mut accumulator = Duration::ZERO;
+        let mut previous = Instant::now();
+        let mut events = Vec::new();
+        loop {
+            let polled = self.poll_events();
+            if polled.contains(&RetroEvent::Quit) {
+                break;
+            }
+            events.extend(polled);
+            let now = Instant::now();
+            accumulator += (now - previous).min(MAX_FRAME_TIME);
+            previous = now;
+            while accumulator >= FIXED_TIMESTEP {
+                update(&mut state, FIXED_TIMESTEP, &events);
+                events.clear();
+                accumulator -= FIXED_TIMESTEP;
+            }
+            render(&mut self, &state);
+            self.present();
+        }
+        state
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::time::Duration;
+    use std::sync::Mutex;
+
+    /// Only one SDL context can exist at a time, so the headless tests take turns.
+    pub(crate) static SDL: Mutex<()> = Mutex::new(());
 
     #[test]
+    #[cfg_attr(not(feature = "window-tests"), ignore)]
     fn scaling() {
         use super::*;
         let mut canvas = RetroCanvas::new((1000, 600), (320, 240), "Test");
@@ -84,4 +1097,492 @@ mod tests {
         canvas.present();
         std::thread::sleep(Duration::from_secs(5));
     }
+
+    #[test]
+    fn hsv_conversion() {
+        use super::*;
+        assert_eq!(hsv_to_color(0.0, 1.0, 1.0), Color::RGB(255, 0, 0));
+        assert_eq!(hsv_to_color(120.0, 1.0, 1.0), Color::RGB(0, 255, 0));
+        assert_eq!(hsv_to_color(240.0, 1.0, 1.0), Color::RGB(0, 0, 255));
+        assert_eq!(hsv_to_color(360.0, 1.0, 1.0), Color::RGB(255, 0, 0));
+        assert_eq!(hsv_to_color(60.0, 1.0, 0.5), Color::RGB(128, 128, 0));
+        assert_eq!(hsv_to_color(200.0, 0.0, 1.0), Color::RGB(255, 255, 255));
+        assert_eq!(hsv_to_color(0.0, 0.0, 0.0), Color::RGB(0, 0, 0));
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "window-tests"), ignore)]
+    fn framebuffer() {
+        use super::*;
+        let mut canvas = RetroCanvas::new((1000, 600), (320, 240), "Test");
+        canvas.clear_buffer(Color::RGB(0, 0, 255));
+        canvas.draw_pixel(10, 20, Color::RGB(255, 0, 0));
+        canvas.draw_sprite(
+            &Sprite::new(2, 1, vec![Color::RGB(0, 255, 0), Color::RGBA(0, 0, 0, 0)]),
+            30,
+            40,
+        );
+        canvas.draw_pixel(-1, 500, Color::RGB(255, 255, 255));
+        canvas.flip();
+        let screenshot = canvas.screenshot();
+        assert_eq!(screenshot[20 * 320 + 10], Color::RGB(255, 0, 0));
+        assert_eq!(screenshot[40 * 320 + 30], Color::RGB(0, 255, 0));
+        assert_eq!(screenshot[40 * 320 + 31], Color::RGB(0, 0, 255));
+        assert_eq!(screenshot[0], Color::RGB(0, 0, 255));
+        std::thread::sleep(Duration::from_secs(2));
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "window-tests"), ignore)]
+    fn camera() {
+        use super::*;
+        let mut canvas = RetroCanvas::new((1000, 600), (320, 240), "Test");
+        canvas.set_camera(50, 50);
+        canvas.draw_sprite(&Sprite::new(1, 1, vec![Color::RGB(255, 0, 0)]), 100, 100);
+        canvas.draw_pixel(10, 10, Color::RGB(0, 255, 0));
+        canvas.flip();
+        let screenshot = canvas.screenshot();
+        assert_eq!(screenshot[50 * 320 + 50], Color::RGB(255, 0, 0));
+        assert!(screenshot.iter().all(|c| *c != Color::RGB(0, 255, 0)));
+        assert_eq!(canvas.world_to_screen(100, 100), (50, 50));
+        assert_eq!(canvas.screen_to_world(50, 50), (100, 100));
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "window-tests"), ignore)]
+    fn frame_timing() {
+        use super::*;
+        let mut canvas = RetroCanvas::new((1000, 600), (320, 240), "Test");
+        assert_eq!(canvas.frame_count(), 0);
+        for _ in 0..3 {
+            std::thread::sleep(Duration::from_millis(10));
+            canvas.present();
+            assert!(canvas.delta_time() > Duration::ZERO);
+        }
+        assert_eq!(canvas.frame_count(), 3);
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "window-tests"), ignore)]
+    fn tilemap() {
+        use super::*;
+        let mut canvas = RetroCanvas::new((1000, 600), (320, 240), "Test");
+        let red = Color::RGB(255, 0, 0);
+        let green = Color::RGB(0, 255, 0);
+        let tileset = Sprite::new(4, 2, vec![red, red, green, green, red, red, green, green]);
+        let mut map = TileMap::new(4, 4, (2, 2));
+        map.set(1, 0, 0);
+        map.set(2, 3, 1);
+        canvas.clear_buffer(Color::RGB(0, 0, 0));
+        canvas.draw_tilemap(&tileset, &map, (10, 10));
+        canvas.flip();
+        let screenshot = canvas.screenshot();
+        assert_eq!(screenshot[10 * 320 + 12], red);
+        assert_eq!(screenshot[11 * 320 + 13], red);
+        assert_eq!(screenshot[16 * 320 + 14], green);
+        assert_eq!(screenshot[17 * 320 + 15], green);
+        assert_eq!(screenshot[10 * 320 + 10], Color::RGB(0, 0, 0));
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "window-tests"), ignore)]
+    fn additive_blending() {
+        use super::*;
+        let mut canvas = RetroCanvas::new((1000, 600), (320, 240), "Test");
+        canvas.clear_buffer(Color::RGB(0, 0, 0));
+        canvas.set_blend_mode(BlendMode::Add);
+        for (left, top) in [(10, 10), (15, 15)] {
+            for y in top..top + 10 {
+                for x in left..left + 10 {
+                    canvas.draw_pixel_blended(x, y, Color::RGBA(100, 50, 0, 255));
+                }
+            }
+        }
+        canvas.flip();
+        let screenshot = canvas.screenshot();
+        assert_eq!(screenshot[12 * 320 + 12], Color::RGB(100, 50, 0));
+        assert_eq!(screenshot[17 * 320 + 17], Color::RGB(200, 100, 0));
+        assert_eq!(screenshot[30 * 320 + 30], Color::RGB(0, 0, 0));
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "window-tests"), ignore)]
+    fn dirty_rectangles() {
+        use super::*;
+        let mut canvas = RetroCanvas::new((1000, 600), (320, 240), "Test");
+        assert_eq!(canvas.dirty_bounds(), Some(Rect::new(0, 0, 320, 240)));
+        canvas.flip();
+        assert_eq!(canvas.dirty_bounds(), None);
+        canvas.draw_pixel(10, 20, Color::RGB(255, 0, 0));
+        assert_eq!(canvas.dirty_bounds(), Some(Rect::new(10, 20, 1, 1)));
+        canvas.flip();
+        assert_eq!(canvas.dirty_bounds(), None);
+        assert_eq!(canvas.screenshot()[20 * 320 + 10], Color::RGB(255, 0, 0));
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "window-tests"), ignore)]
+    fn scale_quality() {
+        use super::*;
+        let mut canvas = RetroCanvas::new((1000, 600), (320, 240), "Test");
+        assert!(canvas.nearest_scaling());
+        canvas.draw_pixel(10, 10, Color::RGB(255, 0, 0));
+        canvas.set_scale_quality(false);
+        assert!(!canvas.nearest_scaling());
+        canvas.flip();
+        canvas.set_scale_quality(true);
+        assert!(canvas.nearest_scaling());
+        canvas.flip();
+    }
+
+    #[test]
+    fn headless() {
+        use super::*;
+        let _sdl = SDL.lock().unwrap_or_else(|e| e.into_inner());
+        let mut canvas = RetroCanvas::new_headless((32, 24)).unwrap();
+        canvas.clear_buffer(Color::RGB(0, 0, 255));
+        canvas.draw_pixel(3, 4, Color::RGB(255, 0, 0));
+        canvas.flip();
+        let screenshot = canvas.screenshot();
+        assert_eq!(screenshot[4 * 32 + 3], Color::RGB(255, 0, 0));
+        assert_eq!(screenshot[0], Color::RGB(0, 0, 255));
+        let pixels = canvas.read_pixels(None, PixelFormatEnum::RGBA32).unwrap();
+        assert_eq!(&pixels[(4 * 32 + 3) * 4..][..3], &[255, 0, 0]);
+        assert_eq!(&pixels[..3], &[0, 0, 255]);
+    }
+
+    #[test]
+    fn run_loop() {
+        use super::*;
+        use sdl2::event::Event;
+        use sdl2::mouse::MouseState;
+        let _sdl = SDL.lock().unwrap_or_else(|e| e.into_inner());
+        let canvas = RetroCanvas::new_headless((32, 24)).unwrap();
+        let mut frames = 0;
+        let (updates, motions) = canvas.run_loop(
+            (0, 0),
+            |(updates, motions), timestep, events| {
+                assert_eq!(timestep, FIXED_TIMESTEP);
+                *updates += 1;
+                *motions += events
+                    .iter()
+                    .filter(|event| matches!(event, RetroEvent::MouseMotion { .. }))
+                    .count();
+            },
+            |canvas, _| {
+                std::thread::sleep(FIXED_TIMESTEP);
+                frames += 1;
+                let events = canvas.window().subsystem().sdl().event().unwrap();
+                if frames == 2 {
+                    events
+                        .push_event(Event::MouseMotion {
+                            timestamp: 0,
+                            window_id: canvas.window().id(),
+                            which: 0,
+                            mousestate: MouseState::from_sdl_state(0),
+                            x: 1,
+                            y: 1,
+                            xrel: 1,
+                            yrel: 1,
+                        })
+                        .unwrap();
+                }
+                if frames == 5 {
+                    events.push_event(Event::Quit { timestamp: 0 }).unwrap();
+                }
+            },
+        );
+        assert_eq!(frames, 5);
+        // Every frame sleeps for a timestep, so at least four timesteps passed before the last frame.
+        assert!(updates >= 4);
+        // The motion reaches exactly one update.
+        assert_eq!(motions, 1);
+    }
+
+    #[test]
+    fn sprite_transforms() {
+        use super::*;
+        let _sdl = SDL.lock().unwrap_or_else(|e| e.into_inner());
+        let mut canvas = RetroCanvas::new_headless((32, 24)).unwrap();
+        let (red, green, blue) = (
+            Color::RGB(255, 0, 0),
+            Color::RGB(0, 255, 0),
+            Color::RGB(0, 0, 255),
+        );
+        let sprite = Sprite::new(3, 1, vec![red, green, blue]);
+        canvas.draw_sprite_ex(&sprite, 1, 1, true, false, 0.0);
+        // Rotated clockwise, the left end of the sprite ends up at the top.
+        canvas.draw_sprite_ex(&sprite, 10, 10, false, false, 90.0);
+        canvas.flip();
+        let screenshot = canvas.screenshot();
+        assert_eq!(screenshot[32 + 1], blue);
+        assert_eq!(screenshot[32 + 2], green);
+        assert_eq!(screenshot[32 + 3], red);
+        assert_eq!(screenshot[9 * 32 + 11], red);
+        assert_eq!(screenshot[10 * 32 + 11], green);
+        assert_eq!(screenshot[11 * 32 + 11], blue);
+        assert_eq!(screenshot[10 * 32 + 10], Color::RGB(0, 0, 0));
+        assert_eq!(screenshot[10 * 32 + 12], Color::RGB(0, 0, 0));
+    }
+
+    #[test]
+    fn points() {
+        use super::*;
+        let _sdl = SDL.lock().unwrap_or_else(|e| e.into_inner());
+        let mut canvas = RetroCanvas::new_headless((32, 24)).unwrap();
+        canvas.set_camera(1, 1);
+        let points: Vec<(i32, i32)> = (0..500).map(|i| (i % 40, i / 40)).collect();
+        canvas.draw_points(&points, Color::RGB(255, 0, 0));
+        assert_eq!(canvas.dirty_bounds(), Some(Rect::new(0, 0, 32, 12)));
+        canvas.draw_points_colored(&[
+            ((5, 20), Color::RGB(0, 255, 0)),
+            ((6, 21), Color::RGB(0, 0, 255)),
+        ]);
+        canvas.flip();
+        let screenshot = canvas.screenshot();
+        // The point at (0, 0) is off screen, as the camera moved.
+        assert_eq!(screenshot[0], Color::RGB(255, 0, 0));
+        assert_eq!(screenshot[10 * 32 + 31], Color::RGB(255, 0, 0));
+        assert_eq!(screenshot[11 * 32 + 18], Color::RGB(255, 0, 0));
+        assert_eq!(screenshot[11 * 32 + 19], Color::RGB(0, 0, 0));
+        assert_eq!(screenshot[19 * 32 + 4], Color::RGB(0, 255, 0));
+        assert_eq!(screenshot[20 * 32 + 5], Color::RGB(0, 0, 255));
+    }
+
+    #[test]
+    fn borders() {
+        use super::*;
+        // 320x240 in 1000x600 is scaled by 2.5, leaving 100 pixel bars on the left and right.
+        assert_eq!(
+            border_rects((1000, 600), (320, 240), false),
+            vec![Rect::new(0, 0, 100, 600), Rect::new(900, 0, 100, 600)]
+        );
+        // With integer scaling, the scale is 2, leaving bars on every side.
+        assert_eq!(
+            border_rects((1000, 600), (320, 240), true),
+            vec![
+                Rect::new(0, 0, 1000, 60),
+                Rect::new(0, 540, 1000, 60),
+                Rect::new(0, 60, 180, 480),
+                Rect::new(820, 60, 180, 480),
+            ]
+        );
+        assert!(border_rects((640, 480), (320, 240), false).is_empty());
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "window-tests"), ignore)]
+    fn border_color() {
+        use super::*;
+        let mut canvas = RetroCanvas::new((1000, 600), (320, 240), "Test");
+        canvas.set_border_color(Color::RGB(0, 0, 255));
+        canvas.set_draw_color(Color::RGB(255, 0, 255));
+        canvas.clear();
+        canvas.draw_border();
+        assert_eq!(canvas.draw_color(), Color::RGB(255, 0, 255));
+        // Read the whole window, rather than the simulated screen.
+        canvas.set_logical_size(0, 0).unwrap();
+        let pixels = canvas.read_pixels(None, PixelFormatEnum::RGBA32).unwrap();
+        let pixel = |x: usize, y: usize| &pixels[(y * 1000 + x) * 4..][..3];
+        assert_eq!(pixel(50, 300), &[0, 0, 255]);
+        assert_eq!(pixel(950, 10), &[0, 0, 255]);
+        assert_eq!(pixel(500, 300), &[255, 0, 255]);
+    }
+
+    #[test]
+    fn gradient_and_dithering() {
+        use super::*;
+        let _sdl = SDL.lock().unwrap_or_else(|e| e.into_inner());
+        let mut canvas = RetroCanvas::new_headless((32, 24)).unwrap();
+        let (white, black) = (Color::RGB(255, 255, 255), Color::RGB(0, 0, 0));
+        canvas.fill_dithered(Rect::new(0, 0, 4, 4), black, white, 0.5);
+        canvas.fill_dithered(Rect::new(4, 0, 4, 4), black, white, 0.0);
+        canvas.fill_dithered(Rect::new(8, 0, 4, 4), black, white, 1.0);
+        canvas.fill_gradient(Rect::new(0, 10, 2, 3), black, Color::RGB(0, 200, 100));
+        canvas.flip();
+        let screenshot = canvas.screenshot();
+        for y in 0..4 {
+            for x in 0..4 {
+                let expected = if (x + y) % 2 == 0 { white } else { black };
+                assert_eq!(screenshot[y * 32 + x], expected);
+                assert_eq!(screenshot[y * 32 + x + 4], black);
+                assert_eq!(screenshot[y * 32 + x + 8], white);
+            }
+        }
+        assert_eq!(screenshot[10 * 32 + 1], black);
+        assert_eq!(screenshot[11 * 32], Color::RGB(0, 100, 50));
+        assert_eq!(screenshot[12 * 32 + 1], Color::RGB(0, 200, 100));
+    }
+
+    #[test]
+    fn custom_texture() {
+        use super::*;
+        let _sdl = SDL.lock().unwrap_or_else(|e| e.into_inner());
+        let mut canvas = RetroCanvas::new_headless((32, 24)).unwrap();
+        let texture_creator = canvas.texture_creator();
+        let mut texture = canvas
+            .create_texture_streaming(&texture_creator, 4, 4)
+            .unwrap();
+        assert_eq!(texture.query().format, canvas.pixel_format());
+        texture.with_lock(None, |bytes, _| bytes.fill(255)).unwrap();
+        canvas.copy(&texture, None, Rect::new(0, 0, 4, 4)).unwrap();
+        // The texture keeps the renderer alive through its creator, so it can outlive the canvas.
+        drop(canvas);
+        texture.with_lock(None, |bytes, _| bytes.fill(0)).unwrap();
+    }
+
+    #[test]
+    fn present_hook() {
+        use super::*;
+        let _sdl = SDL.lock().unwrap_or_else(|e| e.into_inner());
+        let mut canvas = RetroCanvas::new_headless((32, 24)).unwrap();
+        canvas.clear_buffer(Color::RGB(0, 0, 255));
+        canvas.set_present_hook(|canvas, viewport| {
+            assert_eq!(viewport, Rect::new(0, 0, 32, 24));
+            canvas.set_draw_color(Color::RGB(255, 0, 0));
+            canvas.draw_line((0, 5), (31, 5)).unwrap();
+        });
+        canvas.flip();
+        let pixels = canvas.read_pixels(None, PixelFormatEnum::RGBA32).unwrap();
+        assert_eq!(&pixels[(5 * 32 + 10) * 4..][..3], &[255, 0, 0]);
+        assert_eq!(&pixels[(6 * 32 + 10) * 4..][..3], &[0, 0, 255]);
+        // The framebuffer itself is left untouched.
+        assert_eq!(canvas.screenshot()[5 * 32 + 10], Color::RGB(0, 0, 255));
+
+        canvas.clear_present_hook();
+        canvas.flip();
+        let pixels = canvas.read_pixels(None, PixelFormatEnum::RGBA32).unwrap();
+        assert_eq!(&pixels[(5 * 32 + 10) * 4..][..3], &[0, 0, 255]);
+    }
+
+    #[test]
+    fn circles() {
+        use super::*;
+        let _sdl = SDL.lock().unwrap_or_else(|e| e.into_inner());
+        let mut canvas = RetroCanvas::new_headless((32, 24)).unwrap();
+        let (black, white) = (Color::RGB(0, 0, 0), Color::RGB(255, 255, 255));
+        canvas.clear_buffer(black);
+        canvas.set_camera(-2, -2);
+        canvas.fill_circle((8, 8), 5, white);
+        canvas.draw_circle((20, 8), 5, white);
+        canvas.draw_circle((3, 18), 0, white);
+        canvas.fill_circle((8, 18), -1, white);
+        canvas.flip();
+        let screenshot = canvas.screenshot();
+        let pixel = |x: usize, y: usize| screenshot[y * 32 + x];
+        // The filled circle, centered at (10, 10) on screen.
+        assert_eq!(pixel(10, 10), white);
+        assert_eq!(pixel(15, 10), white);
+        assert_eq!(pixel(10, 5), white);
+        assert_eq!(pixel(14, 13), white);
+        assert_eq!(pixel(16, 10), black);
+        assert_eq!(pixel(14, 14), black);
+        // The outline, centered at (22, 10), is hollow.
+        assert_eq!(pixel(22, 10), black);
+        assert_eq!(pixel(27, 10), white);
+        assert_eq!(pixel(22, 15), white);
+        assert_eq!(pixel(26, 13), white);
+        assert_eq!(pixel(25, 12), black);
+        assert_eq!(pixel(5, 20), white);
+        assert_eq!(
+            screenshot.iter().filter(|pixel| **pixel == white).count(),
+            97 + 28 + 1
+        );
+    }
+
+    #[test]
+    fn shared_context() {
+        use super::*;
+        use sdl2::event::Event;
+        use sdl2::mouse::MouseState;
+        let _sdl = SDL.lock().unwrap_or_else(|e| e.into_inner());
+        let context = RetroContext::new_headless().unwrap();
+        let mut first = context.create_canvas((32, 24), (32, 24), "First").unwrap();
+        let mut second = context.create_canvas((16, 16), (16, 16), "Second").unwrap();
+        first.clear_buffer(Color::RGB(255, 0, 0));
+        second.clear_buffer(Color::RGB(0, 0, 255));
+        first.flip();
+        second.flip();
+        assert_eq!(first.screenshot()[0], Color::RGB(255, 0, 0));
+        assert_eq!(second.screenshot()[0], Color::RGB(0, 0, 255));
+        let pixels = second.read_pixels(None, PixelFormatEnum::RGBA32).unwrap();
+        assert_eq!(&pixels[..3], &[0, 0, 255]);
+        // Both canvases can poll, and each one only gets the events of its own window, in its own coordinates.
+        let events = second.window().subsystem().sdl().event().unwrap();
+        events
+            .push_event(Event::MouseMotion {
+                timestamp: 0,
+                window_id: second.window().id(),
+                which: 0,
+                mousestate: MouseState::from_sdl_state(0),
+                x: 20,
+                y: 8,
+                xrel: 0,
+                yrel: 0,
+            })
+            .unwrap();
+        events.push_event(Event::Quit { timestamp: 0 }).unwrap();
+        assert_eq!(first.poll_events(), vec![RetroEvent::Quit]);
+        assert_eq!(
+            second.poll_events(),
+            vec![RetroEvent::MouseMotion { position: None }, RetroEvent::Quit]
+        );
+        drop(first);
+        second.flip();
+        assert_eq!(second.poll_events(), vec![]);
+    }
+
+    #[test]
+    fn without_vsync() {
+        use super::*;
+        let _sdl = SDL.lock().unwrap_or_else(|e| e.into_inner());
+        let context = RetroContext::new_headless().unwrap();
+        let mut canvas = context
+            .create_canvas_ex((32, 24), (32, 24), "No vsync", false)
+            .unwrap();
+        canvas.clear_buffer(Color::RGB(0, 255, 0));
+        canvas.flip();
+        assert_eq!(canvas.frame_count(), 1);
+        assert_eq!(canvas.screenshot()[0], Color::RGB(0, 255, 0));
+        // The software renderer has no swap interval to set.
+        assert!(canvas.set_swap_interval(SwapInterval::Immediate).is_err());
+    }
+
+    #[test]
+    fn atlas_regions() {
+        use super::*;
+        let _sdl = SDL.lock().unwrap_or_else(|e| e.into_inner());
+        let mut canvas = RetroCanvas::new_headless((32, 24)).unwrap();
+        let (red, green, clear) = (
+            Color::RGB(255, 0, 0),
+            Color::RGB(0, 255, 0),
+            Color::RGBA(0, 0, 0, 0),
+        );
+        let black = Color::RGB(0, 0, 0);
+        // A 2x2 red square with a transparent corner, next to a 2x2 green one.
+        let sprite = Sprite::new(4, 2, vec![red, red, green, green, red, clear, green, green]);
+        let mut atlas = SpriteAtlas::new(sprite);
+        atlas.add_region("red", Rect::new(0, 0, 2, 2));
+        atlas.add_region("green", Rect::new(2, 0, 2, 2));
+        assert_eq!(atlas.region("green"), Some(Rect::new(2, 0, 2, 2)));
+        assert_eq!(atlas.region("blue"), None);
+        canvas.clear_buffer(black);
+        canvas.set_camera(-5, 0);
+        canvas.draw_atlas_region(&atlas, "red", 0, 3);
+        // Clipped by the right edge of the screen.
+        canvas.draw_atlas_region(&atlas, "red", 26, 10);
+        canvas.flip();
+        let screenshot = canvas.screenshot();
+        let pixel = |x: usize, y: usize| screenshot[y * 32 + x];
+        assert_eq!(pixel(5, 3), red);
+        assert_eq!(pixel(6, 3), red);
+        assert_eq!(pixel(5, 4), red);
+        assert_eq!(pixel(6, 4), black);
+        assert_eq!(pixel(31, 11), red);
+        assert!(!screenshot.contains(&green));
+        assert_eq!(
+            screenshot.iter().filter(|pixel| **pixel == red).count(),
+            3 + 2
+        );
+    }
 }
@@ -0,0 +1,107 @@
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+
+use crate::RetroCanvas;
+
+impl RetroCanvas {
+    /// Sets an entry of the palette used by `draw_pixel_indexed`. Every pixel drawn with that index changes color at the next `flip`.
+    /// The palette has 256 entries, all black by default.
+    pub fn set_palette_color(&mut self, index: u8, color: Color) {
+        self.palette[index as usize] = color;
+        self.refresh_indexed();
+    }
+
+    /// Returns an entry of the palette.
+    pub fn palette_color(&self, index: u8) -> Color {
+        self.palette[index as usize]
+    }
+
+    /// Draws a pixel onto the framebuffer, in world coordinates, with the color of the given palette entry.
+    /// Unlike with `draw_pixel`, the pixel keeps following its palette entry until something else is drawn over it.
+    pub fn draw_pixel_indexed(&mut self, x: i32, y: i32, index: u8) {
+        let (x, y) = self.world_to_screen(x, y);
+        if let Some(buffer_index) = self.buffer_index(x, y) {
+            self.buffer[buffer_index] = self.palette[index as usize];
+            self.indices[buffer_index] = Some(index);
+            self.mark_dirty(Rect::new(x, y, 1, 1));
+        }
+    }
+
+    /// Rotates the palette entries from `start` to `end` (inclusive) by `step` positions, wrapping around within the range,
+    /// so that the entry at `start` moves to `start + step`. A negative step rotates the other way.
+    /// Calling this every frame animates everything drawn with those indices (e.g. water or fire) without redrawing it.
+    /// Panics if `start` is after `end`.
+    pub fn cycle_palette(&mut self, start: u8, end: u8, step: i32) {
+        if start > end {
+            panic!(
+                "The palette range starts at {} but ends before it, at {}!",
+                start, end
+            );
+        }
+        let range = &mut self.palette[start as usize..=end as usize];
+        let length = range.len() as i32;
+        range.rotate_right(step.rem_euclid(length) as usize);
+        self.refresh_indexed();
+    }
+
+    /// Redraws every indexed pixel with the current color of its palette entry.
+    fn refresh_indexed(&mut self) {
+        let mut changed = false;
+        for (pixel, index) in self.buffer.iter_mut().zip(&self.indices) {
+            if let Some(index) = index {
+                *pixel = self.palette[*index as usize];
+                changed = true;
+            }
+        }
+        if changed {
+            self.mark_all_dirty();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sdl2::pixels::Color;
+
+    use crate::tests::SDL;
+    use crate::RetroCanvas;
+
+    #[test]
+    fn palette_cycling() {
+        let _sdl = SDL.lock().unwrap_or_else(|e| e.into_inner());
+        let mut canvas = RetroCanvas::new_headless((32, 24)).unwrap();
+        let (red, green, blue) = (
+            Color::RGB(255, 0, 0),
+            Color::RGB(0, 255, 0),
+            Color::RGB(0, 0, 255),
+        );
+        canvas.set_palette_color(1, red);
+        canvas.set_palette_color(2, green);
+        canvas.set_palette_color(3, blue);
+        for i in 0..4 {
+            canvas.draw_pixel_indexed(i, 0, i as u8 + 1);
+        }
+        canvas.flip();
+        assert_eq!(
+            &canvas.screenshot()[..4],
+            &[red, green, blue, Color::RGB(0, 0, 0)]
+        );
+
+        canvas.cycle_palette(1, 3, 1);
+        canvas.flip();
+        assert_eq!(
+            &canvas.screenshot()[..4],
+            &[blue, red, green, Color::RGB(0, 0, 0)]
+        );
+
+        // Drawing over an indexed pixel stops it from following the palette.
+        canvas.draw_pixel(0, 0, Color::RGB(255, 255, 255));
+        canvas.cycle_palette(1, 3, -4);
+        canvas.flip();
+        assert_eq!(
+            &canvas.screenshot()[..4],
+            &[Color::RGB(255, 255, 255), green, blue, Color::RGB(0, 0, 0)]
+        );
+        assert_eq!(canvas.palette_color(1), red);
+    }
+}
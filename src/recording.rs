@@ -0,0 +1,309 @@
+use std::collections::{HashMap, VecDeque};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use sdl2::pixels::Color;
+
+use crate::RetroCanvas;
+
+/// The largest code an LZW code can have in a GIF.
+const MAX_CODE: u16 = 4096;
+
+/// The frames captured since `RetroCanvas::start_recording`, along with when they were presented.
+pub(crate) struct Recording {
+    frames: VecDeque<(Vec<Color>, Instant)>,
+    max_frames: usize,
+}
+
+/// Writes codes of varying sizes into bytes, least significant bit first, as GIF expects.
+struct BitWriter {
+    bytes: Vec<u8>,
+    buffer: u32,
+    bits: u8,
+}
+
+impl BitWriter {
+    fn write(&mut self, code: u16, size: u8) {
+        self.buffer |= (code as u32) << self.bits;
+        self.bits += size;
+        while self.bits >= 8 {
+            self.bytes.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.bits -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.bits > 0 {
+            self.bytes.push(self.buffer as u8);
+        }
+        self.bytes
+    }
+}
+
+/// Compresses the color indices of a frame with the variable-length LZW used by GIF.
+fn lzw_encode(indices: &[u8], min_code_size: u8) -> Vec<u8> {
+    let clear = 1 << min_code_size;
+    let end = clear + 1;
+    let mut writer = BitWriter {
+        bytes: Vec::new(),
+        buffer: 0,
+        bits: 0,
+    };
+    let mut dictionary: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut code_size = min_code_size + 1;
+    let mut next = end + 1;
+    writer.write(clear, code_size);
+    let mut prefix: Option<u16> = None;
+    for &index in indices {
+        let current = match prefix {
+            Some(current) => current,
+            None => {
+                prefix = Some(index as u16);
+                continue;
+            }
+        };
+        if let Some(&code) = dictionary.get(&(current, index)) {
+            prefix = Some(code);
+            continue;
+        }
+        writer.write(current, code_size);
+        if next == MAX_CODE {
+            writer.write(clear, code_size);
+            dictionary.clear();
+            code_size = min_code_size + 1;
+            next = end + 1;
+        } else {
+            dictionary.insert((current, index), next);
+            if next == 1 << code_size {
+                code_size += 1;
+            }
+            next += 1;
+        }
+        prefix = Some(index as u16);
+    }
+    if let Some(current) = prefix {
+        writer.write(current, code_size);
+        // The decoder adds an entry for the last code too, which can make the end code one bit longer.
+        if next == 1 << code_size && next < MAX_CODE {
+            code_size += 1;
+        }
+    }
+    writer.write(end, code_size);
+    writer.finish()
+}
+
+/// Turns a frame into a palette (of at most 256 colors) and the index of each pixel in it.
+/// Frames with more colors are reduced to 3 bits of red, 3 of green and 2 of blue.
+fn palettize(pixels: &[Color]) -> (Vec<Color>, Vec<u8>) {
+    let mut palette = Vec::new();
+    let mut lookup = HashMap::new();
+    let mut indices = Vec::with_capacity(pixels.len());
+    for pixel in pixels {
+        let key = (pixel.r, pixel.g, pixel.b);
+        let index = match lookup.get(&key) {
+            Some(index) => *index,
+            None if palette.len() < 256 => {
+                let index = palette.len() as u8;
+                lookup.insert(key, index);
+                palette.push(Color::RGB(pixel.r, pixel.g, pixel.b));
+                index
+            }
+            None => {
+                let palette = (0..=255)
+                    .map(|i: u8| Color::RGB(i & 0xE0, (i << 3) & 0xE0, (i << 6) & 0xC0))
+                    .collect();
+                let indices = pixels
+                    .iter()
+                    .map(|pixel| (pixel.r & 0xE0) | ((pixel.g & 0xE0) >> 3) | (pixel.b >> 6))
+                    .collect();
+                return (palette, indices);
+            }
+        };
+        indices.push(index);
+    }
+    (palette, indices)
+}
+
+/// Encodes frames as an animated GIF which loops forever. Each frame is shown for its duration, rounded to hundredths of a second.
+fn encode_gif(size: (u32, u32), frames: &[(&[Color], Duration)]) -> Vec<u8> {
+    let mut gif = b"GIF89a".to_vec();
+    gif.extend_from_slice(&(size.0 as u16).to_le_bytes());
+    gif.extend_from_slice(&(size.1 as u16).to_le_bytes());
+    // No global color table, every frame has its own.
+    gif.extend_from_slice(&[0, 0, 0]);
+    gif.extend_from_slice(b"\x21\xFF\x0BNETSCAPE2.0\x03\x01\x00\x00\x00");
+    for (pixels, duration) in frames {
+        let delay = ((duration.as_millis() + 5) / 10).clamp(1, u16::MAX as u128) as u16;
+        gif.extend_from_slice(&[0x21, 0xF9, 0x04, 0x04]);
+        gif.extend_from_slice(&delay.to_le_bytes());
+        gif.extend_from_slice(&[0, 0]);
+
+        let (palette, indices) = palettize(pixels);
+        // The color table has 2^(bits + 1) entries.
+        let bits = (palette.len().max(2) as f32).log2().ceil() as u8 - 1;
+        gif.push(0x2C);
+        gif.extend_from_slice(&[0, 0, 0, 0]);
+        gif.extend_from_slice(&(size.0 as u16).to_le_bytes());
+        gif.extend_from_slice(&(size.1 as u16).to_le_bytes());
+        gif.push(0x80 | bits);
+        for i in 0..1 << (bits + 1) {
+            let color = palette.get(i).copied().unwrap_or(Color::RGB(0, 0, 0));
+            gif.extend_from_slice(&[color.r, color.g, color.b]);
+        }
+
+        let min_code_size = (bits + 1).max(2);
+        gif.push(min_code_size);
+        for block in lzw_encode(&indices, min_code_size).chunks(255) {
+            gif.push(block.len() as u8);
+            gif.extend_from_slice(block);
+        }
+        gif.push(0);
+    }
+    gif.push(0x3B);
+    gif
+}
+
+impl RetroCanvas {
+    /// Starts recording the framebuffer, capturing it every time the canvas is presented (e.g. by `flip`).
+    /// Only the last `max_frames` frames are kept, to limit memory use. Restarts the recording if one was already going.
+    pub fn start_recording(&mut self, max_frames: usize) {
+        self.recording = Some(Recording {
+            frames: VecDeque::with_capacity(max_frames),
+            max_frames,
+        });
+    }
+
+    /// Returns whether a recording is going on.
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    /// Captures the framebuffer into the recording, if there is one.
+    pub(crate) fn capture_frame(&mut self) {
+        if let Some(recording) = &mut self.recording {
+            if recording.max_frames == 0 {
+                return;
+            }
+            if recording.frames.len() == recording.max_frames {
+                recording.frames.pop_front();
+            }
+            recording
+                .frames
+                .push_back((self.buffer.clone(), Instant::now()));
+        }
+    }
+
+    /// Stops recording, and saves the captured frames as an animated GIF at the simulated resolution.
+    /// Each frame lasts as long as it was on screen, so the GIF plays at the frame rate of the recording.
+    /// Returns an error if no recording was going on, if no frame was captured, or if the file can't be written.
+    pub fn stop_recording(&mut self, path: &Path) -> Result<(), String> {
+        let recording = self
+            .recording
+            .take()
+            .ok_or_else(|| "There is no recording to stop!".to_string())?;
+        if recording.frames.is_empty() {
+            return Err("No frame was recorded!".to_string());
+        }
+        let times: Vec<Instant> = recording.frames.iter().map(|(_, time)| *time).collect();
+        let durations: Vec<Duration> = times.windows(2).map(|pair| pair[1] - pair[0]).collect();
+        // The last frame has nothing after it, so it lasts as long as the one before it.
+        let last = durations.last().copied().unwrap_or(crate::FIXED_TIMESTEP);
+        let frames: Vec<(&[Color], Duration)> = recording
+            .frames
+            .iter()
+            .zip(durations.into_iter().chain(std::iter::once(last)))
+            .map(|((pixels, _), duration)| (pixels.as_slice(), duration))
+            .collect();
+        std::fs::write(path, encode_gif(self.simulated_size, &frames)).map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use sdl2::pixels::Color;
+
+    use crate::recording::{encode_gif, lzw_encode, palettize};
+    use crate::tests::SDL;
+    use crate::RetroCanvas;
+
+    #[test]
+    fn lzw() {
+        // Two runs of 1s and 2s, repeated: clear (4), 1, 6, 6, 2, 9, 9, 7, 8, 10, 2, end (5).
+        let indices = [1, 1, 1, 1, 1, 2, 2, 2, 2, 2, 1, 1, 1, 1, 1, 2, 2, 2, 2, 2];
+        let bytes = lzw_encode(&indices, 2);
+        // The codes start 3 bits wide, and grow to 4 bits once the dictionary reaches code 8.
+        let codes = [
+            (4, 3),
+            (1, 3),
+            (6, 3),
+            (6, 3),
+            (2, 4),
+            (9, 4),
+            (9, 4),
+            (7, 4),
+            (8, 4),
+            (10, 4),
+            (2, 4),
+            (5, 4),
+        ];
+        let mut bits = bytes
+            .iter()
+            .flat_map(|byte| (0..8).map(move |bit| (byte >> bit) & 1));
+        for (code, width) in codes {
+            let read = (0..width).fold(0, |read, bit| read | (bits.next().unwrap() as u16) << bit);
+            assert_eq!(read, code);
+        }
+        assert!(bits.all(|bit| bit == 0));
+        assert_eq!(bytes.len(), 6);
+    }
+
+    #[test]
+    fn palette() {
+        let (red, blue) = (Color::RGB(255, 0, 0), Color::RGB(0, 0, 255));
+        assert_eq!(
+            palettize(&[red, blue, red]),
+            (vec![red, blue], vec![0, 1, 0])
+        );
+        let many: Vec<Color> = (0..300)
+            .map(|i| Color::RGB(i as u8, (i / 256) as u8, 0))
+            .collect();
+        let (palette, indices) = palettize(&many);
+        assert_eq!(palette.len(), 256);
+        assert_eq!(palette[indices[255] as usize], Color::RGB(224, 0, 0));
+    }
+
+    #[test]
+    fn gif() {
+        let pixels = vec![Color::RGB(0, 0, 255); 4];
+        let gif = encode_gif((2, 2), &[(&pixels, Duration::from_millis(100))]);
+        assert!(gif.starts_with(b"GIF89a"));
+        assert_eq!(&gif[6..10], &[2, 0, 2, 0]);
+        assert_eq!(gif.last(), Some(&0x3B));
+    }
+
+    #[test]
+    fn recording() {
+        let _sdl = SDL.lock().unwrap_or_else(|e| e.into_inner());
+        let mut canvas = RetroCanvas::new_headless((32, 24)).unwrap();
+        let path = std::env::temp_dir().join("retro_core_recording.gif");
+        assert!(canvas.stop_recording(&path).is_err());
+        canvas.start_recording(3);
+        for i in 0..5 {
+            canvas.clear_buffer(Color::RGB(i * 50, 0, 255 - i * 50));
+            canvas.flip();
+        }
+        canvas.stop_recording(&path).unwrap();
+        assert!(!canvas.is_recording());
+        let gif = std::fs::read(&path).unwrap();
+        assert!(gif.starts_with(b"GIF89a"));
+        // Only the last three frames are kept, each with its own graphic control extension.
+        assert_eq!(
+            gif.windows(3).filter(|w| w == &[0x21, 0xF9, 0x04]).count(),
+            3
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,84 @@
+use std::io::{self, Read, Write};
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// Writes the code produced by a tokenizer, so that it can be loaded again with `read_code` instead of being processed again.
+/// The format is the amount of codes, followed by each code prefixed with its length, all lengths being little-endian u32s.
+pub fn write_code<C>(codes: &[C], mut w: impl Write) -> io::Result<()>
+where
+    C: Serialize,
+{
+    w.write_all(&(codes.len() as u32).to_le_bytes())?;
+    for code in codes {
+        let bytes = bincode::serialize(code).map_err(invalid_data)?;
+        w.write_all(&(bytes.len() as u32).to_le_bytes())?;
+        w.write_all(&bytes)?;
+    }
+    Ok(())
+}
+
+/// Reads code written by `write_code`. The lengths aren't trusted: only the bytes actually present are allocated,
+/// so truncated or corrupt input is an error rather than a huge allocation.
+pub fn read_code<C>(mut r: impl Read) -> io::Result<Vec<C>>
+where
+    C: DeserializeOwned,
+{
+    let count = read_u32(&mut r)?;
+    let mut codes = Vec::new();
+    for _ in 0..count {
+        let len = read_u32(&mut r)? as u64;
+        let mut bytes = Vec::new();
+        (&mut r).take(len).read_to_end(&mut bytes)?;
+        if bytes.len() as u64 != len {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        codes.push(bincode::deserialize(&bytes).map_err(invalid_data)?);
+    }
+    Ok(codes)
+}
+
+fn invalid_data(error: bincode::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+fn read_u32(r: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    r.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+
+    use crate::tokenizing::bytecode::{read_code, write_code};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    enum Code {
+        Load(u8, i32),
+        Print(String),
+        Halt,
+    }
+
+    #[test]
+    fn round_trip_test() {
+        let codes = vec![
+            Code::Load(0, -5),
+            Code::Print("hello".to_string()),
+            Code::Halt,
+        ];
+        let mut bytes = Vec::new();
+        write_code(&codes, &mut bytes).unwrap();
+        assert_eq!(read_code::<Code>(bytes.as_slice()).unwrap(), codes);
+        assert!(read_code::<Code>(&bytes[..bytes.len() - 1]).is_err());
+        // A single code claiming to be 4 GiB long, with only a few bytes behind it.
+        let mut huge = 1u32.to_le_bytes().to_vec();
+        huge.extend(u32::MAX.to_le_bytes());
+        huge.extend([0; 8]);
+        assert_eq!(
+            read_code::<Code>(huge.as_slice()).unwrap_err().kind(),
+            std::io::ErrorKind::UnexpectedEof
+        );
+    }
+}
@@ -0,0 +1,104 @@
+type Predicate = Box<dyn Fn(char) -> bool>;
+
+type SubParser<Token, Error> = Box<dyn Fn(&str) -> Result<Vec<Token>, Error>>;
+
+/// Builds a parser for `Tokenizer::new` out of sub-parsers, each handling the fragments whose first character matches a predicate
+/// (e.g. digits for numbers and letters for identifiers). Each fragment goes to the first matching sub-parser, and empty fragments are skipped.
+pub struct CharClassParser<Token, Error> {
+    parsers: Vec<(Predicate, SubParser<Token, Error>)>,
+    fallback: Option<SubParser<Token, Error>>,
+}
+
+impl<Token, Error> CharClassParser<Token, Error> {
+    pub fn new() -> Self {
+        CharClassParser {
+            parsers: Vec::new(),
+            fallback: None,
+        }
+    }
+
+    /// Adds a sub-parser for the fragments whose first character matches the predicate. Sub-parsers are tried in the order they were added.
+    pub fn add<P, F>(mut self, predicate: P, parser: F) -> Self
+    where
+        P: Fn(char) -> bool + 'static,
+        F: Fn(&str) -> Result<Vec<Token>, Error> + 'static,
+    {
+        self.parsers.push((Box::new(predicate), Box::new(parser)));
+        self
+    }
+
+    /// Sets the sub-parser for the fragments which no other sub-parser matches, e.g. to return an error.
+    /// Without one, such fragments cause a panic.
+    pub fn with_fallback<F>(mut self, parser: F) -> Self
+    where
+        F: Fn(&str) -> Result<Vec<Token>, Error> + 'static,
+    {
+        self.fallback = Some(Box::new(parser));
+        self
+    }
+
+    /// Creates the parser, to be given to `Tokenizer::new`.
+    pub fn build<State>(self) -> impl Fn(String, &mut State) -> Result<Vec<Token>, Error> {
+        move |fragment: String, _: &mut State| {
+            let first = match fragment.chars().next() {
+                Some(first) => first,
+                None => return Ok(Vec::new()),
+            };
+            match self.parsers.iter().find(|(predicate, _)| predicate(first)) {
+                Some((_, parser)) => parser(&fragment),
+                None => match &self.fallback {
+                    Some(fallback) => fallback(&fragment),
+                    None => panic!("No parser accepts the fragment {:?}!", fragment),
+                },
+            }
+        }
+    }
+}
+
+impl<Token, Error> Default for CharClassParser<Token, Error> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tokenizing::char_class::CharClassParser;
+    use crate::tokenizing::simple_rules::{parse_int, BasicToken};
+    use crate::tokenizing::Tokenizer;
+
+    #[test]
+    fn char_class_test() {
+        let parser = CharClassParser::new()
+            .add(
+                |c| c.is_ascii_digit() || c == '-',
+                |s| {
+                    Ok(vec![BasicToken::Number(
+                        parse_int(s).map_err(|e| e.to_string())?,
+                    )])
+                },
+            )
+            .add(char::is_alphabetic, |s| {
+                Ok(vec![BasicToken::Text(s.to_string())])
+            })
+            .with_fallback(|s| Err(format!("Unexpected {:?}", s)))
+            .build();
+        let tokenizer: Tokenizer<BasicToken, String, (), _, ()> =
+            Tokenizer::new(parser).split_on_whitespace();
+        assert_eq!(
+            tokenizer.tokenize("mov 42".to_string(), &mut ()),
+            Ok(vec![vec![
+                BasicToken::Text("mov".to_string()),
+                BasicToken::Number(42)
+            ]])
+        );
+        assert_eq!(
+            tokenizer.tokenize("mov 4x".to_string(), &mut ()),
+            Err("invalid digit found in string".to_string())
+        );
+        assert_eq!(
+            tokenizer.tokenize("mov #".to_string(), &mut ()),
+            Err("Unexpected \"#\"".to_string())
+        );
+    }
+}
@@ -0,0 +1,123 @@
+use crate::tokenizing::simple_rules::{parse_int, BasicToken};
+
+/// Matches a whole fragment, giving its token, or None if the fragment doesn't match.
+pub type Combinator<Token> = Box<dyn Fn(&str) -> Option<Token>>;
+
+/// A token type which the building blocks of this module can produce.
+pub trait CombinatorToken {
+    fn literal(text: &str) -> Self;
+    fn number(number: i32) -> Self;
+    fn identifier(text: &str) -> Self;
+}
+
+impl CombinatorToken for BasicToken {
+    fn literal(text: &str) -> Self {
+        BasicToken::Text(text.to_string())
+    }
+
+    fn number(number: i32) -> Self {
+        BasicToken::Number(number)
+    }
+
+    fn identifier(text: &str) -> Self {
+        BasicToken::Text(text.to_string())
+    }
+}
+
+/// Matches exactly the given text, e.g. a keyword.
+pub fn literal<Token>(text: &str) -> Combinator<Token>
+where
+    Token: CombinatorToken,
+{
+    let text = text.to_string();
+    Box::new(move |fragment| (fragment == text).then(|| Token::literal(fragment)))
+}
+
+/// Matches an integer, in any of the formats accepted by `parse_int`.
+pub fn number<Token>() -> Combinator<Token>
+where
+    Token: CombinatorToken,
+{
+    Box::new(|fragment| parse_int(fragment).ok().map(Token::number))
+}
+
+/// Matches a letter or underscore, followed by any amount of letters, digits and underscores.
+pub fn identifier<Token>() -> Combinator<Token>
+where
+    Token: CombinatorToken,
+{
+    Box::new(|fragment| {
+        let mut chars = fragment.chars();
+        let valid = matches!(chars.next(), Some(c) if c.is_alphabetic() || c == '_')
+            && chars.all(|c| c.is_alphanumeric() || c == '_');
+        valid.then(|| Token::identifier(fragment))
+    })
+}
+
+/// Matches whatever the first matching combinator matches, trying them in order.
+pub fn one_of<Token>(combinators: impl IntoIterator<Item = Combinator<Token>>) -> Combinator<Token>
+where
+    Token: 'static,
+{
+    let combinators: Vec<Combinator<Token>> = combinators.into_iter().collect();
+    Box::new(move |fragment| {
+        combinators
+            .iter()
+            .find_map(|combinator| combinator(fragment))
+    })
+}
+
+/// Creates a parser for `Tokenizer::new` from a combinator, which turns each fragment into a single token.
+/// Empty fragments are skipped, and fragments which don't match return an error.
+pub fn parser<Token, State>(
+    combinator: Combinator<Token>,
+) -> impl Fn(String, &mut State) -> Result<Vec<Token>, String> {
+    move |fragment: String, _: &mut State| {
+        if fragment.is_empty() {
+            return Ok(Vec::new());
+        }
+        match combinator(&fragment) {
+            Some(token) => Ok(vec![token]),
+            None => Err(format!("Unexpected {:?}", fragment)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tokenizing::combinators::{identifier, literal, number, one_of, parser};
+    use crate::tokenizing::simple_rules::BasicToken;
+    use crate::tokenizing::Tokenizer;
+
+    #[test]
+    fn combinators_test() {
+        let instruction = one_of([literal("mov"), literal("add")]);
+        let tokenizer: Tokenizer<BasicToken, String, (), _, ()> =
+            Tokenizer::new(parser(one_of([instruction, number()]))).split_on_whitespace();
+        assert_eq!(
+            tokenizer.tokenize("mov 5".to_string(), &mut ()),
+            Ok(vec![vec![
+                BasicToken::Text("mov".to_string()),
+                BasicToken::Number(5)
+            ]])
+        );
+        assert_eq!(
+            tokenizer.tokenize("add 0x10".to_string(), &mut ()),
+            Ok(vec![vec![
+                BasicToken::Text("add".to_string()),
+                BasicToken::Number(16)
+            ]])
+        );
+        assert_eq!(
+            tokenizer.tokenize("sub 5".to_string(), &mut ()),
+            Err("Unexpected \"sub\"".to_string())
+        );
+        let label = identifier::<BasicToken>();
+        assert_eq!(
+            label("_loop2"),
+            Some(BasicToken::Text("_loop2".to_string()))
+        );
+        assert_eq!(label("2loop"), None);
+        assert_eq!(label("loop-2"), None);
+    }
+}
@@ -1,17 +1,188 @@
+use std::collections::HashMap;
 use std::fmt::Debug;
+use std::hash::Hash;
+use std::ops::Range;
 
+#[cfg(feature = "serde")]
+pub mod bytecode;
+pub mod char_class;
+pub mod combinators;
 pub mod simple_rules;
 
+type Condition<Token, State> = Box<dyn Fn(&Vec<Token>, &mut State) -> bool>;
+
+type RawErrorHook<Error> = Box<dyn Fn(&str, usize) -> Error>;
+
+#[cfg(feature = "regex")]
+type RegexRule<Code, Error, State> = (
+    regex::Regex,
+    Box<dyn Fn(Vec<String>, &mut State) -> Result<Vec<Code>, Error>>,
+);
+
+type LimitErrorHook<Error> = Box<dyn Fn(TokenizerLimit) -> Error>;
+
+type RecoveryCode<Code> = Box<dyn Fn(usize) -> Code>;
+
+type UnclosedBlockError<Error> = Box<dyn Fn(usize) -> Error>;
+
+type IndentationMarkers<Token> = Box<dyn Fn(isize) -> Vec<Token>>;
+
+type NoMatchError<Token, Error> = fn(&Vec<Token>) -> Error;
+
+type StateSnapshot<State> = fn(&State) -> State;
+
+type AliasExpander<Token> = fn(&HashMap<Token, Vec<Token>>, Vec<Token>) -> Vec<Token>;
+
+/// Replaces every aliased token by its replacement. Replacements aren't expanded again, so aliases can't loop.
+fn expand_aliases<Token>(aliases: &HashMap<Token, Vec<Token>>, tokens: Vec<Token>) -> Vec<Token>
+where
+    Token: Eq + Hash + Clone,
+{
+    tokens
+        .into_iter()
+        .flat_map(|token| match aliases.get(&token) {
+            Some(replacement) => replacement.clone(),
+            None => vec![token],
+        })
+        .collect()
+}
+
+/// A limit set on a Tokenizer which the input exceeded, given to the function creating its error.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum TokenizerLimit {
+    /// The (0-indexed) line `line` is `length` bytes long, more than the `max` set with `with_max_line_length`.
+    LineLength {
+        line: usize,
+        length: usize,
+        max: usize,
+    },
+    /// The input has more than the `max` lines set with `with_max_lines`.
+    Lines { max: usize },
+}
+
+/// A line which nothing could process: no rule accepted it, or its indentation matches no enclosing line.
+/// When no hook handles it, `process_recovering` and `TokenizerSession` turn it into an error with `From`, instead of panicking.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct RejectedLine {
+    /// The (0-indexed) line number.
+    pub line: usize,
+    pub text: String,
+}
+
+impl From<RejectedLine> for String {
+    fn from(rejected: RejectedLine) -> Self {
+        format!(
+            "no rule accepted line {}: {:?}",
+            rejected.line, rejected.text
+        )
+    }
+}
+
+impl From<RejectedLine> for () {
+    fn from(_: RejectedLine) -> Self {}
+}
+
+/// What is carried from one line to the next while processing: the block being collected (the index of its rule,
+/// the line it started on and its lines so far), and the indentation levels of the enclosing lines.
+struct Progress<Token> {
+    block: Option<(usize, usize, Vec<Vec<Token>>)>,
+    indentation: Vec<usize>,
+}
+
+impl<Token> Progress<Token> {
+    fn new() -> Self {
+        Progress {
+            block: None,
+            indentation: vec![0],
+        }
+    }
+}
+
+/// The position of a fragment in the input. The line is 0-indexed, and the column and length are in bytes.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Span {
+    pub line: usize,
+    pub col: usize,
+    pub len: usize,
+}
+
+/// An error along with where it happened, e.g. to underline it in a diagnostic. Returned by `Tokenizer::process_spanned`.
+/// The line is 0-indexed, and the columns are in bytes. They cover the fragment whose parsing failed, or the whole line for other errors.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SpannedError<E> {
+    pub line: usize,
+    pub col_range: Range<usize>,
+    pub inner: E,
+}
+
+/// What happened while processing a line: which rule handled it, and which fragment failed to parse.
+#[derive(Default)]
+struct LineReport {
+    rule: Option<usize>,
+    failed_fragment: Option<Span>,
+}
+
+/// Something which can turn a fragment of a line into tokens. Returning no tokens skips the fragment.
+/// Implemented by closures taking a `String` (which ignore the span), and by `Spanned` closures taking a `(String, Span)`.
+pub trait FragmentParser<Token, Error, State> {
+    fn parse(&self, fragment: String, span: Span, state: &mut State) -> Result<Vec<Token>, Error>;
+}
+
+impl<Token, Error, State, F> FragmentParser<Token, Error, State> for F
+where
+    F: Fn(String, &mut State) -> Result<Vec<Token>, Error>,
+{
+    fn parse(&self, fragment: String, _span: Span, state: &mut State) -> Result<Vec<Token>, Error> {
+        self(fragment, state)
+    }
+}
+
+/// A parser which also receives the span of each fragment. Created by `Tokenizer::with_spanned_parser`.
+pub struct Spanned<F>(F);
+
+impl<Token, Error, State, F> FragmentParser<Token, Error, State> for Spanned<F>
+where
+    F: Fn((String, Span), &mut State) -> Result<Vec<Token>, Error>,
+{
+    fn parse(&self, fragment: String, span: Span, state: &mut State) -> Result<Vec<Token>, Error> {
+        (self.0)((fragment, span), state)
+    }
+}
+
 pub struct Tokenizer<Token, Error, Code, Parser, State>
 where
-    Parser: Fn(String, &mut State) -> Result<Vec<Token>, Error>,
+    Parser: FragmentParser<Token, Error, State>,
 {
     splitters: Vec<char>,
+    escape_char: Option<char>,
+    raw_line_mode: bool,
+    case_insensitive: bool,
+    collapse_whitespace: bool,
+    ignore_empty_fragments: bool,
+    skip_blank_lines: bool,
+    aliases: HashMap<Token, Vec<Token>>,
+    expand_aliases: Option<AliasExpander<Token>>,
     parser: Parser,
+    raw_error: Option<RawErrorHook<Error>>,
+    no_match: Option<NoMatchError<Token, Error>>,
+    max_line_length: Option<(usize, LimitErrorHook<Error>)>,
+    max_lines: Option<(usize, LimitErrorHook<Error>)>,
+    recovery_code: Option<RecoveryCode<Code>>,
+    snapshot_state: Option<StateSnapshot<State>>,
+    indentation: Option<(usize, IndentationMarkers<Token>)>,
+    #[cfg(feature = "regex")]
+    regex_rules: Vec<RegexRule<Code, Error, State>>,
     rules: Vec<(
-        Box<dyn Fn(&Vec<Token>, &mut State) -> bool>,
+        Condition<Token, State>,
         Box<dyn Fn(Vec<Token>, &mut State) -> Result<Vec<Code>, Error>>,
     )>,
+    rule_names: Vec<Option<String>>,
+    block_rules: Vec<(
+        Condition<Token, State>,
+        Condition<Token, State>,
+        Box<dyn Fn(Vec<Vec<Token>>, &mut State) -> Result<Vec<Code>, Error>>,
+        UnclosedBlockError<Error>,
+    )>,
 }
 
 impl<Token, Error, Code, Parser, State>
@@ -21,19 +192,144 @@ where
     Token: Debug
 {
     pub fn new(parser: Parser) -> Self {
+        Self::with_parser(parser)
+    }
+}
+
+impl<Token, Code, Parser, State> Tokenizer<Token, String, Code, Parser, State>
+where
+    Parser: Fn(String, &mut State) -> Result<Vec<Token>, String>,
+    Token: Debug,
+{
+    /// Like `new`, for tokenizers whose errors are strings: lines which no rule accepts return an error listing their tokens, instead of panicking.
+    /// A hook set with `with_raw_error` still takes precedence.
+    pub fn with_string_error(parser: Parser) -> Self {
+        let mut tokenizer = Self::new(parser);
+        tokenizer.no_match = Some(|tokens| format!("no rule matched: {:?}", tokens));
+        tokenizer
+    }
+}
+
+impl<Token, Error, Code, F, State> Tokenizer<Token, Error, Code, Spanned<F>, State>
+where
+    F: Fn((String, Span), &mut State) -> Result<Vec<Token>, Error>,
+    Token: Debug,
+{
+    /// Creates a tokenizer whose parser also receives the position of each fragment in the input.
+    pub fn with_spanned_parser(parser: F) -> Self {
+        Self::with_parser(Spanned(parser))
+    }
+}
+
+impl<Token, Error, Code, Parser, State> Tokenizer<Token, Error, Code, Parser, State>
+where
+    Parser: FragmentParser<Token, Error, State>,
+{
+    /// Creates a tokenizer with the given parser and nothing else set, for `new` and `with_spanned_parser`.
+    fn with_parser(parser: Parser) -> Self {
         Self {
             splitters: Vec::new(),
+            escape_char: None,
+            raw_line_mode: false,
+            case_insensitive: false,
+            collapse_whitespace: false,
+            ignore_empty_fragments: false,
+            skip_blank_lines: false,
+            aliases: HashMap::new(),
+            expand_aliases: None,
             parser,
+            raw_error: None,
+            no_match: None,
+            max_line_length: None,
+            max_lines: None,
+            recovery_code: None,
+            snapshot_state: None,
+            indentation: None,
+            #[cfg(feature = "regex")]
+            regex_rules: Vec::new(),
             rules: Vec::new(),
+            rule_names: Vec::new(),
+            block_rules: Vec::new(),
         }
     }
+}
+
+impl<Token, Error, Code, Parser, State> Tokenizer<Token, Error, Code, Parser, State>
+where
+    Parser: FragmentParser<Token, Error, State>,
+    Token: Debug,
+    State: Clone,
+{
+    /// Snapshots the state before each line, and restores it if the line errors, so that the fragments parsed (or rules run)
+    /// before the error don't leave partial changes behind. This only matters for `process_recovering` and `TokenizerSession::feed_line`,
+    /// as the other ways of processing stop at the first error.
+    pub fn rollback_state_on_error(mut self) -> Self {
+        self.snapshot_state = Some(State::clone);
+        self
+    }
+}
 
+impl<Token, Error, Code, Parser, State> Tokenizer<Token, Error, Code, Parser, State>
+where
+    Parser: FragmentParser<Token, Error, State>,
+    Token: Debug,
+{
     pub fn add_rule<X, Y>(mut self, condition: X, parse: Y) -> Self
     where
         X: Fn(&Vec<Token>, &mut State) -> bool + 'static,
         Y: Fn(Vec<Token>, &mut State) -> Result<Vec<Code>, Error> + 'static,
     {
         self.rules.push((Box::new(condition), Box::new(parse)));
+        self.rule_names.push(None);
+        self
+    }
+
+    /// Like `add_rule`, but with a name, which `rule_name` gives back, e.g. to make the trace of `process_traced` readable.
+    pub fn add_named_rule<X, Y>(mut self, name: &str, condition: X, parse: Y) -> Self
+    where
+        X: Fn(&Vec<Token>, &mut State) -> bool + 'static,
+        Y: Fn(Vec<Token>, &mut State) -> Result<Vec<Code>, Error> + 'static,
+    {
+        self = self.add_rule(condition, parse);
+        *self.rule_names.last_mut().unwrap() = Some(name.to_string());
+        self
+    }
+
+    /// The name of the rule at the given index (in the order the rules were added), if it was added with `add_named_rule`.
+    pub fn rule_name(&self, index: usize) -> Option<&str> {
+        self.rule_names.get(index)?.as_deref()
+    }
+
+    /// Adds a rule which accepts lines matching the regex, matched against the raw text of the line (before splitting).
+    /// The capture groups are given to `parse` in order, with groups that didn't participate in the match as empty strings.
+    /// Regex rules are checked before regular rules. Returns an error if the pattern isn't a valid regex.
+    #[cfg(feature = "regex")]
+    pub fn add_regex_rule<Y>(mut self, pattern: &str, parse: Y) -> Result<Self, regex::Error>
+    where
+        Y: Fn(Vec<String>, &mut State) -> Result<Vec<Code>, Error> + 'static,
+    {
+        self.regex_rules
+            .push((regex::Regex::new(pattern)?, Box::new(parse)));
+        Ok(self)
+    }
+
+    /// Adds a rule for blocks spanning multiple lines. A block starts on a line accepted by `start`, and ends on the next line accepted by `end`.
+    /// All the lines of the block, including the first and last one, are given to `parse` together.
+    /// Block rules are checked before regular rules. If the input ends before the block does, processing fails with the error created by `unclosed`,
+    /// which receives the (0-indexed) line the block started on.
+    pub fn add_block_rule<X, Y, Z, W>(mut self, start: X, end: Y, parse: Z, unclosed: W) -> Self
+    where
+        X: Fn(&Vec<Token>, &mut State) -> bool + 'static,
+        Y: Fn(&Vec<Token>, &mut State) -> bool + 'static,
+        Z: Fn(Vec<Vec<Token>>, &mut State) -> Result<Vec<Code>, Error> + 'static,
+        W: Fn(usize) -> Error + 'static,
+    {
+        self.block_rules.push((
+            Box::new(start),
+            Box::new(end),
+            Box::new(parse),
+            Box::new(unclosed),
+        ));
         self
     }
 
@@ -42,31 +338,601 @@ where
         self
     }
 
-    fn process_line(&self, input: Vec<Token>, state: &mut State) -> Result<Vec<Code>, Error> {
-        for rule in &self.rules {
+    /// Lets splitters be escaped, so that e.g. `foo\,bar` with `\` as the escape char and a `,` splitter gives the single fragment `foo,bar`.
+    /// The escape char can also escape itself. Before any other character, it is kept as is.
+    pub fn with_escape_char(mut self, escape_char: char) -> Self {
+        self.escape_char = Some(escape_char);
+        self
+    }
+
+    /// Gives each whole line to the parser as a single fragment, for parsers which do all of their own lexing.
+    /// Splitters (including `split_on_whitespace`) are ignored in this mode.
+    pub fn raw_line_mode(mut self) -> Self {
+        self.raw_line_mode = true;
+        self
+    }
+
+    /// Lowercases every fragment before it reaches the parser, for case-insensitive languages.
+    /// This applies to the whole fragment, so if your language has case-sensitive parts (such as string literals),
+    /// leave this off and compare case-insensitively in your rules instead, e.g. with `simple_rules::first_matches_ci`.
+    pub fn case_insensitive(mut self) -> Self {
+        self.case_insensitive = true;
+        self
+    }
+
+    /// Splits on every ASCII whitespace character, collapsing runs of whitespace so that `"a   b"` gives two fragments.
+    /// Empty fragments next to whitespace (including leading and trailing whitespace) are dropped; empty fragments between other splitters are kept.
+    pub fn split_on_whitespace(mut self) -> Self {
+        for splitter in [' ', '\t', '\n', '\r', '\x0c'] {
+            if !self.splitters.contains(&splitter) {
+                self.splitters.push(splitter);
+            }
+        }
+        self.collapse_whitespace = true;
+        self
+    }
+
+    /// Drops empty fragments (e.g. between two consecutive splitters) instead of giving them to the parser.
+    pub fn ignore_empty_fragments(mut self) -> Self {
+        self.ignore_empty_fragments = true;
+        self
+    }
+
+    /// Adds an alias, so that every `from` token is replaced by the `to` tokens after parsing, before the rules see the line.
+    /// The replacement tokens aren't expanded again.
+    pub fn add_alias(mut self, from: Token, to: Vec<Token>) -> Self
+    where
+        Token: Eq + Hash + Clone,
+    {
+        self.aliases.insert(from, to);
+        self.expand_aliases = Some(expand_aliases::<Token>);
+        self
+    }
+
+    /// Makes lines which no rule accepts return the error created by `f`, instead of panicking.
+    /// `f` receives the original text of the line and its (0-indexed) line number, e.g. to quote the line in an error message.
+    pub fn with_raw_error<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&str, usize) -> Error + 'static,
+    {
+        self.raw_error = Some(Box::new(f));
+        self
+    }
+
+    /// Makes lines longer than `max` bytes an error, created by `error`, e.g. to guard against huge lines in untrusted input.
+    pub fn with_max_line_length<F>(mut self, max: usize, error: F) -> Self
+    where
+        F: Fn(TokenizerLimit) -> Error + 'static,
+    {
+        self.max_line_length = Some((max, Box::new(error)));
+        self
+    }
+
+    /// Makes input with more than `max` lines (including blank ones) an error, created by `error`, e.g. to guard against huge untrusted input.
+    /// Processing stops at the first line past the limit, even in `process_recovering`.
+    pub fn with_max_lines<F>(mut self, max: usize, error: F) -> Self
+    where
+        F: Fn(TokenizerLimit) -> Error + 'static,
+    {
+        self.max_lines = Some((max, Box::new(error)));
+        self
+    }
+
+    /// Makes `process_recovering` emit the code created by `recovery_code` in place of every line which fails, so that the output keeps one entry per bad line.
+    /// `recovery_code` receives the (0-indexed) line number of the failed line.
+    pub fn recover<F>(mut self, recovery_code: F) -> Self
+    where
+        F: Fn(usize) -> Code + 'static,
+    {
+        self.recovery_code = Some(Box::new(recovery_code));
+        self
+    }
+
+    /// Makes indentation significant, for languages which use it for blocks (like Python).
+    /// Each line's leading whitespace is measured in levels of `spaces_per_level` spaces (a tab counts as a whole level),
+    /// and whenever it changes, the tokens created by `markers` are put before the line's tokens. `markers` receives how many levels were
+    /// opened (1 for an indent) or closed (negative, one per dedent). Blank lines don't change the indentation.
+    /// Dedenting to a level which no enclosing line has is an error, created with the `with_raw_error` hook (or from a `RejectedLine` without it, which panics outside of `process_recovering` and sessions).
+    /// Levels still open at the end of the input aren't closed with markers.
+    /// Panics if `spaces_per_level` is 0.
+    pub fn with_indentation<F>(mut self, spaces_per_level: usize, markers: F) -> Self
+    where
+        F: Fn(isize) -> Vec<Token> + 'static,
+    {
+        assert!(
+            spaces_per_level > 0,
+            "An indentation level must be at least 1 space wide!"
+        );
+        self.indentation = Some((spaces_per_level, Box::new(markers)));
+        self
+    }
+
+    /// Ignores lines which are empty or only contain whitespace, instead of giving them to the rules.
+    pub fn skip_blank_lines(mut self) -> Self {
+        self.skip_blank_lines = true;
+        self
+    }
+
+    /// The lines of the input along with their line numbers, without blank lines if they are skipped.
+    fn lines<'a>(&'a self, input: &'a str) -> impl Iterator<Item = (usize, &'a str)> + 'a {
+        input
+            .lines()
+            .enumerate()
+            .filter(move |(_, line)| !(self.skip_blank_lines && line.trim().is_empty()))
+    }
+
+    fn process_line(
+        &self,
+        line_number: usize,
+        line: &str,
+        input: Vec<Token>,
+        state: &mut State,
+        report: &mut LineReport,
+        rejected: Option<fn(RejectedLine) -> Error>,
+    ) -> Result<Vec<Code>, Error> {
+        #[cfg(feature = "regex")]
+        for (regex, parse) in &self.regex_rules {
+            if let Some(captures) = regex.captures(line) {
+                let groups = captures
+                    .iter()
+                    .skip(1)
+                    .map(|group| group.map_or(String::new(), |group| group.as_str().to_string()))
+                    .collect();
+                return parse(groups, state);
+            }
+        }
+        for (index, rule) in self.rules.iter().enumerate() {
             if rule.0(&input, state) {
+                report.rule = Some(index);
                 return rule.1(input, state);
             }
         }
+        if let Some(raw_error) = &self.raw_error {
+            return Err(raw_error(line, line_number));
+        }
+        if let Some(no_match) = self.no_match {
+            return Err(no_match(&input));
+        }
+        if let Some(rejected) = rejected {
+            return Err(rejected(RejectedLine {
+                line: line_number,
+                text: line.to_string(),
+            }));
+        }
         panic!("No rule has accepted the line {:?}!", input);
     }
 
-    pub fn process(self, input: String, mut initial_state: State) -> Result<Vec<Code>, Error> {
+    fn tokenize_line(
+        &self,
+        line_number: usize,
+        line: &str,
+        state: &mut State,
+        failed_fragment: &mut Option<Span>,
+    ) -> Result<Vec<Token>, Error> {
+        let mut token_list = vec![];
+        let mut start = 0;
+        let mut previous_splitter = None;
+        let mut ends = vec![];
+        let mut chars = line.char_indices().peekable();
+        while let Some((i, c)) = chars.next() {
+            if self.raw_line_mode {
+                break;
+            }
+            if self.escapes(c, chars.peek().map(|(_, next)| *next)) {
+                chars.next();
+            } else if self.splitters.contains(&c) {
+                ends.push((i, i + c.len_utf8(), Some(c)));
+            }
+        }
+        ends.push((line.len(), line.len(), None));
+        for (end, next_start, splitter) in ends {
+            let next_to_whitespace = [previous_splitter, splitter]
+                .iter()
+                .flatten()
+                .any(|c| c.is_ascii_whitespace());
+            previous_splitter = splitter;
+            if start == end
+                && (self.ignore_empty_fragments || self.collapse_whitespace && next_to_whitespace)
+            {
+                start = next_start;
+                continue;
+            }
+            let span = Span {
+                line: line_number,
+                col: start,
+                len: end - start,
+            };
+            let fragment = self.unescape(&line[start..end]);
+            let fragment = if self.case_insensitive {
+                fragment.to_lowercase()
+            } else {
+                fragment
+            };
+            let mut tokens = self
+                .parser
+                .parse(fragment, span, state)
+                .inspect_err(|_| *failed_fragment = Some(span))?;
+            token_list.append(&mut tokens);
+            start = next_start;
+        }
+        if let Some(expand_aliases) = self.expand_aliases {
+            token_list = expand_aliases(&self.aliases, token_list);
+        }
+        Ok(token_list)
+    }
+
+    /// Whether `c` is the escape char, escaping the `next` character.
+    fn escapes(&self, c: char, next: Option<char>) -> bool {
+        match next {
+            Some(next) => {
+                Some(c) == self.escape_char && (next == c || self.splitters.contains(&next))
+            }
+            None => false,
+        }
+    }
+
+    /// Removes the escape chars from a fragment, keeping the characters they escape.
+    fn unescape(&self, fragment: &str) -> String {
+        let mut result = String::with_capacity(fragment.len());
+        let mut chars = fragment.chars().peekable();
+        while let Some(c) = chars.next() {
+            if self.escapes(c, chars.peek().copied()) {
+                result.push(chars.next().unwrap());
+            } else {
+                result.push(c);
+            }
+        }
+        result
+    }
+
+    /// Runs only the splitting and parsing stages, returning the tokens of each line without applying any rules.
+    pub fn tokenize(&self, input: String, state: &mut State) -> Result<Vec<Vec<Token>>, Error> {
+        self.lines(&input)
+            .map(|(line_number, i)| {
+                self.check_line_count(line_number)?;
+                self.check_line_length(line_number, i)?;
+                self.tokenize_line(line_number, i, state, &mut None)
+            })
+            .collect()
+    }
+
+    fn check_line_count(&self, line_number: usize) -> Result<(), Error> {
+        match &self.max_lines {
+            Some((max, error)) if line_number >= *max => {
+                Err(error(TokenizerLimit::Lines { max: *max }))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn check_line_length(&self, line_number: usize, line: &str) -> Result<(), Error> {
+        match &self.max_line_length {
+            Some((max, error)) if line.len() > *max => Err(error(TokenizerLimit::LineLength {
+                line: line_number,
+                length: line.len(),
+                max: *max,
+            })),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn process(self, input: String, initial_state: State) -> Result<Vec<Code>, Error> {
+        self.process_with_state(input, initial_state)
+            .map(|(output, _)| output)
+    }
+
+    /// Like `process`, but also returns the final state, e.g. to inspect a label table built while processing.
+    pub fn process_with_state(
+        &self,
+        input: String,
+        initial_state: State,
+    ) -> Result<(Vec<Code>, State), Error> {
+        self.process_lines(input, initial_state, &mut Vec::new())
+            .map_err(|error| error.inner)
+    }
+
+    /// Like `process`, but also returns which rule handled each line, for debugging rule precedence.
+    /// Each line number is paired with the index of the rule (in the order the rules were added, see `rule_name`),
+    /// or None if no regular rule handled it (e.g. a regex rule or a block rule did).
+    pub fn process_traced(
+        &self,
+        input: String,
+        initial_state: State,
+    ) -> Result<(Vec<Code>, Vec<(usize, Option<usize>)>), Error> {
+        let mut trace = Vec::new();
+        let (output, _) = self
+            .process_lines(input, initial_state, &mut trace)
+            .map_err(|error| error.inner)?;
+        Ok((output, trace))
+    }
+
+    /// Like `process`, but errors come with their position: the fragment which failed to parse, or the whole line
+    /// if a rule (or a limit) rejected it. This is meant for diagnostics underlining the faulty part of the line.
+    pub fn process_spanned(
+        &self,
+        input: String,
+        initial_state: State,
+    ) -> Result<Vec<Code>, SpannedError<Error>> {
+        self.process_lines(input, initial_state, &mut Vec::new())
+            .map(|(output, _)| output)
+    }
+
+    fn process_lines(
+        &self,
+        input: String,
+        mut state: State,
+        trace: &mut Vec<(usize, Option<usize>)>,
+    ) -> Result<(Vec<Code>, State), SpannedError<Error>> {
         let mut output = Vec::new();
-        for i in input.lines() {
-            let mut token_list = vec![];
-            for j in i.split(|c| self.splitters.contains(&c)) {
-                token_list.append(&mut (self.parser)(j.to_string(), &mut initial_state)?);
+        let mut progress = Progress::new();
+        for (line_number, i) in self.lines(&input) {
+            let mut report = LineReport::default();
+            let result = self.check_line_count(line_number).and_then(|_| {
+                self.process_step(line_number, i, &mut progress, &mut state, &mut report, None)
+            });
+            match result {
+                Ok(mut code) => output.append(&mut code),
+                Err(inner) => {
+                    return Err(SpannedError {
+                        line: line_number,
+                        col_range: match report.failed_fragment {
+                            Some(span) => span.col..span.col + span.len,
+                            None => 0..i.len(),
+                        },
+                        inner,
+                    })
+                }
             }
-            output.append(&mut self.process_line(token_list, &mut initial_state)?)
+            trace.push((line_number, report.rule));
+        }
+        if let Some((line_number, inner)) = self.unclosed_block(progress) {
+            return Err(SpannedError {
+                line: line_number,
+                col_range: 0..input.lines().nth(line_number).map_or(0, str::len),
+                inner,
+            });
+        }
+        Ok((output, state))
+    }
+
+    /// Returns the indentation markers to put before the given line, updating the levels of the enclosing lines.
+    fn indentation_markers(
+        &self,
+        line_number: usize,
+        line: &str,
+        indentation: &mut Vec<usize>,
+        rejected: Option<fn(RejectedLine) -> Error>,
+    ) -> Result<Vec<Token>, Error> {
+        let (spaces_per_level, markers) = match &self.indentation {
+            Some(indentation) => indentation,
+            None => return Ok(Vec::new()),
+        };
+        if line.trim().is_empty() {
+            return Ok(Vec::new());
+        }
+        let spaces: usize = line
+            .chars()
+            .take_while(|c| *c == ' ' || *c == '\t')
+            .map(|c| if c == '\t' { *spaces_per_level } else { 1 })
+            .sum();
+        let level = spaces / spaces_per_level;
+        let current = *indentation.last().unwrap();
+        if !spaces.is_multiple_of(*spaces_per_level) {
+            return Err(self.indentation_error(line_number, line, rejected));
+        }
+        if level > current {
+            indentation.push(level);
+            return Ok(markers(1));
+        }
+        let mut dedents = 0;
+        while *indentation.last().unwrap() > level {
+            indentation.pop();
+            dedents += 1;
+        }
+        if *indentation.last().unwrap() != level {
+            return Err(self.indentation_error(line_number, line, rejected));
+        }
+        Ok(if dedents > 0 {
+            markers(-dedents)
+        } else {
+            Vec::new()
+        })
+    }
+
+    fn indentation_error(
+        &self,
+        line_number: usize,
+        line: &str,
+        rejected: Option<fn(RejectedLine) -> Error>,
+    ) -> Error {
+        match (&self.raw_error, rejected) {
+            (Some(raw_error), _) => raw_error(line, line_number),
+            (None, Some(rejected)) => rejected(RejectedLine {
+                line: line_number,
+                text: line.to_string(),
+            }),
+            (None, None) => panic!(
+                "Inconsistent indentation on line {}: {:?}",
+                line_number, line
+            ),
+        }
+    }
+
+    /// Processes a single line, either on its own or as part of the current block.
+    /// Rejected lines which no hook handles are turned into errors by `rejected`, or panic without it.
+    fn process_step(
+        &self,
+        line_number: usize,
+        line: &str,
+        progress: &mut Progress<Token>,
+        state: &mut State,
+        report: &mut LineReport,
+        rejected: Option<fn(RejectedLine) -> Error>,
+    ) -> Result<Vec<Code>, Error> {
+        self.check_line_length(line_number, line)?;
+        let mut token_list =
+            self.indentation_markers(line_number, line, &mut progress.indentation, rejected)?;
+        token_list.append(&mut self.tokenize_line(
+            line_number,
+            line,
+            state,
+            &mut report.failed_fragment,
+        )?);
+        if let Some((index, start_line, mut lines)) = progress.block.take() {
+            let (_, end, parse, _) = &self.block_rules[index];
+            let ended = end(&token_list, state);
+            lines.push(token_list);
+            if ended {
+                return parse(lines, state);
+            }
+            progress.block = Some((index, start_line, lines));
+            return Ok(Vec::new());
+        }
+        if let Some(index) = self
+            .block_rules
+            .iter()
+            .position(|rule| rule.0(&token_list, state))
+        {
+            progress.block = Some((index, line_number, vec![token_list]));
+            return Ok(Vec::new());
+        }
+        self.process_line(line_number, line, token_list, state, report, rejected)
+    }
+
+    /// The error for the block still open at the end of the input, if any, along with the line it started on.
+    fn unclosed_block(&self, progress: Progress<Token>) -> Option<(usize, Error)> {
+        let (index, start_line, _) = progress.block?;
+        Some((start_line, self.block_rules[index].3(start_line)))
+    }
+}
+
+impl<Token, Error, Code, Parser, State> Tokenizer<Token, Error, Code, Parser, State>
+where
+    Parser: FragmentParser<Token, Error, State>,
+    Token: Debug,
+    Error: From<RejectedLine>,
+{
+    /// Like `process`, but keeps going after a line fails, to find every error at once (e.g. for an editor).
+    /// The errors are returned along with the (0-indexed) line they happened on. If `recover` was called,
+    /// its placeholder code is emitted in place of every failed line. Lines which no rule accepts are errors created from a `RejectedLine`,
+    /// unless `with_raw_error` (or `with_string_error`) handles them.
+    pub fn process_recovering(
+        &self,
+        input: String,
+        mut initial_state: State,
+    ) -> (Vec<Code>, Vec<(usize, Error)>) {
+        let mut output = Vec::new();
+        let mut errors = Vec::new();
+        let mut progress = Progress::new();
+        for (line_number, i) in self.lines(&input) {
+            if let Err(error) = self.check_line_count(line_number) {
+                errors.push((line_number, error));
+                return (output, errors);
+            }
+            let snapshot = self.snapshot_state.map(|snapshot| snapshot(&initial_state));
+            match self.process_step(
+                line_number,
+                i,
+                &mut progress,
+                &mut initial_state,
+                &mut LineReport::default(),
+                Some(Error::from),
+            ) {
+                Ok(mut code) => output.append(&mut code),
+                Err(error) => {
+                    if let Some(snapshot) = snapshot {
+                        initial_state = snapshot;
+                    }
+                    errors.push((line_number, error));
+                    if let Some(recovery_code) = &self.recovery_code {
+                        output.push(recovery_code(line_number));
+                    }
+                }
+            }
+        }
+        errors.extend(self.unclosed_block(progress));
+        (output, errors)
+    }
+
+    /// Starts processing input one line at a time (e.g. for a REPL), keeping the state between lines. See `TokenizerSession`.
+    pub fn session(
+        self,
+        initial_state: State,
+    ) -> TokenizerSession<Token, Error, Code, Parser, State> {
+        TokenizerSession {
+            tokenizer: self,
+            state: initial_state,
+            progress: Progress::new(),
+            line_number: 0,
+        }
+    }
+}
+
+/// A tokenizer fed one line at a time, created by `Tokenizer::session`. The state, the open block and the indentation carry over from one line to the next.
+pub struct TokenizerSession<Token, Error, Code, Parser, State>
+where
+    Parser: FragmentParser<Token, Error, State>,
+{
+    tokenizer: Tokenizer<Token, Error, Code, Parser, State>,
+    state: State,
+    progress: Progress<Token>,
+    line_number: usize,
+}
+
+impl<Token, Error, Code, Parser, State> TokenizerSession<Token, Error, Code, Parser, State>
+where
+    Parser: FragmentParser<Token, Error, State>,
+    Token: Debug,
+    Error: From<RejectedLine>,
+{
+    /// Processes the next line, returning its code. Lines inside a block return nothing until the block ends.
+    /// Like in `process_recovering`, lines which no rule accepts are errors created from a `RejectedLine`, unless a hook handles them.
+    /// If the line fails, the changes it made to the state are kept, unless `rollback_state_on_error` was set.
+    pub fn feed_line(&mut self, line: &str) -> Result<Vec<Code>, Error> {
+        let line_number = self.line_number;
+        self.line_number += 1;
+        if self.tokenizer.skip_blank_lines && line.trim().is_empty() {
+            return Ok(Vec::new());
+        }
+        self.tokenizer.check_line_count(line_number)?;
+        let snapshot = self
+            .tokenizer
+            .snapshot_state
+            .map(|snapshot| snapshot(&self.state));
+        let result = self.tokenizer.process_step(
+            line_number,
+            line,
+            &mut self.progress,
+            &mut self.state,
+            &mut LineReport::default(),
+            Some(Error::from),
+        );
+        if let (Err(_), Some(snapshot)) = (&result, snapshot) {
+            self.state = snapshot;
+        }
+        result
+    }
+
+    /// The current state.
+    pub fn state(&self) -> &State {
+        &self.state
+    }
+
+    /// Ends the session, returning the final state, or the error for the block which is still open, if any.
+    pub fn finish(self) -> Result<State, Error> {
+        match self.tokenizer.unclosed_block(self.progress) {
+            Some((_, error)) => Err(error),
+            None => Ok(self.state),
         }
-        Ok(output)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::tokenizing::Tokenizer;
+    use crate::tokenizing::simple_rules::{first_matches_ci, parse_basic_token, BasicToken};
+    use std::collections::HashMap;
+
+    use crate::tokenizing::{RejectedLine, Span, SpannedError, Tokenizer, TokenizerLimit};
 
     #[test]
     fn basic_test() {
@@ -75,4 +941,548 @@ mod tests {
             Text(String),
         }
     }
+
+    #[test]
+    fn tokenize_test() {
+        let tokenizer: Tokenizer<String, (), (), _, ()> =
+            Tokenizer::new(|input: String, _: &mut ()| Ok(vec![input])).add_splitter(' ');
+        let tokens = tokenizer
+            .tokenize("mov a 1\nhalt".to_string(), &mut ())
+            .unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                vec!["mov".to_string(), "a".to_string(), "1".to_string()],
+                vec!["halt".to_string()],
+            ]
+        );
+    }
+
+    #[test]
+    fn block_rule_test() {
+        let tokenizer: Tokenizer<String, (), String, _, ()> =
+            Tokenizer::new(|input: String, _: &mut ()| Ok(vec![input]))
+                .add_splitter(' ')
+                .add_block_rule(
+                    |line, _| line[0] == "loop:",
+                    |line, _| line[0] == "endloop",
+                    |lines, _| Ok(vec![format!("block of {} lines", lines.len())]),
+                    |_| (),
+                )
+                .add_rule(|_, _| true, |line, _| Ok(line));
+        let code = tokenizer
+            .process_with_state("nop\nloop:\nendloop\nhalt".to_string(), ())
+            .unwrap()
+            .0;
+        assert_eq!(code, vec!["nop", "block of 2 lines", "halt"]);
+        assert_eq!(
+            tokenizer.process_spanned("nop\nloop:\nhalt".to_string(), ()),
+            Err(SpannedError {
+                line: 1,
+                col_range: 0..5,
+                inner: ()
+            })
+        );
+        assert_eq!(
+            tokenizer.process_recovering("loop:".to_string(), ()),
+            (vec![], vec![(0, ())])
+        );
+    }
+
+    #[test]
+    fn span_test() {
+        let tokenizer: Tokenizer<(String, Span), (), (), _, ()> =
+            Tokenizer::with_spanned_parser(|fragment, _: &mut ()| Ok(vec![fragment]))
+                .add_splitter(' ')
+                .add_splitter('é');
+        let tokens = tokenizer
+            .tokenize("nop\nmov  abc\u{e9}1".to_string(), &mut ())
+            .unwrap();
+        assert_eq!(
+            tokens[1][2],
+            (
+                "abc".to_string(),
+                Span {
+                    line: 1,
+                    col: 5,
+                    len: 3
+                }
+            )
+        );
+        assert_eq!(
+            tokens[1][3],
+            (
+                "1".to_string(),
+                Span {
+                    line: 1,
+                    col: 10,
+                    len: 1
+                }
+            )
+        );
+    }
+
+    #[test]
+    fn case_insensitive_test() {
+        let is_mov = first_matches_ci("Mov");
+        let tokenizer: Tokenizer<BasicToken, (), &str, _, ()> =
+            Tokenizer::new(|input: String, _: &mut ()| Ok(vec![BasicToken::Text(input)]))
+                .add_splitter(' ')
+                .case_insensitive()
+                .add_rule(move |line, _| is_mov(line), |_, _| Ok(vec!["mov"]))
+                .add_rule(|_, _| true, |_, _| Ok(vec!["other"]));
+        let code = tokenizer
+            .process("MOV a 1\nmov a 2\nadd a 1".to_string(), ())
+            .unwrap();
+        assert_eq!(code, vec!["mov", "mov", "other"]);
+        assert!(first_matches_ci("mov")(&vec![BasicToken::Text(
+            "MoV".to_string()
+        )]));
+    }
+
+    #[test]
+    fn final_state_test() {
+        let tokenizer: Tokenizer<String, (), (), _, usize> =
+            Tokenizer::new(|input: String, _: &mut usize| Ok(vec![input])).add_rule(
+                |_, _| true,
+                |_, lines: &mut usize| {
+                    *lines += 1;
+                    Ok(vec![])
+                },
+            );
+        let (_, lines) = tokenizer
+            .process_with_state("a\nb\nc".to_string(), 0)
+            .unwrap();
+        assert_eq!(lines, 3);
+    }
+
+    #[test]
+    fn blank_line_test() {
+        let tokenizer: Tokenizer<String, (), String, _, ()> =
+            Tokenizer::new(|input: String, _: &mut ()| Ok(vec![input]))
+                .skip_blank_lines()
+                .add_rule(|line, _| !line[0].is_empty(), |line, _| Ok(line));
+        let code = tokenizer.process("a\n\n   \nb".to_string(), ()).unwrap();
+        assert_eq!(code, vec!["a", "b"]);
+    }
+
+    #[test]
+    fn whitespace_test() {
+        let tokenizer: Tokenizer<String, (), (), _, ()> =
+            Tokenizer::new(|input: String, _: &mut ()| Ok(vec![input]))
+                .add_splitter(',')
+                .split_on_whitespace();
+        let tokens = tokenizer
+            .tokenize("  a   b\t c,,d , e ".to_string(), &mut ())
+            .unwrap();
+        assert_eq!(tokens, vec![vec!["a", "b", "c", "", "d", "e"]]);
+    }
+
+    #[test]
+    fn alias_test() {
+        let tokenizer: Tokenizer<BasicToken, (), (), _, ()> =
+            Tokenizer::new(|input: String, _: &mut ()| Ok(vec![BasicToken::Text(input)]))
+                .add_splitter(' ')
+                .add_alias(
+                    BasicToken::Text("acc".to_string()),
+                    vec![BasicToken::Register(0)],
+                )
+                .add_alias(
+                    BasicToken::Text("loop".to_string()),
+                    vec![BasicToken::Text("loop".to_string()); 2],
+                );
+        let tokens = tokenizer
+            .tokenize("mov acc loop".to_string(), &mut ())
+            .unwrap();
+        assert_eq!(
+            tokens,
+            vec![vec![
+                BasicToken::Text("mov".to_string()),
+                BasicToken::Register(0),
+                BasicToken::Text("loop".to_string()),
+                BasicToken::Text("loop".to_string()),
+            ]]
+        );
+    }
+
+    #[test]
+    fn raw_error_test() {
+        let tokenizer: Tokenizer<String, String, String, _, ()> =
+            Tokenizer::new(|input: String, _: &mut ()| Ok(vec![input]))
+                .add_splitter(' ')
+                .add_rule(
+                    |line, _| line[0] == "halt",
+                    |_, _| Ok(vec!["halt".to_string()]),
+                )
+                .with_raw_error(|line, line_number| {
+                    format!("error on line {}: {:?}", line_number + 1, line)
+                });
+        let result = tokenizer.process("halt\nhalt\nfrobnicate  r1".to_string(), ());
+        assert_eq!(
+            result,
+            Err("error on line 3: \"frobnicate  r1\"".to_string())
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "regex")]
+    fn regex_rule_test() {
+        let tokenizer: Tokenizer<String, (), u8, _, ()> =
+            Tokenizer::new(|input: String, _: &mut ()| Ok(vec![input]))
+                .add_splitter(' ')
+                .add_regex_rule(r"^mov\s+r(\d+)", |groups, _| {
+                    Ok(vec![groups[0].parse().unwrap()])
+                })
+                .unwrap();
+        let output = tokenizer.process("mov   r12, 5".to_string(), ()).unwrap();
+        assert_eq!(output, vec![12]);
+        assert!(
+            Tokenizer::<String, (), u8, _, ()>::new(|input: String, _: &mut ()| Ok(vec![input]))
+                .add_regex_rule("(", |_, _| Ok(vec![]))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn recover_test() {
+        let tokenizer: Tokenizer<String, String, String, _, ()> =
+            Tokenizer::new(|input: String, _: &mut ()| Ok(vec![input]))
+                .add_rule(|line, _| line[0] != "bad", |line, _| Ok(line))
+                .with_raw_error(|line, _| line.to_string())
+                .recover(|line_number| format!("<error on line {}>", line_number));
+        let (code, errors) = tokenizer.process_recovering("a\nbad\nb\nbad".to_string(), ());
+        assert_eq!(
+            code,
+            vec!["a", "<error on line 1>", "b", "<error on line 3>"]
+        );
+        assert_eq!(errors, vec![(1, "bad".to_string()), (3, "bad".to_string())]);
+    }
+
+    #[test]
+    fn indentation_test() {
+        let tokenizer: Tokenizer<String, String, Vec<String>, _, ()> =
+            Tokenizer::new(|input: String, _: &mut ()| Ok(vec![input]))
+                .split_on_whitespace()
+                .with_indentation(4, |delta| {
+                    let marker = if delta > 0 { "INDENT" } else { "DEDENT" };
+                    vec![marker.to_string(); delta.unsigned_abs()]
+                })
+                .with_raw_error(|line, line_number| format!("{}: {}", line_number, line))
+                .add_rule(|_, _| true, |line, _| Ok(vec![line]));
+        let input = "loop:\n    if a\n        inc b\n\n    dec a\nhalt";
+        let (code, _) = tokenizer.process_with_state(input.to_string(), ()).unwrap();
+        let lines: Vec<Vec<&str>> = code
+            .iter()
+            .map(|line| line.iter().map(|token| token.as_str()).collect())
+            .collect();
+        assert_eq!(
+            lines,
+            vec![
+                vec!["loop:"],
+                vec!["INDENT", "if", "a"],
+                vec!["INDENT", "inc", "b"],
+                vec![""],
+                vec!["DEDENT", "dec", "a"],
+                vec!["DEDENT", "halt"],
+            ]
+        );
+        let (code, _) = tokenizer
+            .process_with_state("a\n    b\n        c\nd".to_string(), ())
+            .unwrap();
+        assert_eq!(code[3], vec!["DEDENT", "DEDENT", "d"]);
+        let error = tokenizer.process_with_state("a\n    b\n        c\n  d".to_string(), ());
+        assert_eq!(error.unwrap_err(), "3:   d".to_string());
+        let error = tokenizer.process_with_state("a\n        b\n    c".to_string(), ());
+        assert_eq!(error.unwrap_err(), "2:     c".to_string());
+    }
+
+    #[test]
+    fn raw_line_mode_test() {
+        let tokenizer: Tokenizer<String, String, String, _, ()> =
+            Tokenizer::new(|line: String, _: &mut ()| {
+                Ok(line
+                    .split(',')
+                    .map(|token| token.trim().to_string())
+                    .collect())
+            })
+            .split_on_whitespace()
+            .raw_line_mode()
+            .add_rule(|_, _| true, |tokens, _| Ok(vec![tokens.join("|")]));
+        assert_eq!(
+            tokenizer.tokenize("mov a, b c\nhalt".to_string(), &mut ()),
+            Ok(vec![
+                vec!["mov a".to_string(), "b c".to_string()],
+                vec!["halt".to_string()]
+            ])
+        );
+        assert_eq!(
+            tokenizer.process("mov a, b c\nhalt".to_string(), ()),
+            Ok(vec!["mov a|b c".to_string(), "halt".to_string()])
+        );
+    }
+
+    #[test]
+    fn ignore_empty_fragments_test() {
+        let tokenizer: Tokenizer<String, String, String, _, ()> =
+            Tokenizer::new(|fragment: String, _: &mut ()| {
+                assert!(!fragment.is_empty());
+                Ok(vec![fragment])
+            })
+            .add_splitter(',')
+            .ignore_empty_fragments();
+        assert_eq!(
+            tokenizer.tokenize("a,,b\n,".to_string(), &mut ()),
+            Ok(vec![vec!["a".to_string(), "b".to_string()], vec![]])
+        );
+    }
+
+    #[test]
+    fn limits_test() {
+        let tokenizer: Tokenizer<String, TokenizerLimit, String, _, ()> =
+            Tokenizer::new(|fragment: String, _: &mut ()| Ok(vec![fragment]))
+                .split_on_whitespace()
+                .with_max_line_length(8, |limit| limit)
+                .with_max_lines(3, |limit| limit)
+                .add_rule(|_, _| true, |tokens, _| Ok(vec![tokens.join(" ")]));
+        assert_eq!(
+            tokenizer.process_with_state("mov a b\nhalt\n".to_string(), ()),
+            Ok((vec!["mov a b".to_string(), "halt".to_string()], ()))
+        );
+        assert_eq!(
+            tokenizer
+                .process_with_state("mov a b\nmov a, b, c".to_string(), ())
+                .unwrap_err(),
+            TokenizerLimit::LineLength {
+                line: 1,
+                length: 11,
+                max: 8
+            }
+        );
+        assert_eq!(
+            tokenizer
+                .process_with_state("a\nb\n\nc".to_string(), ())
+                .unwrap_err(),
+            TokenizerLimit::Lines { max: 3 }
+        );
+        assert_eq!(
+            tokenizer.tokenize("a\nb\nc\nd".to_string(), &mut ()),
+            Err(TokenizerLimit::Lines { max: 3 })
+        );
+        let tokenizer: Tokenizer<String, String, String, _, ()> =
+            Tokenizer::new(|fragment: String, _: &mut ()| Ok(vec![fragment]))
+                .with_max_lines(3, |limit| format!("{:?}", limit))
+                .add_rule(|_, _| true, |tokens, _| Ok(tokens));
+        let (code, errors) = tokenizer.process_recovering("a\nb\nc\nd\ne".to_string(), ());
+        assert_eq!(code.len(), 3);
+        assert_eq!(errors, vec![(3, "Lines { max: 3 }".to_string())]);
+    }
+
+    #[test]
+    fn string_error_test() {
+        let is_halt = first_matches_ci("halt");
+        let tokenizer =
+            Tokenizer::with_string_error(|input: String, _: &mut ()| parse_basic_token(input))
+                .split_on_whitespace()
+                .add_rule(move |line, _| is_halt(line), |_, _| Ok(vec!["halt"]));
+        assert_eq!(
+            tokenizer.process_with_state("halt".to_string(), ()),
+            Ok((vec!["halt"], ()))
+        );
+        assert_eq!(
+            tokenizer.process("jmp r1".to_string(), ()),
+            Err("no rule matched: [Text(\"jmp\"), Register(1)]".to_string())
+        );
+    }
+
+    #[test]
+    fn escape_test() {
+        let tokenizer: Tokenizer<String, (), (), _, ()> =
+            Tokenizer::new(|input: String, _: &mut ()| Ok(vec![input]))
+                .add_splitter(',')
+                .with_escape_char('\\');
+        let tokens = tokenizer
+            .tokenize("foo\\,bar\na\\\\,b\\c,\\".to_string(), &mut ())
+            .unwrap();
+        assert_eq!(tokens, vec![vec!["foo,bar"], vec!["a\\", "b\\c", "\\"]]);
+    }
+
+    #[test]
+    fn rollback_test() {
+        // Counts the fragments parsed so far, and emits the count at the end of each line. The failed line parses 2 fragments.
+        let tokenizer = || {
+            Tokenizer::new(|input: String, count: &mut usize| {
+                *count += 1;
+                match input.as_str() {
+                    "bad" => Err(input),
+                    _ => Ok(vec![input]),
+                }
+            })
+            .add_splitter(' ')
+            .add_rule(|_, _| true, |_, count: &mut usize| Ok(vec![*count]))
+        };
+        let input = "a b\nc bad d\ne";
+        assert_eq!(
+            tokenizer().process_recovering(input.to_string(), 0),
+            (vec![2, 5], vec![(1, "bad".to_string())])
+        );
+        assert_eq!(
+            tokenizer()
+                .rollback_state_on_error()
+                .process_recovering(input.to_string(), 0),
+            (vec![2, 3], vec![(1, "bad".to_string())])
+        );
+    }
+
+    #[test]
+    fn trace_test() {
+        let tokenizer: Tokenizer<String, (), String, _, ()> =
+            Tokenizer::new(|input: String, _: &mut ()| Ok(vec![input]))
+                .add_splitter(' ')
+                .add_rule(
+                    |line, _| line[0] == "halt",
+                    |_, _| Ok(vec!["halt".to_string()]),
+                )
+                .add_named_rule("anything", |_, _| true, |line, _| Ok(line))
+                .add_block_rule(
+                    |line, _| line[0] == "loop:",
+                    |line, _| line[0] == "endloop",
+                    |_, _| Ok(vec![]),
+                    |_| (),
+                );
+        let (code, trace) = tokenizer
+            .process_traced("mov a\nhalt".to_string(), ())
+            .unwrap();
+        assert_eq!(code, vec!["mov", "a", "halt"]);
+        assert_eq!(trace, vec![(0, Some(1)), (1, Some(0))]);
+        assert_eq!(tokenizer.rule_name(0), None);
+        assert_eq!(tokenizer.rule_name(1), Some("anything"));
+        let (_, trace) = tokenizer
+            .process_traced("loop:\nendloop".to_string(), ())
+            .unwrap();
+        assert_eq!(trace, vec![(0, None), (1, None)]);
+    }
+
+    #[test]
+    fn session_test() {
+        // Labels are defined with `name: value` and read back with `print name`.
+        let tokenizer = Tokenizer::new(|input: String, _: &mut HashMap<String, i32>| {
+            Ok::<_, String>(vec![input])
+        })
+        .split_on_whitespace()
+        .add_rule(
+            |line, _| line.len() == 2 && line[0].ends_with(':'),
+            |line, labels| {
+                let value = line[1].parse().map_err(|_| line[1].clone())?;
+                labels.insert(line[0].trim_end_matches(':').to_string(), value);
+                Ok(vec![])
+            },
+        )
+        .add_rule(
+            |line, _| line[0] == "print",
+            |line, labels| {
+                labels
+                    .get(&line[1])
+                    .map(|value| vec![*value])
+                    .ok_or(line[1].clone())
+            },
+        );
+        let mut session = tokenizer.session(HashMap::new());
+        assert_eq!(session.feed_line("answer: 42"), Ok(vec![]));
+        assert_eq!(
+            session.feed_line("print question"),
+            Err("question".to_string())
+        );
+        assert_eq!(session.feed_line("print answer"), Ok(vec![42]));
+        assert_eq!(session.state().len(), 1);
+        assert_eq!(session.finish().unwrap()["answer"], 42);
+    }
+
+    #[test]
+    fn spanned_error_test() {
+        let tokenizer = Tokenizer::new(|input: String, _: &mut ()| {
+            if input.chars().all(|c| c.is_ascii_alphanumeric()) {
+                Ok(vec![input])
+            } else {
+                Err(format!("unexpected {:?}", input))
+            }
+        })
+        .split_on_whitespace()
+        .add_rule(
+            |line, _| line[0] == "mov",
+            |line, _| match line.len() {
+                3 => Ok(vec![line.join(" ")]),
+                _ => Err("mov takes 2 operands".to_string()),
+            },
+        );
+        assert_eq!(
+            tokenizer.process_spanned("mov a b\n  mov a $b".to_string(), ()),
+            Err(SpannedError {
+                line: 1,
+                col_range: 8..10,
+                inner: "unexpected \"$b\"".to_string(),
+            })
+        );
+        assert_eq!(
+            tokenizer.process_spanned("mov a".to_string(), ()),
+            Err(SpannedError {
+                line: 0,
+                col_range: 0..5,
+                inner: "mov takes 2 operands".to_string(),
+            })
+        );
+        assert_eq!(
+            tokenizer.process_spanned("mov a b".to_string(), ()),
+            Ok(vec!["mov a b".to_string()])
+        );
+    }
+
+    #[test]
+    fn rejected_line_test() {
+        let tokenizer = || {
+            Tokenizer::<String, RejectedLine, String, _, ()>::new(|input: String, _: &mut ()| {
+                Ok(vec![input])
+            })
+            .split_on_whitespace()
+            .with_indentation(4, |_| vec![])
+            .add_rule(|line, _| line[0] == "nop", |line, _| Ok(line))
+        };
+        assert_eq!(
+            tokenizer().process_recovering("nop\njmp a\n    nop\n  nop".to_string(), ()),
+            (
+                vec!["nop".to_string(), "nop".to_string()],
+                vec![
+                    (
+                        1,
+                        RejectedLine {
+                            line: 1,
+                            text: "jmp a".to_string()
+                        }
+                    ),
+                    (
+                        3,
+                        RejectedLine {
+                            line: 3,
+                            text: "  nop".to_string()
+                        }
+                    ),
+                ]
+            )
+        );
+        let mut session = tokenizer().session(());
+        assert_eq!(session.feed_line("nop"), Ok(vec!["nop".to_string()]));
+        assert_eq!(
+            session.feed_line("jmp a"),
+            Err(RejectedLine {
+                line: 1,
+                text: "jmp a".to_string()
+            })
+        );
+        assert_eq!(
+            String::from(RejectedLine {
+                line: 1,
+                text: "jmp a".to_string()
+            }),
+            "no rule accepted line 1: \"jmp a\""
+        );
+    }
 }
@@ -1,3 +1,5 @@
+use std::num::ParseIntError;
+
 pub fn first_matches_condition<T>(required: T) -> impl Fn(&Vec<T>) -> bool
 where
     T: PartialEq,
@@ -12,7 +14,7 @@ where
     return move |input: &Vec<T>| input.get(index) == Some(&required);
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum BasicToken {
     Number(i32),
     Text(String),
@@ -20,8 +22,78 @@ pub enum BasicToken {
     Symbol(char),
 }
 
-pub fn parse_basic_token(_input: String) -> Result<Vec<BasicToken>, String> {
-    let output = Vec::new();
+/// Parses an integer in decimal, hexadecimal (`0x`) or binary (`0b`), with an optional leading `-`.
+/// Underscores can be used to separate digits, e.g. `1_000` or `0b1010_0101`.
+pub fn parse_int(s: &str) -> Result<i32, ParseIntError> {
+    let (sign, s) = match s.strip_prefix('-') {
+        Some(s) => ("-", s),
+        None => ("+", s),
+    };
+    let (radix, digits) = match s.get(..2) {
+        Some("0x" | "0X") => (16, &s[2..]),
+        Some("0b" | "0B") => (2, &s[2..]),
+        _ => (10, s),
+    };
+    // The sign is always given explicitly, so that a second sign in the digits is rejected.
+    i32::from_str_radix(&format!("{}{}", sign, digits.replace('_', "")), radix)
+}
+
+/// Parses a fragment into a single token: a number (see `parse_int`), a register (`r` followed by its index),
+/// a symbol (a single character which isn't alphanumeric), or text otherwise. Empty fragments give no tokens.
+pub fn parse_basic_token(input: String) -> Result<Vec<BasicToken>, String> {
+    let mut chars = input.chars();
+    let token = match (chars.next(), chars.next()) {
+        (None, _) => return Ok(Vec::new()),
+        (Some(c), None) if !c.is_alphanumeric() => BasicToken::Symbol(c),
+        (Some(c), _) if c.is_ascii_digit() || c == '-' => match parse_int(&input) {
+            Ok(number) => BasicToken::Number(number),
+            Err(_) if c == '-' => BasicToken::Text(input),
+            Err(e) => return Err(format!("Invalid number {:?}: {}", input, e)),
+        },
+        (Some('r'), Some(_)) => match input[1..].parse() {
+            Ok(register) => BasicToken::Register(register),
+            Err(_) => BasicToken::Text(input),
+        },
+        _ => BasicToken::Text(input),
+    };
+    Ok(vec![token])
+}
+
+pub fn first_matches_ci(required: &str) -> impl Fn(&Vec<BasicToken>) -> bool {
+    let required = required.to_lowercase();
+    move |input: &Vec<BasicToken>| match input.first() {
+        Some(BasicToken::Text(text)) => text.to_lowercase() == required,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tokenizing::simple_rules::{parse_basic_token, parse_int, BasicToken};
+
+    #[test]
+    fn parse_int_test() {
+        assert_eq!(parse_int("0xFF"), Ok(255));
+        assert_eq!(parse_int("0b1010"), Ok(10));
+        assert_eq!(parse_int("-7"), Ok(-7));
+        assert_eq!(parse_int("42"), Ok(42));
+        assert_eq!(parse_int("1_000"), Ok(1000));
+        assert_eq!(parse_int("-0X10"), Ok(-16));
+        assert!(parse_int("0xGG").is_err());
+        assert!(parse_int("0x-5").is_err());
+        assert!(parse_int("").is_err());
+    }
 
-    Ok(output)
+    #[test]
+    fn parse_basic_token_test() {
+        let parse = |input: &str| parse_basic_token(input.to_string());
+        assert_eq!(parse("0x10"), Ok(vec![BasicToken::Number(16)]));
+        assert_eq!(parse("r3"), Ok(vec![BasicToken::Register(3)]));
+        assert_eq!(parse(","), Ok(vec![BasicToken::Symbol(',')]));
+        assert_eq!(parse("-"), Ok(vec![BasicToken::Symbol('-')]));
+        assert_eq!(parse("mov"), Ok(vec![BasicToken::Text("mov".to_string())]));
+        assert_eq!(parse("ret"), Ok(vec![BasicToken::Text("ret".to_string())]));
+        assert_eq!(parse(""), Ok(vec![]));
+        assert!(parse("12ab").is_err());
+    }
 }